@@ -0,0 +1,3 @@
+ALTER TABLE newsletter_issues ADD COLUMN delivery_status TEXT NOT NULL DEFAULT 'in_progress';
+ALTER TABLE newsletter_issues ADD COLUMN delivered_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE newsletter_issues ADD COLUMN undelivered_count INTEGER NOT NULL DEFAULT 0;
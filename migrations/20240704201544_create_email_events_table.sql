@@ -0,0 +1,19 @@
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE tracking_tokens(
+    tracking_token TEXT NOT NULL,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY(tracking_token)
+);
+
+CREATE TABLE email_events(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    first_occurred_at timestamptz NOT NULL,
+    last_occurred_at timestamptz NOT NULL,
+    occurrences INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email, event_type)
+);
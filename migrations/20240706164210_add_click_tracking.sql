@@ -0,0 +1,5 @@
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE email_events ADD COLUMN url TEXT NOT NULL DEFAULT '';
+ALTER TABLE email_events DROP CONSTRAINT email_events_pkey;
+ALTER TABLE email_events ADD PRIMARY KEY (newsletter_issue_id, subscriber_email, event_type, url);
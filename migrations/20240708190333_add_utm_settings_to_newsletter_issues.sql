@@ -0,0 +1,4 @@
+ALTER TABLE newsletter_issues ADD COLUMN utm_tagging BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN utm_source TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN utm_medium TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN utm_campaign TEXT NULL;
@@ -0,0 +1,17 @@
+CREATE TABLE issue_subject_variants(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(newsletter_issue_id),
+    variant SMALLINT NOT NULL,
+    subject TEXT NOT NULL,
+    sent_count INTEGER NOT NULL DEFAULT 0,
+    engaged_count INTEGER NULL,
+    PRIMARY KEY(newsletter_issue_id, variant)
+);
+
+ALTER TABLE newsletter_issues ADD COLUMN ab_metric TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN ab_decide_at timestamptz NULL;
+ALTER TABLE newsletter_issues ADD COLUMN ab_winner SMALLINT NULL;
+
+ALTER TABLE issue_delivery_queue ADD COLUMN subject_variant SMALLINT NULL;
+ALTER TABLE issue_delivery_queue ADD COLUMN held BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE tracking_tokens ADD COLUMN subject_variant SMALLINT NULL;
@@ -0,0 +1,31 @@
+CREATE TABLE lists(
+    list_id uuid NOT NULL,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(list_id)
+);
+
+-- Everyone who subscribed before lists existed belongs to the default list.
+INSERT INTO lists (list_id, slug, name)
+VALUES ('00000000-0000-0000-0000-000000000001', 'default', 'Newsletter');
+
+CREATE TABLE list_subscriptions(
+    list_id uuid NOT NULL REFERENCES lists(list_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(list_id, subscriber_id)
+);
+
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT '00000000-0000-0000-0000-000000000001', id, status, subscribed_at
+FROM subscriptions;
+
+ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL REFERENCES lists(list_id);
+UPDATE subscription_tokens SET list_id = '00000000-0000-0000-0000-000000000001';
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+ALTER TABLE newsletter_issues ADD COLUMN list_id uuid NULL REFERENCES lists(list_id);
+UPDATE newsletter_issues SET list_id = '00000000-0000-0000-0000-000000000001';
+ALTER TABLE newsletter_issues ALTER COLUMN list_id SET NOT NULL;
@@ -0,0 +1,19 @@
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    tag TEXT NOT NULL,
+    tagged_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags(tag);
+
+CREATE TABLE segments(
+    segment_id uuid NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    tag_expression TEXT NOT NULL DEFAULT '',
+    subscribed_before DATE NULL,
+    opened_within_days INTEGER NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(segment_id)
+);
+
+ALTER TABLE newsletter_issues ADD COLUMN segment_id uuid NULL REFERENCES segments(segment_id);
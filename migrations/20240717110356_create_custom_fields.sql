@@ -0,0 +1,20 @@
+CREATE TABLE custom_fields(
+    field_id uuid NOT NULL,
+    key TEXT NOT NULL UNIQUE,
+    label TEXT NOT NULL,
+    field_type TEXT NOT NULL,
+    options TEXT[] NOT NULL DEFAULT '{}',
+    required BOOLEAN NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(field_id)
+);
+
+CREATE TABLE subscriber_field_values(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    field_id uuid NOT NULL REFERENCES custom_fields(field_id),
+    value TEXT NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(subscriber_id, field_id)
+);
+
+ALTER TABLE segments ADD COLUMN field_conditions TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,29 @@
+CREATE TABLE preference_tokens(
+    preference_token TEXT NOT NULL,
+    subscriber_id uuid NOT NULL UNIQUE REFERENCES subscriptions(id),
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(preference_token)
+);
+
+ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue';
+ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN next_digest_at timestamptz NULL;
+
+-- Issues waiting to go out in the next weekly digest of a subscriber.
+CREATE TABLE digest_items(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(newsletter_issue_id),
+    added_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(subscriber_id, newsletter_issue_id)
+);
+
+CREATE TABLE preference_changes(
+    change_id uuid NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    field TEXT NOT NULL,
+    old_value TEXT NULL,
+    new_value TEXT NULL,
+    changed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(change_id)
+);
+CREATE INDEX preference_changes_subscriber_id_idx ON preference_changes(subscriber_id);
@@ -0,0 +1,4 @@
+-- Existing tokens count as created now, so they get a full lifetime.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE subscription_tokens ADD COLUMN used_at timestamptz NULL;
+CREATE INDEX subscription_tokens_subscriber_id_idx ON subscription_tokens(subscriber_id);
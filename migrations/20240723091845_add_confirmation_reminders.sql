@@ -0,0 +1,2 @@
+ALTER TABLE list_subscriptions ADD COLUMN reminders_sent SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE list_subscriptions ADD COLUMN last_reminder_at timestamptz NULL;
@@ -0,0 +1,25 @@
+CREATE TABLE welcome_steps(
+    step_id uuid NOT NULL,
+    position INT NOT NULL UNIQUE,
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    delay_hours INT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(step_id)
+);
+
+-- Steps waiting to go out, scheduled when a subscriber confirms.
+CREATE TABLE welcome_queue(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    step_id uuid NOT NULL REFERENCES welcome_steps(step_id),
+    send_at timestamptz NOT NULL,
+    PRIMARY KEY(subscriber_id, step_id)
+);
+
+CREATE TABLE welcome_deliveries(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    step_id uuid NOT NULL REFERENCES welcome_steps(step_id),
+    sent_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(subscriber_id, step_id)
+);
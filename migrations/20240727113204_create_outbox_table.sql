@@ -0,0 +1,17 @@
+-- Emails written in the same transaction as the change that triggers them,
+-- delivered by a background worker.
+CREATE TABLE outbox(
+    message_id uuid NOT NULL,
+    kind TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    attempts SMALLINT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    sent_at timestamptz NULL,
+    PRIMARY KEY(message_id)
+);
+CREATE INDEX outbox_pending_idx ON outbox(next_attempt_at) WHERE sent_at IS NULL;
@@ -0,0 +1,6 @@
+-- NULL means the list follows the global setting.
+ALTER TABLE lists ADD COLUMN opt_in TEXT NULL CHECK (opt_in IN ('single', 'double'));
+
+-- How each membership was consented to, and when.
+ALTER TABLE list_subscriptions ADD COLUMN opt_in TEXT NOT NULL DEFAULT 'double';
+ALTER TABLE list_subscriptions ADD COLUMN consented_at timestamptz NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE consent_events(
+    event_id uuid NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    list_id uuid NULL REFERENCES lists(list_id),
+    -- 'signup' or 'confirmation'
+    event_type TEXT NOT NULL,
+    ip_address TEXT NULL,
+    user_agent TEXT NULL,
+    form_id TEXT NULL,
+    consent_text_version TEXT NULL,
+    occurred_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(event_id)
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events(subscriber_id);
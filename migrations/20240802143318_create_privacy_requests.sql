@@ -0,0 +1,15 @@
+-- Short-lived links to the data export and erasure page, sent by email.
+CREATE TABLE privacy_tokens(
+    privacy_token TEXT NOT NULL,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(privacy_token)
+);
+
+-- Erased addresses, kept only as a hash so they are never imported again.
+CREATE TABLE suppressions(
+    email_hash TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(email_hash)
+);
@@ -0,0 +1,25 @@
+ALTER TABLE consent_events ADD COLUMN note TEXT NULL;
+
+CREATE TABLE subscriber_imports(
+    import_id uuid NOT NULL,
+    -- 'upload' or 'cli'
+    source TEXT NOT NULL,
+    list_id uuid NOT NULL REFERENCES lists(list_id),
+    mark_confirmed BOOLEAN NOT NULL,
+    consent_note TEXT NULL,
+    imported INTEGER NOT NULL DEFAULT 0,
+    skipped_duplicates INTEGER NOT NULL DEFAULT 0,
+    skipped_suppressed INTEGER NOT NULL DEFAULT 0,
+    rejected_invalid INTEGER NOT NULL DEFAULT 0,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(import_id)
+);
+
+-- Rows of an import that were not imported, and why.
+CREATE TABLE import_rejections(
+    import_id uuid NOT NULL REFERENCES subscriber_imports(import_id),
+    line BIGINT NOT NULL,
+    email TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    PRIMARY KEY(import_id, line)
+);
@@ -0,0 +1,70 @@
+-- The form used to spot duplicate subscribers, computed by the application
+-- (see `SubscriberEmail::key`). Existing rows get the lowercased address.
+ALTER TABLE subscriptions ADD COLUMN email_key TEXT NULL;
+
+UPDATE subscriptions SET email_key = lower(btrim(email, E' \t\r\n'));
+
+-- Addresses that only differ by case or surrounding whitespace are merged
+-- into a single subscriber: the confirmed one if any, else the oldest.
+CREATE TEMPORARY TABLE duplicate_subscribers ON COMMIT DROP AS
+SELECT id, keeper_id FROM (
+    SELECT
+        id,
+        first_value(id) OVER (
+            PARTITION BY email_key
+            ORDER BY (status = 'confirmed') DESC, subscribed_at, id
+        ) AS keeper_id
+    FROM subscriptions
+) ranked
+WHERE id <> keeper_id;
+
+INSERT INTO list_subscriptions (
+    list_id, subscriber_id, status, subscribed_at, reminders_sent, last_reminder_at, opt_in,
+    consented_at
+)
+SELECT
+    ls.list_id, d.keeper_id, ls.status, ls.subscribed_at, ls.reminders_sent, ls.last_reminder_at,
+    ls.opt_in, ls.consented_at
+FROM list_subscriptions ls
+JOIN duplicate_subscribers d ON d.id = ls.subscriber_id
+ON CONFLICT DO NOTHING;
+
+INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+SELECT d.keeper_id, t.tag, t.tagged_at
+FROM subscriber_tags t
+JOIN duplicate_subscribers d ON d.id = t.subscriber_id
+ON CONFLICT DO NOTHING;
+
+INSERT INTO subscriber_field_values (subscriber_id, field_id, value, updated_at)
+SELECT d.keeper_id, v.field_id, v.value, v.updated_at
+FROM subscriber_field_values v
+JOIN duplicate_subscribers d ON d.id = v.subscriber_id
+ON CONFLICT DO NOTHING;
+
+UPDATE subscription_tokens t SET subscriber_id = d.keeper_id
+FROM duplicate_subscribers d WHERE t.subscriber_id = d.id;
+
+UPDATE consent_events e SET subscriber_id = d.keeper_id
+FROM duplicate_subscribers d WHERE e.subscriber_id = d.id;
+
+UPDATE preference_changes c SET subscriber_id = d.keeper_id
+FROM duplicate_subscribers d WHERE c.subscriber_id = d.id;
+
+DELETE FROM list_subscriptions WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM subscriber_tags WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM subscriber_field_values WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM preference_tokens WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM privacy_tokens WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM digest_items WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM welcome_queue WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM welcome_deliveries WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+DELETE FROM subscriptions WHERE id IN (SELECT id FROM duplicate_subscribers);
+
+-- Stored addresses are trimmed, with the domain lowercased.
+UPDATE subscriptions
+SET email = substring(btrim(email, E' \t\r\n') FROM '^(.*)@[^@]*$')
+    || '@' || lower(substring(btrim(email, E' \t\r\n') FROM '@([^@]*)$'))
+WHERE email LIKE '%@%';
+
+ALTER TABLE subscriptions ALTER COLUMN email_key SET NOT NULL;
+CREATE UNIQUE INDEX subscriptions_email_key_idx ON subscriptions (email_key);
@@ -0,0 +1,9 @@
+-- Every confirmation email queued from a public endpoint, for rate limiting
+-- per address and per client IP.
+CREATE TABLE confirmation_sends(
+    email_key TEXT NOT NULL,
+    ip_address TEXT NULL,
+    sent_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX confirmation_sends_email_key_idx ON confirmation_sends (email_key, sent_at);
+CREATE INDEX confirmation_sends_ip_address_idx ON confirmation_sends (ip_address, sent_at);
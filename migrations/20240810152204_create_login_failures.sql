@@ -0,0 +1,10 @@
+-- Failed logins, for slowing down and locking out password guessing per
+-- username and per client IP.
+CREATE TABLE login_failures(
+    username TEXT NOT NULL,
+    ip_address TEXT NULL,
+    failed_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX login_failures_username_idx ON login_failures (username, failed_at);
+CREATE INDEX login_failures_ip_address_idx ON login_failures (ip_address, failed_at);
+CREATE INDEX login_failures_failed_at_idx ON login_failures (failed_at);
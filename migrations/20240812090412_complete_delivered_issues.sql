@@ -0,0 +1,7 @@
+-- Issues delivered before their status was tracked never got to see their
+-- last task go, so they are still shown as in progress.
+UPDATE newsletter_issues i
+SET delivery_status = 'completed'
+WHERE i.delivery_status = 'in_progress' AND NOT EXISTS (
+    SELECT 1 FROM issue_delivery_queue q WHERE q.newsletter_issue_id = i.newsletter_issue_id
+);
@@ -0,0 +1,3 @@
+-- Rows that could not be written to the database. They are listed in
+-- import_rejections along with the other rows that were not imported.
+ALTER TABLE subscriber_imports ADD COLUMN failed INTEGER NOT NULL DEFAULT 0;
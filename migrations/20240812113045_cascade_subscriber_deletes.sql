@@ -0,0 +1,47 @@
+-- Everything tied to a subscriber goes when the subscriber does, so deleting
+-- from subscriptions is all erasure, cleanup and merging have to do.
+
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscription_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE list_subscriptions
+    DROP CONSTRAINT list_subscriptions_subscriber_id_fkey,
+    ADD CONSTRAINT list_subscriptions_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE subscriber_tags
+    DROP CONSTRAINT subscriber_tags_subscriber_id_fkey,
+    ADD CONSTRAINT subscriber_tags_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE subscriber_field_values
+    DROP CONSTRAINT subscriber_field_values_subscriber_id_fkey,
+    ADD CONSTRAINT subscriber_field_values_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE preference_tokens
+    DROP CONSTRAINT preference_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT preference_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE preference_changes
+    DROP CONSTRAINT preference_changes_subscriber_id_fkey,
+    ADD CONSTRAINT preference_changes_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE digest_items
+    DROP CONSTRAINT digest_items_subscriber_id_fkey,
+    ADD CONSTRAINT digest_items_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE consent_events
+    DROP CONSTRAINT consent_events_subscriber_id_fkey,
+    ADD CONSTRAINT consent_events_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE privacy_tokens
+    DROP CONSTRAINT privacy_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT privacy_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE welcome_queue
+    DROP CONSTRAINT welcome_queue_subscriber_id_fkey,
+    ADD CONSTRAINT welcome_queue_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
+ALTER TABLE welcome_deliveries
+    DROP CONSTRAINT welcome_deliveries_subscriber_id_fkey,
+    ADD CONSTRAINT welcome_deliveries_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions(id) ON DELETE CASCADE;
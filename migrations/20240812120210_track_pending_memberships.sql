@@ -0,0 +1,11 @@
+-- When a membership last started waiting for confirmation, so that signups
+-- expire by the age of the request rather than of the subscriber.
+ALTER TABLE list_subscriptions ADD COLUMN pending_since timestamptz NULL;
+UPDATE list_subscriptions SET pending_since = subscribed_at
+WHERE status = 'pending_confirmation';
+
+-- When the subscriber first confirmed a membership. Subscribers who did are
+-- never deleted for leaving a later signup unconfirmed.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+UPDATE subscriptions SET confirmed_at = subscribed_at
+WHERE status IN ('confirmed', 'unsubscribed');
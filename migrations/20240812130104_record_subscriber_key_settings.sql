@@ -0,0 +1,8 @@
+-- The `alias_folding_domains` the subscriber keys were last computed with.
+-- No row means the keys are still the ones the database backfilled, so the
+-- application recomputes them on startup.
+CREATE TABLE subscriber_key_settings (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    alias_folding_domains TEXT[] NOT NULL,
+    rekeyed_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Uploaded imports run in the background. The counts are updated as they
+-- go, and `error` says why a failed import stopped.
+ALTER TABLE subscriber_imports ADD COLUMN status TEXT NOT NULL DEFAULT 'completed'
+    CHECK (status IN ('running', 'completed', 'failed'));
+ALTER TABLE subscriber_imports ADD COLUMN error TEXT NULL;
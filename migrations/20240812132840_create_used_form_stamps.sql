@@ -0,0 +1,8 @@
+-- Nonces of the form stamps that have been submitted, so each rendered
+-- signup form can only be used once. Kept until the stamp would have
+-- expired anyway.
+CREATE TABLE used_form_stamps (
+    nonce TEXT PRIMARY KEY,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX used_form_stamps_expires_at_idx ON used_form_stamps (expires_at);
@@ -0,0 +1,7 @@
+-- Addresses that were sent back with a "did you mean" suggestion, so that
+-- submitting the same address again lets it through on any instance.
+CREATE TABLE typo_suggestions (
+    address TEXT PRIMARY KEY,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX typo_suggestions_expires_at_idx ON typo_suggestions (expires_at);
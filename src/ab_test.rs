@@ -0,0 +1,201 @@
+use anyhow::Context;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbTestMetric {
+    Opens,
+    Clicks,
+}
+
+impl AbTestMetric {
+    /// The `email_events` type that counts as an engagement for this metric.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            AbTestMetric::Opens => "open",
+            AbTestMetric::Clicks => "click",
+        }
+    }
+}
+
+impl TryFrom<String> for AbTestMetric {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "open" => Ok(Self::Opens),
+            "click" => Ok(Self::Clicks),
+            other => Err(format!(
+                "{} is not a supported A/B test metric. Use either 'open' or 'click'.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VariantResult {
+    pub variant: i16,
+    pub sent_count: i32,
+    pub engaged_count: i64,
+}
+
+/// The variant with the best engagement rate wins, ties go to the variant
+/// listed first.
+pub fn pick_winner(results: &[VariantResult]) -> Option<i16> {
+    let mut winner: Option<&VariantResult> = None;
+    for result in results {
+        let better = match winner {
+            None => true,
+            Some(best) => {
+                // Compare engaged / sent ratios without dividing by zero.
+                result.engaged_count * (best.sent_count.max(1) as i64)
+                    > best.engaged_count * (result.sent_count.max(1) as i64)
+            }
+        };
+        if better {
+            winner = Some(result);
+        }
+    }
+    winner.map(|r| r.variant)
+}
+
+/// Picks a winner for every A/B test whose window has elapsed and whose
+/// sample has been fully sent, then releases the rest of the audience.
+#[tracing::instrument(skip_all)]
+pub async fn decide_ab_tests(pool: &PgPool) -> Result<(), anyhow::Error> {
+    while let Some(issue_id) = decide_next_ab_test(pool).await? {
+        tracing::info!(newsletter_issue_id = %issue_id, "Picked the winner of an A/B test");
+    }
+    Ok(())
+}
+
+async fn decide_next_ab_test(pool: &PgPool) -> Result<Option<Uuid>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, ab_metric as "ab_metric!"
+        FROM newsletter_issues i
+        WHERE
+            ab_metric IS NOT NULL AND
+            ab_winner IS NULL AND
+            ab_decide_at <= now() AND
+            delivery_status <> 'cancelled' AND
+            NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+                AND q.subject_variant IS NOT NULL
+            )
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for A/B tests to decide")?;
+    let Some(issue) = issue else {
+        return Ok(None);
+    };
+    let issue_id = issue.newsletter_issue_id;
+    let metric = AbTestMetric::try_from(issue.ab_metric).map_err(anyhow::Error::msg)?;
+    let results = sqlx::query_as!(
+        VariantResult,
+        r#"
+        SELECT
+            v.variant,
+            v.sent_count,
+            COUNT(DISTINCT e.subscriber_email) as "engaged_count!"
+        FROM issue_subject_variants v
+        LEFT JOIN tracking_tokens t
+            ON t.newsletter_issue_id = v.newsletter_issue_id AND t.subject_variant = v.variant
+        LEFT JOIN email_events e
+            ON e.newsletter_issue_id = t.newsletter_issue_id
+            AND e.subscriber_email = t.subscriber_email
+            AND e.event_type = $2
+        WHERE v.newsletter_issue_id = $1
+        GROUP BY v.variant, v.sent_count
+        ORDER BY v.variant
+        "#,
+        issue_id,
+        metric.event_type()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to compute the A/B test results")?;
+    let winner = pick_winner(&results).context("The A/B test has no subject variants")?;
+    for result in &results {
+        transaction
+            .execute(sqlx::query!(
+                r#"
+            UPDATE issue_subject_variants
+            SET engaged_count = $3
+            WHERE newsletter_issue_id = $1 AND variant = $2
+            "#,
+                issue_id,
+                result.variant,
+                result.engaged_count as i32
+            ))
+            .await?;
+    }
+    transaction
+        .execute(sqlx::query!(
+            r#"UPDATE newsletter_issues SET ab_winner = $2 WHERE newsletter_issue_id = $1"#,
+            issue_id,
+            winner
+        ))
+        .await?;
+    transaction
+        .execute(sqlx::query!(
+            r#"UPDATE issue_delivery_queue SET held = false WHERE newsletter_issue_id = $1"#,
+            issue_id
+        ))
+        .await?;
+    transaction.commit().await?;
+    Ok(Some(issue_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(variant: i16, sent_count: i32, engaged_count: i64) -> VariantResult {
+        VariantResult {
+            variant,
+            sent_count,
+            engaged_count,
+        }
+    }
+
+    #[test]
+    fn the_best_rate_wins() {
+        let results = [result(0, 10, 2), result(1, 5, 2), result(2, 10, 3)];
+        assert_eq!(pick_winner(&results), Some(1));
+    }
+
+    #[test]
+    fn ties_go_to_the_first_variant() {
+        let results = [result(0, 10, 0), result(1, 10, 0)];
+        assert_eq!(pick_winner(&results), Some(0));
+    }
+
+    #[test]
+    fn variants_that_were_never_sent_do_not_win() {
+        let results = [result(0, 0, 0), result(1, 10, 1)];
+        assert_eq!(pick_winner(&results), Some(1));
+    }
+
+    #[test]
+    fn no_variants_means_no_winner() {
+        assert_eq!(pick_winner(&[]), None);
+    }
+
+    #[test]
+    fn only_known_metrics_are_accepted() {
+        assert_eq!(
+            AbTestMetric::try_from("click".to_string()),
+            Ok(AbTestMetric::Clicks)
+        );
+        assert!(AbTestMetric::try_from("bounce".to_string()).is_err());
+    }
+}
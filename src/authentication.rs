@@ -1,4 +1,6 @@
 mod middleware;
 mod password;
+mod throttle;
 pub use middleware::*;
 pub use password::*;
+pub use throttle::*;
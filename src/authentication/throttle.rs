@@ -0,0 +1,373 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgExecutor, PgPool};
+
+use crate::{
+    domain::SubscriberEmail,
+    outbox::{enqueue_email, OutboxMessage},
+};
+
+/// Slows down, then locks out, clients that keep failing to log in. Failures
+/// are counted per username and per client IP over a sliding window.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    pub window: Duration,
+    /// Failures allowed before each further attempt has to wait.
+    pub delay_after: i64,
+    /// The wait after `delay_after` failures, doubled with every failure
+    /// since, up to `max_delay`.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub lockout_per_username: i64,
+    pub lockout_per_ip: i64,
+    pub lockout: Duration,
+    /// Who to tell when an account gets locked.
+    pub admin_email: Option<SubscriberEmail>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LockoutScope {
+    Account,
+    Network,
+}
+
+/// Whether a login attempt may go ahead.
+#[derive(Debug, PartialEq)]
+pub enum LoginGate {
+    Open,
+    Delayed(Duration),
+    LockedOut(LockoutScope, Duration),
+}
+
+impl LoginGate {
+    /// What to tell the person trying to log in.
+    pub fn message(&self) -> Option<String> {
+        match self {
+            LoginGate::Open => None,
+            LoginGate::Delayed(wait) => Some(format!(
+                "Too many failed logins. Please wait {} seconds before trying again.",
+                ceil_seconds(*wait)
+            )),
+            LoginGate::LockedOut(LockoutScope::Account, wait) => Some(format!(
+                "This account is locked after too many failed logins. Please try again in {} minutes.",
+                ceil_minutes(*wait)
+            )),
+            LoginGate::LockedOut(LockoutScope::Network, wait) => Some(format!(
+                "Too many failed logins from your network. Please try again in {} minutes.",
+                ceil_minutes(*wait)
+            )),
+        }
+    }
+}
+
+/// Recent failures for one username or one client IP.
+#[derive(Debug, Default)]
+struct Failures {
+    count: i64,
+    last_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginAttempt {
+    /// Turned away before the password was checked.
+    Refused(LoginGate),
+    /// Let through. `locks_account` tells whether failing this attempt
+    /// locks the account, so the admin hears of each lockout once.
+    Started { locks_account: bool },
+}
+
+impl LoginThrottle {
+    #[tracing::instrument(name = "Checking login failures", skip(self, executor))]
+    pub async fn check(
+        &self,
+        executor: impl PgExecutor<'_>,
+        username: &str,
+        ip_address: Option<&str>,
+    ) -> Result<LoginGate, sqlx::Error> {
+        let (by_username, by_ip) = self.failures(executor, username, ip_address).await?;
+        Ok(self.gate(&by_username, &by_ip, Utc::now()))
+    }
+
+    /// Lets an attempt through the throttle, or says why it has to wait.
+    ///
+    /// Attempts are recorded as failures before the password is checked,
+    /// under a lock on the username and client IP, so guesses sent in
+    /// parallel count against each other. `clear` forgets them again once
+    /// the password turns out to be right.
+    #[tracing::instrument(name = "Starting a login attempt", skip(self, pool))]
+    pub async fn begin_attempt(
+        &self,
+        pool: &PgPool,
+        username: &str,
+        ip_address: Option<&str>,
+    ) -> Result<LoginAttempt, sqlx::Error> {
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"SELECT true as "locked!" FROM pg_advisory_xact_lock(1, hashtext($1))"#,
+            username
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if let Some(ip_address) = ip_address {
+            sqlx::query!(
+                r#"SELECT true as "locked!" FROM pg_advisory_xact_lock(2, hashtext($1))"#,
+                ip_address
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+        }
+        let (by_username, by_ip) = self
+            .failures(&mut *transaction, username, ip_address)
+            .await?;
+        let gate = self.gate(&by_username, &by_ip, Utc::now());
+        if gate != LoginGate::Open {
+            return Ok(LoginAttempt::Refused(gate));
+        }
+        sqlx::query!(
+            r#"DELETE FROM login_failures WHERE failed_at < $1"#,
+            Utc::now() - self.window
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            r#"INSERT INTO login_failures (username, ip_address) VALUES ($1, $2)"#,
+            username,
+            ip_address
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(LoginAttempt::Started {
+            locks_account: by_username.count + 1 == self.lockout_per_username,
+        })
+    }
+
+    async fn failures(
+        &self,
+        executor: impl PgExecutor<'_>,
+        username: &str,
+        ip_address: Option<&str>,
+    ) -> Result<(Failures, Failures), sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                count(*) FILTER (WHERE username = $1) as "by_username!",
+                max(failed_at) FILTER (WHERE username = $1) as by_username_last,
+                count(*) FILTER (WHERE ip_address = $2) as "by_ip!",
+                max(failed_at) FILTER (WHERE ip_address = $2) as by_ip_last
+            FROM login_failures
+            WHERE (username = $1 OR ip_address = $2) AND failed_at > $3
+            "#,
+            username,
+            ip_address,
+            Utc::now() - self.window
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok((
+            Failures {
+                count: row.by_username,
+                last_at: row.by_username_last,
+            },
+            Failures {
+                count: row.by_ip,
+                last_at: row.by_ip_last,
+            },
+        ))
+    }
+
+    /// Forgets the failures of a username once someone logs in with it.
+    #[tracing::instrument(name = "Clearing login failures", skip(self, executor))]
+    pub async fn clear(
+        &self,
+        executor: impl PgExecutor<'_>,
+        username: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"DELETE FROM login_failures WHERE username = $1"#,
+            username
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Emails the admin about an account that just got locked. Usernames
+    /// without an account are locked too, but nobody needs to hear of it.
+    #[tracing::instrument(name = "Notifying the admin of a lockout", skip(self, pool))]
+    pub async fn notify_lockout(
+        &self,
+        pool: &PgPool,
+        username: &str,
+        ip_address: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let Some(admin_email) = &self.admin_email else {
+            return Ok(());
+        };
+        let exists = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE username = $1) as "exists!""#,
+            username
+        )
+        .fetch_one(pool)
+        .await?
+        .exists;
+        if !exists {
+            return Ok(());
+        }
+        let ip_address = ip_address.unwrap_or("an unknown address");
+        let minutes = ceil_minutes(self.lockout);
+        enqueue_email(
+            pool,
+            OutboxMessage {
+                kind: "login_lockout",
+                recipient: admin_email,
+                subject: "Your account has been locked",
+                html_content: &format!(
+                    "The account <b>{}</b> was locked for {} minutes after too many failed logins, the last one from {}.<br/>If that was not you, consider changing your password.",
+                    htmlescape::encode_minimal(username),
+                    minutes,
+                    htmlescape::encode_minimal(ip_address)
+                ),
+                text_content: &format!(
+                    "The account {} was locked for {} minutes after too many failed logins, the last one from {}.\nIf that was not you, consider changing your password.",
+                    username, minutes, ip_address
+                ),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn gate(&self, by_username: &Failures, by_ip: &Failures, now: DateTime<Utc>) -> LoginGate {
+        let remaining = |failures: &Failures, wait: Duration| {
+            failures
+                .last_at
+                .map(|last_at| last_at + wait - now)
+                .filter(|remaining| *remaining > Duration::zero())
+        };
+        if by_username.count >= self.lockout_per_username {
+            if let Some(wait) = remaining(by_username, self.lockout) {
+                return LoginGate::LockedOut(LockoutScope::Account, wait);
+            }
+        }
+        if by_ip.count >= self.lockout_per_ip {
+            if let Some(wait) = remaining(by_ip, self.lockout) {
+                return LoginGate::LockedOut(LockoutScope::Network, wait);
+            }
+        }
+        [by_username, by_ip]
+            .into_iter()
+            .filter_map(|failures| remaining(failures, self.delay(failures.count)?))
+            .max()
+            .map_or(LoginGate::Open, LoginGate::Delayed)
+    }
+
+    fn delay(&self, failures: i64) -> Option<Duration> {
+        let doublings = failures.checked_sub(self.delay_after)?;
+        let factor = 1i32.checked_shl(doublings.min(30) as u32)?;
+        Some((self.base_delay * factor).min(self.max_delay))
+    }
+}
+
+fn ceil_seconds(duration: Duration) -> i64 {
+    (duration.num_milliseconds() + 999) / 1000
+}
+
+fn ceil_minutes(duration: Duration) -> i64 {
+    (ceil_seconds(duration) + 59) / 60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            window: Duration::minutes(60),
+            delay_after: 3,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::seconds(30),
+            lockout_per_username: 10,
+            lockout_per_ip: 50,
+            lockout: Duration::minutes(15),
+            admin_email: None,
+        }
+    }
+
+    fn failures(count: i64, seconds_ago: i64, now: DateTime<Utc>) -> Failures {
+        Failures {
+            count,
+            last_at: Some(now - Duration::seconds(seconds_ago)),
+        }
+    }
+
+    #[test]
+    fn a_few_failures_do_not_slow_anyone_down() {
+        let now = Utc::now();
+        let gate = throttle().gate(&failures(2, 0, now), &failures(2, 0, now), now);
+        assert_eq!(gate, LoginGate::Open);
+    }
+
+    #[test]
+    fn the_delay_doubles_with_every_failure_up_to_the_maximum() {
+        let throttle = throttle();
+        assert_eq!(throttle.delay(2), None);
+        assert_eq!(throttle.delay(3), Some(Duration::seconds(1)));
+        assert_eq!(throttle.delay(4), Some(Duration::seconds(2)));
+        assert_eq!(throttle.delay(6), Some(Duration::seconds(8)));
+        assert_eq!(throttle.delay(9), Some(Duration::seconds(30)));
+        assert_eq!(throttle.delay(1000), Some(Duration::seconds(30)));
+    }
+
+    #[test]
+    fn attempts_must_wait_out_the_delay() {
+        let now = Utc::now();
+        let throttle = throttle();
+        let none = Failures::default();
+        assert_eq!(
+            throttle.gate(&failures(5, 1, now), &none, now),
+            LoginGate::Delayed(Duration::seconds(3))
+        );
+        assert_eq!(
+            throttle.gate(&failures(5, 4, now), &none, now),
+            LoginGate::Open
+        );
+    }
+
+    #[test]
+    fn the_longest_delay_applies() {
+        let now = Utc::now();
+        let gate = throttle().gate(&failures(4, 0, now), &failures(8, 0, now), now);
+        assert_eq!(gate, LoginGate::Delayed(Duration::seconds(30)));
+    }
+
+    #[test]
+    fn accounts_are_locked_after_too_many_failures() {
+        let now = Utc::now();
+        let gate = throttle().gate(&failures(10, 60, now), &failures(10, 60, now), now);
+        assert_eq!(
+            gate,
+            LoginGate::LockedOut(LockoutScope::Account, Duration::minutes(14))
+        );
+        assert_eq!(
+            gate.message().unwrap(),
+            "This account is locked after too many failed logins. Please try again in 14 minutes."
+        );
+    }
+
+    #[test]
+    fn networks_are_locked_after_too_many_failures() {
+        let now = Utc::now();
+        let gate = throttle().gate(&failures(1, 0, now), &failures(50, 0, now), now);
+        assert_eq!(
+            gate,
+            LoginGate::LockedOut(LockoutScope::Network, Duration::minutes(15))
+        );
+    }
+
+    #[test]
+    fn lockouts_expire() {
+        let now = Utc::now();
+        let gate = throttle().gate(&failures(10, 20 * 60, now), &Failures::default(), now);
+        assert_eq!(gate, LoginGate::Open);
+    }
+}
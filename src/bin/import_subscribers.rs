@@ -0,0 +1,96 @@
+//! Imports subscribers from a CSV file, like the upload form in the admin area.
+//!
+//! Usage:
+//!     import_subscribers <file.csv> [--list <slug>] [--confirmed --consent-note <note>]
+//!         [--report <report.csv>]
+use std::{fs::File, io::BufReader};
+
+use anyhow::{bail, Context};
+use monkey_letter::{
+    configuration::get_configuration,
+    lists::DEFAULT_LIST_SLUG,
+    startup::get_connection_pool,
+    subscriber_import::{import_subscribers, write_rejection_report, ImportOptions},
+    telemetry::{get_subscriber, init_subscriber},
+};
+
+struct Args {
+    path: String,
+    report_path: Option<String>,
+    options: ImportOptions,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, anyhow::Error> {
+    let mut path = None;
+    let mut report_path = None;
+    let mut options = ImportOptions {
+        list_slug: DEFAULT_LIST_SLUG.to_string(),
+        mark_confirmed: false,
+        consent_note: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .with_context(|| format!("{} needs a value", flag))
+        };
+        match arg.as_str() {
+            "--list" => options.list_slug = value("--list")?,
+            "--confirmed" => options.mark_confirmed = true,
+            "--consent-note" => options.consent_note = Some(value("--consent-note")?),
+            "--report" => report_path = Some(value("--report")?),
+            flag if flag.starts_with("--") => bail!("Unknown option {}", flag),
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!("Only one file can be imported at a time"),
+        }
+    }
+    let Some(path) = path else {
+        bail!(
+            "Usage: import_subscribers <file.csv> [--list <slug>] \
+            [--confirmed --consent-note <note>] [--report <report.csv>]"
+        );
+    };
+    Ok(Args {
+        path,
+        report_path,
+        options,
+    })
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_subscriber(get_subscriber(
+        "import_subscribers",
+        "warn",
+        std::io::stderr,
+    ));
+    let args = parse_args(std::env::args().skip(1))?;
+    let config = get_configuration().expect("Failed to read configuration.");
+    let pool = get_connection_pool(&config.database);
+    let file = File::open(&args.path).with_context(|| format!("Failed to open {}", args.path))?;
+
+    let summary = import_subscribers(
+        &pool,
+        &config.application.base_url,
+        &config.subscriptions,
+        &config.application.hmac_secret,
+        BufReader::new(file),
+        &args.options,
+        "cli",
+    )
+    .await?;
+    println!("Imported: {}", summary.imported);
+    println!("Skipped duplicates: {}", summary.skipped_duplicates);
+    println!(
+        "Skipped suppressed addresses: {}",
+        summary.skipped_suppressed
+    );
+    println!("Rejected invalid rows: {}", summary.rejected_invalid);
+    println!("Rows that failed to import: {}", summary.failed);
+    if let Some(report_path) = args.report_path {
+        let report = File::create(&report_path)
+            .with_context(|| format!("Failed to create {}", report_path))?;
+        write_rejection_report(&pool, summary.import_id, report).await?;
+        println!("Rows that were not imported are listed in {}", report_path);
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+//! Recomputes the keys that tell subscribers apart, merging subscribers that
+//! turn out to share one. The application does this on startup whenever
+//! `alias_folding_domains` has changed; this runs it without the server.
+//!
+//! Usage:
+//!     rekey_subscribers
+use monkey_letter::{
+    configuration::get_configuration,
+    startup::get_connection_pool,
+    subscriber_keys::rekey_subscribers,
+    telemetry::{get_subscriber, init_subscriber},
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_subscriber(get_subscriber("rekey_subscribers", "warn", std::io::stderr));
+    let config = get_configuration().expect("Failed to read configuration.");
+    let pool = get_connection_pool(&config.database);
+
+    let summary = rekey_subscribers(&pool, &config.subscriptions.alias_folding_domains).await?;
+    println!("Rekeyed: {}", summary.rekeyed);
+    println!("Merged duplicates: {}", summary.merged);
+    for email in summary.unparseable {
+        println!("Left as it is, not a valid address: {}", email);
+    }
+    Ok(())
+}
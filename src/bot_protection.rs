@@ -0,0 +1,513 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha3::Sha3_256;
+use sqlx::PgExecutor;
+
+/// Hidden from people by the signup form; bots filling in every field give
+/// themselves away through it.
+pub const HONEYPOT_FIELD: &str = "website";
+
+fn stamp_mac(hmac_secret: &Secret<String>, timestamp: i64, nonce: &str) -> Hmac<Sha3_256> {
+    let mut mac = Hmac::<Sha3_256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"form-stamp:");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b":");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+/// Records when the signup form was rendered, with a nonce that makes each
+/// rendered form single use, signed so it cannot be forged.
+pub fn form_stamp(hmac_secret: &Secret<String>, rendered_at: DateTime<Utc>) -> String {
+    let timestamp = rendered_at.timestamp();
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let signature = stamp_mac(hmac_secret, timestamp, &nonce)
+        .finalize()
+        .into_bytes();
+    format!("{}.{}.{}", timestamp, nonce, hex::encode(signature))
+}
+
+/// A form stamp whose signature checks out.
+#[derive(Debug)]
+pub struct FormStamp {
+    pub rendered_at: DateTime<Utc>,
+    pub nonce: String,
+}
+
+pub fn verify_form_stamp(
+    hmac_secret: &Secret<String>,
+    stamp: &str,
+) -> Result<FormStamp, anyhow::Error> {
+    let mut parts = stamp.splitn(3, '.');
+    let (Some(timestamp), Some(nonce), Some(signature)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("The form stamp has no nonce or signature");
+    };
+    let timestamp: i64 = timestamp
+        .parse()
+        .context("The form stamp has no valid timestamp")?;
+    let signature = hex::decode(signature).context("The form stamp signature is not valid hex")?;
+    stamp_mac(hmac_secret, timestamp, nonce)
+        .verify_slice(&signature)
+        .context("The form stamp signature does not match")?;
+    Ok(FormStamp {
+        rendered_at: DateTime::from_timestamp(timestamp, 0)
+            .context("The form stamp timestamp is out of range")?,
+        nonce: nonce.to_string(),
+    })
+}
+
+/// Checks the response of a CAPTCHA widget.
+pub trait CaptchaVerifier: Send + Sync {
+    fn verify<'a>(
+        &'a self,
+        response: &'a str,
+        remote_ip: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<bool, anyhow::Error>>;
+}
+
+/// Providers sharing the `siteverify` API.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    Hcaptcha,
+    Recaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    pub fn script_url(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "https://js.hcaptcha.com/1/api.js",
+            CaptchaProvider::Recaptcha => "https://www.google.com/recaptcha/api.js",
+            CaptchaProvider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/api.js",
+        }
+    }
+    pub fn widget_class(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "h-captcha",
+            CaptchaProvider::Recaptcha => "g-recaptcha",
+            CaptchaProvider::Turnstile => "cf-turnstile",
+        }
+    }
+    /// The form field the widget fills in.
+    pub fn response_field(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "h-captcha-response",
+            CaptchaProvider::Recaptcha => "g-recaptcha-response",
+            CaptchaProvider::Turnstile => "cf-turnstile-response",
+        }
+    }
+    pub fn verify_url(&self) -> &'static str {
+        match self {
+            CaptchaProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            CaptchaProvider::Recaptcha => "https://www.google.com/recaptcha/api/siteverify",
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+        }
+    }
+}
+
+pub struct SiteVerifyCaptcha {
+    http_client: reqwest::Client,
+    verify_url: String,
+    secret: Secret<String>,
+}
+
+impl SiteVerifyCaptcha {
+    pub fn new(verify_url: String, secret: Secret<String>, timeout: Duration) -> Self {
+        Self {
+            http_client: reqwest::Client::builder().timeout(timeout).build().unwrap(),
+            verify_url,
+            secret,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SiteVerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remoteip: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl CaptchaVerifier for SiteVerifyCaptcha {
+    fn verify<'a>(
+        &'a self,
+        response: &'a str,
+        remote_ip: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move {
+            let outcome: SiteVerifyResponse = self
+                .http_client
+                .post(&self.verify_url)
+                .form(&SiteVerifyRequest {
+                    secret: self.secret.expose_secret(),
+                    response,
+                    remoteip: remote_ip,
+                })
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .context("The CAPTCHA provider sent an invalid answer")?;
+            Ok(outcome.success)
+        })
+    }
+}
+
+/// Accepts a single fixed response, for tests and local development.
+pub struct StubCaptcha {
+    valid_response: String,
+}
+
+impl StubCaptcha {
+    pub fn new(valid_response: String) -> Self {
+        Self { valid_response }
+    }
+}
+
+impl CaptchaVerifier for StubCaptcha {
+    fn verify<'a>(
+        &'a self,
+        response: &'a str,
+        _remote_ip: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move { Ok(response == self.valid_response) })
+    }
+}
+
+/// What the signup form needs to show the CAPTCHA widget.
+#[derive(Clone, Debug)]
+pub struct CaptchaWidget {
+    pub provider: CaptchaProvider,
+    pub site_key: String,
+}
+
+pub struct Captcha {
+    pub verifier: Arc<dyn CaptchaVerifier>,
+    /// `None` for the stub, which has no widget.
+    pub widget: Option<CaptchaWidget>,
+}
+
+/// Checks run on public signups before anything gets stored or sent.
+pub struct BotProtection {
+    pub hmac_secret: Secret<String>,
+    pub min_fill_time: chrono::Duration,
+    /// Stamps older than this cannot be replayed.
+    pub form_stamp_ttl: chrono::Duration,
+    /// Form posts always need a stamp; this asks JSON calls for one too.
+    pub require_form_stamp: bool,
+    pub captcha: Option<Captcha>,
+    pub limits: ConfirmationLimits,
+}
+
+/// Why a signup was turned away.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Human,
+    /// Dropped without telling the client, so bots learn nothing.
+    Bot(&'static str),
+    Rejected(&'static str),
+}
+
+/// What the signup form submitted besides the subscriber's details.
+#[derive(Debug, Default)]
+pub struct SignupChallenge<'a> {
+    pub honeypot: Option<&'a str>,
+    pub form_stamp: Option<&'a str>,
+    pub captcha_response: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+    /// Posted as a form rather than as JSON.
+    pub from_form: bool,
+}
+
+impl BotProtection {
+    #[tracing::instrument(name = "Checking a signup for bots", skip(self))]
+    pub async fn check(&self, challenge: &SignupChallenge<'_>) -> Result<Verdict, anyhow::Error> {
+        if challenge.honeypot.is_some_and(|v| !v.trim().is_empty()) {
+            return Ok(Verdict::Bot("the honeypot field was filled in"));
+        }
+        match challenge.form_stamp.filter(|s| !s.is_empty()) {
+            Some(stamp) => match verify_form_stamp(&self.hmac_secret, stamp) {
+                Ok(stamp) if Utc::now() - stamp.rendered_at < self.min_fill_time => {
+                    return Ok(Verdict::Bot("the form was filled in too quickly"));
+                }
+                Ok(stamp) if Utc::now() - stamp.rendered_at > self.form_stamp_ttl => {
+                    return Ok(Verdict::Rejected(
+                        "This form has expired. Please reload the page and try again.",
+                    ));
+                }
+                Ok(_) => {}
+                Err(_) => return Ok(Verdict::Bot("the form stamp is forged")),
+            },
+            None if challenge.from_form || self.require_form_stamp => {
+                return Ok(Verdict::Rejected("Please sign up through the signup form."));
+            }
+            None => {}
+        }
+        if let Some(captcha) = &self.captcha {
+            let response = challenge.captcha_response.unwrap_or_default();
+            if response.is_empty()
+                || !captcha
+                    .verifier
+                    .verify(response, challenge.ip_address)
+                    .await
+                    .context("Failed to verify the CAPTCHA")?
+            {
+                return Ok(Verdict::Rejected("Please complete the CAPTCHA."));
+            }
+        }
+        Ok(Verdict::Human)
+    }
+
+    /// Marks a checked form stamp as used. Returns `false` when it already
+    /// was, so a rendered form cannot be replayed for more signups.
+    #[tracing::instrument(name = "Claiming a form stamp", skip(self, executor))]
+    pub async fn claim_form_stamp(
+        &self,
+        executor: impl PgExecutor<'_>,
+        stamp: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let stamp = verify_form_stamp(&self.hmac_secret, stamp)?;
+        let claimed = sqlx::query!(
+            r#"
+            INSERT INTO used_form_stamps (nonce, expires_at) VALUES ($1, $2)
+            ON CONFLICT (nonce) DO NOTHING
+            "#,
+            stamp.nonce,
+            stamp.rendered_at + self.form_stamp_ttl
+        )
+        .execute(executor)
+        .await
+        .context("Failed to record the form stamp as used")?
+        .rows_affected();
+        Ok(claimed == 1)
+    }
+}
+
+/// How many confirmation emails public endpoints may queue within a window.
+#[derive(Clone, Debug)]
+pub struct ConfirmationLimits {
+    pub window: chrono::Duration,
+    pub per_ip: i64,
+    pub per_email: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SendAllowance {
+    Allowed,
+    /// The client has asked for too many confirmation emails.
+    IpLimited,
+    /// The address has been sent enough confirmation emails for now.
+    EmailLimited,
+}
+
+impl ConfirmationLimits {
+    #[tracing::instrument(name = "Checking confirmation send limits", skip(self, executor))]
+    pub async fn check(
+        &self,
+        executor: impl PgExecutor<'_>,
+        email_key: &str,
+        ip_address: Option<&str>,
+    ) -> Result<SendAllowance, sqlx::Error> {
+        let counts = sqlx::query!(
+            r#"
+            SELECT
+                count(*) FILTER (WHERE ip_address = $2) as "by_ip!",
+                count(*) FILTER (WHERE email_key = $1) as "by_email!"
+            FROM confirmation_sends
+            WHERE (email_key = $1 OR ip_address = $2) AND sent_at > $3
+            "#,
+            email_key,
+            ip_address,
+            Utc::now() - self.window
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(if counts.by_ip >= self.per_ip {
+            SendAllowance::IpLimited
+        } else if counts.by_email >= self.per_email {
+            SendAllowance::EmailLimited
+        } else {
+            SendAllowance::Allowed
+        })
+    }
+}
+
+#[tracing::instrument(skip(executor, email_key))]
+pub async fn record_confirmation_send(
+    executor: impl PgExecutor<'_>,
+    email_key: &str,
+    ip_address: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO confirmation_sends (email_key, ip_address) VALUES ($1, $2)"#,
+        email_key,
+        ip_address
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{
+        form_stamp, verify_form_stamp, BotProtection, Captcha, ConfirmationLimits, SignupChallenge,
+        StubCaptcha, Verdict,
+    };
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    fn protection(captcha: Option<Captcha>) -> BotProtection {
+        BotProtection {
+            hmac_secret: secret(),
+            min_fill_time: Duration::seconds(3),
+            form_stamp_ttl: Duration::hours(1),
+            require_form_stamp: false,
+            captcha,
+            limits: ConfirmationLimits {
+                window: Duration::hours(1),
+                per_ip: 10,
+                per_email: 3,
+            },
+        }
+    }
+
+    #[test]
+    fn form_stamps_round_trip() {
+        let rendered_at = Utc::now() - Duration::seconds(10);
+        let stamp = form_stamp(&secret(), rendered_at);
+        assert_eq!(
+            verify_form_stamp(&secret(), &stamp)
+                .unwrap()
+                .rendered_at
+                .timestamp(),
+            rendered_at.timestamp()
+        );
+    }
+
+    #[test]
+    fn each_form_stamp_gets_its_own_nonce() {
+        let rendered_at = Utc::now();
+        let first = verify_form_stamp(&secret(), &form_stamp(&secret(), rendered_at)).unwrap();
+        let second = verify_form_stamp(&secret(), &form_stamp(&secret(), rendered_at)).unwrap();
+        assert_ne!(first.nonce, second.nonce);
+    }
+
+    #[test]
+    fn tampered_form_stamps_are_rejected() {
+        let stamp = form_stamp(&secret(), Utc::now());
+        let (_, nonce_and_signature) = stamp.split_once('.').unwrap();
+        assert_err!(verify_form_stamp(
+            &secret(),
+            &format!("1.{}", nonce_and_signature)
+        ));
+        let (timestamp_and_nonce, signature) = stamp.rsplit_once('.').unwrap();
+        assert_err!(verify_form_stamp(
+            &secret(),
+            &format!("{}0.{}", timestamp_and_nonce, signature)
+        ));
+        assert_err!(verify_form_stamp(&Secret::new("other".into()), &stamp));
+        assert_err!(verify_form_stamp(&secret(), "not-a-stamp"));
+    }
+
+    #[tokio::test]
+    async fn filled_honeypots_and_hasty_forms_are_bots() {
+        let protection = protection(None);
+        let honeypot = SignupChallenge {
+            honeypot: Some("https://spam.example"),
+            ..Default::default()
+        };
+        let stamp = form_stamp(&secret(), Utc::now());
+        let hasty = SignupChallenge {
+            form_stamp: Some(&stamp),
+            ..Default::default()
+        };
+        assert!(matches!(
+            protection.check(&honeypot).await.unwrap(),
+            Verdict::Bot(_)
+        ));
+        assert!(matches!(
+            protection.check(&hasty).await.unwrap(),
+            Verdict::Bot(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn form_posts_need_a_fresh_stamp() {
+        let protection = protection(None);
+        let check = |stamp: Option<String>, from_form| {
+            let protection = &protection;
+            async move {
+                protection
+                    .check(&SignupChallenge {
+                        form_stamp: stamp.as_deref(),
+                        from_form,
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap()
+            }
+        };
+        let fresh = form_stamp(&secret(), Utc::now() - Duration::minutes(5));
+        let stale = form_stamp(&secret(), Utc::now() - Duration::hours(2));
+        assert_eq!(check(Some(fresh), true).await, Verdict::Human);
+        assert!(matches!(
+            check(Some(stale), true).await,
+            Verdict::Rejected(_)
+        ));
+        assert!(matches!(check(None, true).await, Verdict::Rejected(_)));
+        assert_eq!(check(None, false).await, Verdict::Human);
+    }
+
+    #[tokio::test]
+    async fn captcha_responses_are_verified() {
+        let protection = protection(Some(Captcha {
+            verifier: Arc::new(StubCaptcha::new("pass".into())),
+            widget: None,
+        }));
+        let check = |response| {
+            let protection = &protection;
+            async move {
+                protection
+                    .check(&SignupChallenge {
+                        captcha_response: response,
+                        ..Default::default()
+                    })
+                    .await
+            }
+        };
+        assert_eq!(assert_ok!(check(Some("pass")).await), Verdict::Human);
+        assert!(matches!(
+            check(Some("fail")).await.unwrap(),
+            Verdict::Rejected(_)
+        ));
+        assert!(matches!(check(None).await.unwrap(), Verdict::Rejected(_)));
+    }
+}
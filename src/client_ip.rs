@@ -0,0 +1,220 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{
+    http::header::{HeaderMap, FORWARDED, X_FORWARDED_FOR},
+    web, HttpRequest,
+};
+
+/// The address of the client, for rate limits and audit records.
+///
+/// Forwarding headers are written by whoever sent the request, so they are
+/// only believed when the connection comes from a trusted proxy. Even then,
+/// only the hops added by trusted proxies count: the client is the right-most
+/// hop that is not one of them.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    let peer = request.peer_addr()?.ip().to_canonical();
+    Some(match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_ip(peer, request.headers()),
+        None => peer,
+    })
+}
+
+/// The proxies in front of the app, whose forwarding headers can be relied on.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<IpNetwork>);
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNetwork>) -> Self {
+        Self(networks)
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.trusts(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        for hop in forwarded_hops(headers).into_iter().rev() {
+            // A hop we cannot read stops the walk at the last proxy we trust.
+            let Some(hop) = hop else { break };
+            client = hop;
+            if !self.trusts(hop) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The hops listed in `Forwarded`, or else `X-Forwarded-For`, oldest first.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(str::trim)
+            .filter(|hop| !hop.is_empty())
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_hop(node))
+            })
+            .collect();
+    }
+    values(X_FORWARDED_FOR).into_iter().map(parse_hop).collect()
+}
+
+/// Reads an address that may be quoted, bracketed or carry a port.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|socket| socket.ip()))
+        .or_else(|_| {
+            hop.strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .map_or(Err(()), |(ip, _)| ip.parse().map_err(|_| ()))
+        })
+        .ok()
+        .map(|ip: IpAddr| ip.to_canonical())
+}
+
+/// An address range such as `10.0.0.0/8`, or a single address.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.as_str(), None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("{} is not a valid IP address or network.", value))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("{} has an invalid prefix length.", value))?,
+            None => max_prefix,
+        };
+        Ok(Self {
+            address: address.to_canonical(),
+            prefix,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::HeaderValue;
+
+    fn proxies(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(
+            networks
+                .iter()
+                .map(|n| IpNetwork::try_from(n.to_string()).unwrap())
+                .collect(),
+        )
+    }
+
+    fn headers(name: actix_web::http::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarding_headers_from_untrusted_peers_are_ignored() {
+        let headers = headers(X_FORWARDED_FOR, "198.51.100.1");
+        let client = proxies(&[]).client_ip(ip("203.0.113.7"), &headers);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_right_most_untrusted_hop_is_the_client() {
+        let headers = headers(X_FORWARDED_FOR, "1.1.1.1, 198.51.100.1, 10.0.0.2");
+        let client = proxies(&["10.0.0.0/8"]).client_ip(ip("10.0.0.1"), &headers);
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn the_forwarded_header_is_understood() {
+        let headers = headers(
+            FORWARDED,
+            r#"for=1.1.1.1, for="[2001:db8::17]:4711";proto=https"#,
+        );
+        let client = proxies(&["10.0.0.1"]).client_ip(ip("10.0.0.1"), &headers);
+        assert_eq!(client, ip("2001:db8::17"));
+    }
+
+    #[test]
+    fn unreadable_hops_stop_at_the_last_trusted_proxy() {
+        let headers = headers(X_FORWARDED_FOR, "198.51.100.1, unknown");
+        let client = proxies(&["10.0.0.1"]).client_ip(ip("10.0.0.1"), &headers);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn ports_are_dropped_from_hops() {
+        assert_eq!(parse_hop("127.0.0.1:8000"), Some(ip("127.0.0.1")));
+        assert_eq!(parse_hop("[::1]:8000"), Some(ip("::1")));
+        assert_eq!(parse_hop("203.0.113.7"), Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn networks_match_addresses_within_their_prefix() {
+        let network = IpNetwork::try_from("192.168.0.0/16".to_string()).unwrap();
+        assert!(network.contains(ip("192.168.4.2")));
+        assert!(!network.contains(ip("192.169.0.1")));
+        assert!(!network.contains(ip("::1")));
+        let everything = IpNetwork::try_from("0.0.0.0/0".to_string()).unwrap();
+        assert!(everything.contains(ip("8.8.8.8")));
+        assert!(IpNetwork::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(IpNetwork::try_from("not-an-ip".to_string()).is_err());
+    }
+}
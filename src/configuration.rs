@@ -3,13 +3,28 @@ use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
 
+use std::sync::Arc;
+
+use crate::authentication::LoginThrottle;
+use crate::bot_protection::{
+    BotProtection, Captcha, CaptchaProvider, CaptchaWidget, ConfirmationLimits, SiteVerifyCaptcha,
+    StubCaptcha,
+};
+use crate::client_ip::IpNetwork;
+use crate::deliverability::{DeliverabilityChecker, DnsResolver, DomainResolver, StaticResolver};
 use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
+use crate::lists::OptIn;
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub deliverability: DeliverabilitySettings,
+    pub bot_protection: BotProtectionSettings,
+    pub rate_limits: RateLimitSettings,
+    pub login_protection: LoginProtectionSettings,
     pub redis_url: Secret<String>,
 }
 
@@ -20,6 +35,10 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Proxies whose `Forwarded` or `X-Forwarded-For` headers tell the client
+    /// address. Requests from anywhere else are keyed on the peer address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNetwork>,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -52,6 +71,238 @@ impl EmailClientSettings {
     }
 }
 
+/// How long confirmation links stay valid, when unconfirmed subscribers get
+/// reminded, and how long unconfirmed signups are kept around before the
+/// cleanup job removes them.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SubscriptionSettings {
+    /// Used by lists that do not pick an opt-in mode themselves.
+    pub default_opt_in: OptIn,
+    pub confirmation_token_ttl_hours: i64,
+    pub reminder_after_hours: i64,
+    pub max_reminders: i16,
+    pub token_retention_days: i64,
+    pub pending_retention_days: i64,
+    pub cleanup_interval_minutes: u64,
+    /// Recorded with signups whose form does not say which consent text it showed.
+    pub consent_text_version: String,
+    /// Domains whose mailboxes ignore dots and `+tag` suffixes, such as
+    /// `gmail.com`. Addresses at these domains that only differ that way are
+    /// treated as the same subscriber. Run `rekey_subscribers` after
+    /// changing this list, so existing subscribers are keyed the same way.
+    #[serde(default)]
+    pub alias_folding_domains: Vec<String>,
+}
+
+impl SubscriptionSettings {
+    pub fn token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.confirmation_token_ttl_hours)
+    }
+    pub fn reminder_after(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.reminder_after_hours)
+    }
+    pub fn token_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.token_retention_days)
+    }
+    pub fn pending_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.pending_retention_days)
+    }
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_minutes * 60)
+    }
+}
+
+/// Optional checks on the domain of new subscribers' addresses.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct DeliverabilitySettings {
+    /// Rejects domains with neither an MX nor an A/AAAA record.
+    pub check_domains: bool,
+    pub resolver: ResolverSettings,
+    /// Disposable email providers. Their subdomains are blocked too.
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    /// Rejects likely typos of common providers, such as `gmial.com`, with a
+    /// suggestion. Submitting the same address again accepts it as it is.
+    pub suggest_corrections: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResolverSettings {
+    Dns {
+        /// Defaults to the nameservers from /etc/resolv.conf.
+        nameserver: Option<std::net::SocketAddr>,
+        timeout_milliseconds: u64,
+    },
+    /// Only the listed domains accept email.
+    Static { domains: Vec<String> },
+}
+
+impl DeliverabilitySettings {
+    pub fn checker(&self) -> Result<DeliverabilityChecker, anyhow::Error> {
+        let resolver: Option<Arc<dyn DomainResolver>> = match &self.resolver {
+            _ if !self.check_domains => None,
+            ResolverSettings::Dns {
+                nameserver,
+                timeout_milliseconds,
+            } => Some(Arc::new(DnsResolver::new(
+                *nameserver,
+                std::time::Duration::from_millis(*timeout_milliseconds),
+            )?)),
+            ResolverSettings::Static { domains } => Some(Arc::new(StaticResolver::new(domains))),
+        };
+        Ok(DeliverabilityChecker::new(
+            resolver,
+            &self.blocked_domains,
+            self.suggest_corrections,
+        ))
+    }
+}
+
+/// Defences of the public signup endpoints against bots.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct BotProtectionSettings {
+    /// Signups sent sooner than this after the form was rendered are dropped.
+    pub min_fill_seconds: i64,
+    /// How long a rendered signup form can be submitted.
+    pub form_stamp_ttl_minutes: i64,
+    /// Form posts always need the stamp of a rendered signup form. This
+    /// asks JSON API calls for one too; turning it off lets scripts skip the
+    /// stamp checks by posting JSON.
+    pub require_form_stamp: bool,
+    pub captcha: CaptchaSettings,
+    pub confirmation_window_minutes: i64,
+    /// Confirmation emails a single client IP can trigger per window.
+    pub confirmations_per_ip: i64,
+    /// Confirmation emails a single address can receive per window.
+    pub confirmations_per_email: i64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CaptchaSettings {
+    Disabled,
+    SiteVerify {
+        provider: CaptchaProvider,
+        site_key: String,
+        secret: Secret<String>,
+        /// Defaults to the provider's own endpoint.
+        verify_url: Option<String>,
+        timeout_milliseconds: u64,
+    },
+    /// Accepts a single fixed response, for tests and local development.
+    Stub {
+        valid_response: String,
+    },
+}
+
+impl BotProtectionSettings {
+    pub fn protection(&self, hmac_secret: Secret<String>) -> BotProtection {
+        let captcha = match &self.captcha {
+            CaptchaSettings::Disabled => None,
+            CaptchaSettings::SiteVerify {
+                provider,
+                site_key,
+                secret,
+                verify_url,
+                timeout_milliseconds,
+            } => Some(Captcha {
+                verifier: Arc::new(SiteVerifyCaptcha::new(
+                    verify_url
+                        .clone()
+                        .unwrap_or_else(|| provider.verify_url().to_string()),
+                    secret.clone(),
+                    std::time::Duration::from_millis(*timeout_milliseconds),
+                )),
+                widget: Some(CaptchaWidget {
+                    provider: *provider,
+                    site_key: site_key.clone(),
+                }),
+            }),
+            CaptchaSettings::Stub { valid_response } => Some(Captcha {
+                verifier: Arc::new(StubCaptcha::new(valid_response.clone())),
+                widget: None,
+            }),
+        };
+        BotProtection {
+            hmac_secret,
+            min_fill_time: chrono::Duration::seconds(self.min_fill_seconds),
+            form_stamp_ttl: chrono::Duration::minutes(self.form_stamp_ttl_minutes),
+            require_form_stamp: self.require_form_stamp,
+            captcha,
+            limits: ConfirmationLimits {
+                window: chrono::Duration::minutes(self.confirmation_window_minutes),
+                per_ip: self.confirmations_per_ip,
+                per_email: self.confirmations_per_email,
+            },
+        }
+    }
+}
+
+/// Token buckets per client IP, shared by every app instance through Redis.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// Prefix of the Redis keys holding the buckets.
+    pub key_prefix: String,
+    pub login: BucketSettings,
+    pub subscribe: BucketSettings,
+    pub confirm: BucketSettings,
+    pub webhooks: BucketSettings,
+    pub api: BucketSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct BucketSettings {
+    /// How many requests can be made in a burst.
+    pub capacity: u32,
+    /// How many requests are allowed per minute once the burst is used up.
+    pub refill_per_minute: u32,
+}
+
+impl BucketSettings {
+    pub fn refill_per_ms(&self) -> f64 {
+        f64::from(self.refill_per_minute) / 60_000.0
+    }
+}
+
+/// Defences of the login form against password guessing.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LoginProtectionSettings {
+    /// How long failed logins are remembered.
+    pub window_minutes: i64,
+    /// Failures allowed before each further attempt has to wait.
+    pub delay_after_failures: i64,
+    /// The first wait, doubled with every further failure.
+    pub delay_seconds: i64,
+    pub max_delay_seconds: i64,
+    pub lockout_after_failures_per_username: i64,
+    pub lockout_after_failures_per_ip: i64,
+    pub lockout_minutes: i64,
+    /// Gets an email whenever an account is locked.
+    pub admin_email: Option<String>,
+}
+
+impl LoginProtectionSettings {
+    pub fn throttle(&self) -> Result<LoginThrottle, String> {
+        let admin_email = self
+            .admin_email
+            .clone()
+            .map(SubscriberEmail::parse)
+            .transpose()?;
+        Ok(LoginThrottle {
+            window: chrono::Duration::minutes(self.window_minutes),
+            delay_after: self.delay_after_failures,
+            base_delay: chrono::Duration::seconds(self.delay_seconds),
+            max_delay: chrono::Duration::seconds(self.max_delay_seconds),
+            lockout_per_username: self.lockout_after_failures_per_username,
+            lockout_per_ip: self.lockout_after_failures_per_ip,
+            lockout: chrono::Duration::minutes(self.lockout_minutes),
+            admin_email,
+        })
+    }
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
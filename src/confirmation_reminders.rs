@@ -0,0 +1,125 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool};
+
+use crate::{
+    configuration::SubscriptionSettings,
+    domain::SubscriberEmail,
+    outbox::{enqueue_email, OutboxMessage},
+    routes::{confirmation_link, generate_subscription_token, store_token},
+};
+
+/// Reminds pending subscribers to confirm, each time with a fresh link.
+/// Reminders go through the outbox, which retries failed deliveries.
+#[tracing::instrument(skip_all)]
+pub async fn send_confirmation_reminders(
+    pool: &PgPool,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+) -> Result<(), anyhow::Error> {
+    while try_send_next_reminder(pool, base_url, settings).await? {}
+    Ok(())
+}
+
+/// A membership is due when its newest token is older than the reminder
+/// delay, so reminders are spaced out by the same delay. Addresses that
+/// cannot be emailed still count the reminder, so they are not picked again.
+#[tracing::instrument(skip_all, fields(subscriber_id = tracing::field::Empty))]
+async fn try_send_next_reminder(
+    pool: &PgPool,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let due = sqlx::query!(
+        r#"
+        SELECT ls.subscriber_id, ls.list_id, s.email, l.name as list_name
+        FROM list_subscriptions ls
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE
+            ls.status = 'pending_confirmation' AND
+            ls.reminders_sent < $1 AND
+            (
+                SELECT max(t.created_at) FROM subscription_tokens t
+                WHERE t.subscriber_id = ls.subscriber_id AND t.list_id = ls.list_id
+            ) <= $2
+        FOR UPDATE OF ls
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        settings.max_reminders,
+        Utc::now() - settings.reminder_after()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for pending subscribers to remind")?;
+    let Some(due) = due else {
+        return Ok(false);
+    };
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(due.subscriber_id));
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        due.subscriber_id,
+        due.list_id,
+        &subscription_token,
+    )
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET reminders_sent = reminders_sent + 1, last_reminder_at = now()
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        due.subscriber_id,
+        due.list_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    match SubscriberEmail::parse(due.email) {
+        Ok(email) => enqueue_reminder_email(
+            &mut *transaction,
+            &email,
+            &due.list_name,
+            &confirmation_link(base_url, &subscription_token),
+        )
+        .await
+        .context("Failed to queue a confirmation reminder")?,
+        Err(e) => tracing::error!(
+            error.message = %e,
+            "Skipping a confirmation reminder. The stored address is invalid"
+        ),
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+async fn enqueue_reminder_email(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+    list_name: &str,
+    confirmation_link: &str,
+) -> Result<(), sqlx::Error> {
+    enqueue_email(
+        executor,
+        OutboxMessage {
+            kind: "confirmation_reminder",
+            recipient: email,
+            subject: "Please confirm your subscription",
+            html_content: &format!(
+                "You signed up for {} but have not confirmed your subscription yet.<br/>\
+                Click <a href=\"{}\">HERE</a> to start receiving it.",
+                htmlescape::encode_minimal(list_name),
+                confirmation_link
+            ),
+            text_content: &format!(
+                "You signed up for {} but have not confirmed your subscription yet.\n\
+                Visit {} to start receiving it.",
+                list_name, confirmation_link
+            ),
+        },
+    )
+    .await?;
+    Ok(())
+}
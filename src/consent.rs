@@ -0,0 +1,162 @@
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::client_ip::client_ip;
+
+/// Where a signup or confirmation came from.
+#[derive(Debug, Default)]
+pub struct ConsentContext {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ConsentContext {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        let ip_address = client_ip(request).map(|ip| ip.to_string());
+        let user_agent = request
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|ua| ua.chars().take(512).collect());
+        Self {
+            ip_address,
+            user_agent,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConsentEvent {
+    pub email: String,
+    pub list: Option<String>,
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub form_id: Option<String>,
+    pub consent_text_version: Option<String>,
+    pub note: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip(executor, context))]
+pub async fn record_signup_consent(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    context: &ConsentContext,
+    form_id: Option<&str>,
+    consent_text_version: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (
+            event_id, subscriber_id, list_id, event_type,
+            ip_address, user_agent, form_id, consent_text_version
+        )
+        VALUES ($1, $2, $3, 'signup', $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        list_id,
+        context.ip_address,
+        context.user_agent,
+        form_id,
+        consent_text_version
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// The form and consent text are the ones of the signup being confirmed.
+#[tracing::instrument(skip(executor, context))]
+pub async fn record_confirmation_consent(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    context: &ConsentContext,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (
+            event_id, subscriber_id, list_id, event_type,
+            ip_address, user_agent, form_id, consent_text_version
+        )
+        SELECT $1, $2, $3, 'confirmation', $4, $5, signup.form_id, signup.consent_text_version
+        FROM (SELECT 1) AS one
+        LEFT JOIN LATERAL (
+            SELECT form_id, consent_text_version FROM consent_events
+            WHERE subscriber_id = $2 AND list_id = $3 AND event_type = 'signup'
+            ORDER BY occurred_at DESC
+            LIMIT 1
+        ) signup ON true
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        list_id,
+        context.ip_address,
+        context.user_agent
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// For consent given outside of the signup form, e.g. by subscribers
+/// imported as already confirmed. The note says where it was given.
+#[tracing::instrument(skip(executor))]
+pub async fn record_consent_note(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    event_type: &str,
+    note: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (event_id, subscriber_id, list_id, event_type, note)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        list_id,
+        event_type,
+        note
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Every consent event, or only those of one subscriber, oldest first.
+#[tracing::instrument(skip(pool))]
+pub async fn get_consent_events(
+    pool: &PgPool,
+    email: Option<&str>,
+) -> Result<Vec<ConsentEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentEvent,
+        r#"
+        SELECT
+            s.email,
+            l.slug as "list?",
+            c.event_type,
+            c.ip_address,
+            c.user_agent,
+            c.form_id,
+            c.consent_text_version,
+            c.note,
+            c.occurred_at
+        FROM consent_events c
+        JOIN subscriptions s ON s.id = c.subscriber_id
+        LEFT JOIN lists l ON l.list_id = c.list_id
+        WHERE $1::text IS NULL OR lower(s.email) = lower(btrim($1))
+        ORDER BY c.occurred_at, s.email
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Keys already used by the signup form itself.
+const RESERVED_KEYS: [&str; 9] = [
+    "name",
+    "email",
+    "list",
+    "tags",
+    "form_id",
+    "consent_version",
+    "website",
+    "form_stamp",
+    "captcha_response",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Text,
+    Number,
+    Date,
+    Choice,
+}
+
+impl FieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Number => "number",
+            FieldType::Date => "date",
+            FieldType::Choice => "choice",
+        }
+    }
+}
+
+impl TryFrom<String> for FieldType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "text" => Ok(Self::Text),
+            "number" => Ok(Self::Number),
+            "date" => Ok(Self::Date),
+            "choice" => Ok(Self::Choice),
+            other => Err(format!(
+                "{} is not a supported field type. Use text, number, date or choice.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomField {
+    pub field_id: Uuid,
+    pub key: String,
+    pub label: String,
+    pub field_type: FieldType,
+    pub options: Vec<String>,
+    pub required: bool,
+}
+
+impl CustomField {
+    /// Checks a submitted value against the field type and returns its
+    /// canonical text form, or `None` if the value is empty.
+    pub fn parse_value(&self, value: &serde_json::Value) -> Result<Option<String>, String> {
+        let raw = match value {
+            serde_json::Value::Null => return Ok(None),
+            serde_json::Value::String(s) => s.trim().to_string(),
+            serde_json::Value::Number(n) => n.to_string(),
+            serde_json::Value::Bool(b) => b.to_string(),
+            _ => return Err(format!("{} must be a single value.", self.label)),
+        };
+        if raw.is_empty() {
+            return Ok(None);
+        }
+        let value = match self.field_type {
+            FieldType::Text if raw.chars().count() > 1000 => {
+                return Err(format!("{} is too long.", self.label))
+            }
+            FieldType::Text => raw,
+            FieldType::Number => match raw.parse::<f64>() {
+                Ok(n) if n.is_finite() => n.to_string(),
+                _ => return Err(format!("{} must be a number.", self.label)),
+            },
+            FieldType::Date => NaiveDate::parse_from_str(&raw, "%Y-%m-%d")
+                .map_err(|_| format!("{} must be a date formatted as YYYY-MM-DD.", self.label))?
+                .to_string(),
+            FieldType::Choice => {
+                if !self.options.contains(&raw) {
+                    return Err(format!(
+                        "{} must be one of: {}.",
+                        self.label,
+                        self.options.join(", ")
+                    ));
+                }
+                raw
+            }
+        };
+        Ok(Some(value))
+    }
+}
+
+pub fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && key.len() <= 64
+        && !RESERVED_KEYS.contains(&key)
+}
+
+/// Validates the submitted values of every custom field, keyed by field key.
+/// Keys that aren't custom fields are ignored.
+pub fn parse_field_values(
+    fields: &[CustomField],
+    input: &HashMap<String, serde_json::Value>,
+) -> Result<Vec<(Uuid, String)>, String> {
+    let mut values = Vec::new();
+    for field in fields {
+        let value = match input.get(&field.key) {
+            Some(value) => field.parse_value(value)?,
+            None => None,
+        };
+        match value {
+            Some(value) => values.push((field.field_id, value)),
+            None if field.required => return Err(format!("{} is required.", field.label)),
+            None => {}
+        }
+    }
+    Ok(values)
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_custom_fields(
+    executor: impl PgExecutor<'_>,
+) -> Result<Vec<CustomField>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT field_id, key, label, field_type, options, required
+        FROM custom_fields
+        ORDER BY created_at, key
+        "#
+    )
+    .fetch_all(executor)
+    .await?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(CustomField {
+                field_id: r.field_id,
+                key: r.key,
+                label: r.label,
+                field_type: FieldType::try_from(r.field_type).map_err(anyhow::Error::msg)?,
+                options: r.options,
+                required: r.required,
+            })
+        })
+        .collect()
+}
+
+#[tracing::instrument(skip(executor, values))]
+pub async fn set_field_values(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    values: &[(Uuid, String)],
+) -> Result<(), sqlx::Error> {
+    let (field_ids, values): (Vec<Uuid>, Vec<String>) = values.iter().cloned().unzip();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_field_values (subscriber_id, field_id, value)
+        SELECT $1, * FROM UNNEST($2::uuid[], $3::text[])
+        ON CONFLICT (subscriber_id, field_id)
+        DO UPDATE SET value = EXCLUDED.value, updated_at = now()
+        "#,
+        subscriber_id,
+        &field_ids,
+        &values
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Values available to merge tags: the subscriber's name, email and custom
+/// fields.
+#[tracing::instrument(skip(executor))]
+pub async fn get_merge_values(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<HashMap<String, String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT s.name, f.key as "key?", v.value as "value?"
+        FROM subscriptions s
+        LEFT JOIN subscriber_field_values v ON v.subscriber_id = s.id
+        LEFT JOIN custom_fields f ON f.field_id = v.field_id
+        WHERE s.email = $1
+        "#,
+        email
+    )
+    .fetch_all(executor)
+    .await?;
+    let mut values = HashMap::from([("email".to_string(), email.to_string())]);
+    for row in rows {
+        values.insert("name".into(), row.name);
+        if let (Some(key), Some(value)) = (row.key, row.value) {
+            values.insert(key, value);
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok_eq};
+    use serde_json::json;
+
+    fn field(field_type: FieldType) -> CustomField {
+        CustomField {
+            field_id: Uuid::new_v4(),
+            key: "field".into(),
+            label: "Field".into(),
+            field_type,
+            options: vec!["free".into(), "pro".into()],
+            required: false,
+        }
+    }
+
+    #[test]
+    fn numbers_are_accepted_as_json_numbers_or_strings() {
+        let field = field(FieldType::Number);
+        assert_ok_eq!(field.parse_value(&json!(42)), Some("42".to_string()));
+        assert_ok_eq!(field.parse_value(&json!(" 4.50 ")), Some("4.5".to_string()));
+        assert_err!(field.parse_value(&json!("forty two")));
+        assert_err!(field.parse_value(&json!("NaN")));
+    }
+
+    #[test]
+    fn dates_must_be_iso_formatted() {
+        let field = field(FieldType::Date);
+        assert_ok_eq!(
+            field.parse_value(&json!("2024-02-29")),
+            Some("2024-02-29".to_string())
+        );
+        assert_err!(field.parse_value(&json!("2023-02-29")));
+        assert_err!(field.parse_value(&json!("29/02/2024")));
+    }
+
+    #[test]
+    fn choices_must_be_one_of_the_options() {
+        let field = field(FieldType::Choice);
+        assert_ok_eq!(field.parse_value(&json!("pro")), Some("pro".to_string()));
+        assert_err!(field.parse_value(&json!("enterprise")));
+    }
+
+    #[test]
+    fn empty_values_are_only_rejected_for_required_fields() {
+        let mut fields = vec![field(FieldType::Text)];
+        let input = HashMap::from([("field".to_string(), json!(""))]);
+        assert_ok_eq!(parse_field_values(&fields, &input), vec![]);
+        fields[0].required = true;
+        assert_err!(parse_field_values(&fields, &input));
+    }
+
+    #[test]
+    fn keys_are_snake_case_and_not_reserved() {
+        assert!(is_valid_key("company_size"));
+        for key in ["", "1st", "Company", "company-size", "email"] {
+            assert!(!is_valid_key(key), "{key:?} should be rejected");
+        }
+    }
+}
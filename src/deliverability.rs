@@ -0,0 +1,464 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::TokioAsyncResolver;
+use sqlx::PgPool;
+
+use crate::domain::SubscriberEmail;
+
+/// Popular mailbox providers. Domains a single typo away from one of these
+/// get a "did you mean" suggestion.
+const COMMON_DOMAINS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "googlemail.com",
+    "hotmail.co.uk",
+    "hotmail.com",
+    "hotmail.fr",
+    "icloud.com",
+    "live.com",
+    "mac.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "web.de",
+    "yahoo.co.uk",
+    "yahoo.com",
+    "yahoo.fr",
+    "yandex.ru",
+    "ymail.com",
+];
+
+/// Real providers that are only a typo away from one of `COMMON_DOMAINS`,
+/// and must not be "corrected" into it.
+const KNOWN_DOMAINS: &[&str] = &[
+    "email.com",
+    "gmx.at",
+    "gmx.ch",
+    "gmx.net",
+    "hotmail.co.jp",
+    "hotmail.de",
+    "hotmail.es",
+    "hotmail.it",
+    "live.co.uk",
+    "live.de",
+    "live.fr",
+    "mail.ru",
+    "outlook.de",
+    "outlook.fr",
+    "pm.me",
+    "protonmail.ch",
+    "yahoo.ca",
+    "yahoo.co.jp",
+    "yahoo.de",
+    "yahoo.es",
+    "yahoo.it",
+    "yandex.com",
+];
+
+/// How long a rejected suggestion is remembered, so that submitting the
+/// same address again gets it through.
+const SUGGESTION_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+/// Finds out whether a domain can receive email.
+pub trait DomainResolver: Send + Sync {
+    /// Whether the domain has an MX record or, failing that, an A or AAAA
+    /// record to deliver to.
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, anyhow::Error>>;
+}
+
+/// Signup-time checks on top of the syntax checks of `SubscriberEmail`.
+pub struct DeliverabilityChecker {
+    /// `None` when domains are not looked up.
+    resolver: Option<Arc<dyn DomainResolver>>,
+    blocked_domains: HashSet<String>,
+    suggest_corrections: bool,
+}
+
+impl DeliverabilityChecker {
+    pub fn new(
+        resolver: Option<Arc<dyn DomainResolver>>,
+        blocked_domains: &[String],
+        suggest_corrections: bool,
+    ) -> Self {
+        Self {
+            resolver,
+            blocked_domains: blocked_domains
+                .iter()
+                .map(|d| d.trim().to_lowercase())
+                .collect(),
+            suggest_corrections,
+        }
+    }
+
+    /// Returns a message for the subscriber when the address should not be
+    /// accepted. Lookups that fail let the address through, so an outage
+    /// of the resolver does not block signups.
+    ///
+    /// Suggestions are advisory: an address is only sent back once, and
+    /// goes through when it is submitted again unchanged.
+    #[tracing::instrument(name = "Checking the email domain", skip(self, pool))]
+    pub async fn check(&self, pool: &PgPool, email: &SubscriberEmail) -> Result<(), String> {
+        let (local, domain) = email.as_ref().rsplit_once('@').unwrap_or_default();
+        if self.is_blocked(domain) {
+            return Err(format!(
+                "Addresses at {} are disposable. Please use a permanent email address.",
+                domain
+            ));
+        }
+        let suggestion = self
+            .suggest_corrections
+            .then(|| suggest_domain(domain))
+            .flatten();
+        if let Some(suggestion) = suggestion {
+            let suggest = suggest_once(pool, email).await.unwrap_or_else(|e| {
+                tracing::warn!(error.cause_chain = ?e, "Failed to remember a typo suggestion");
+                false
+            });
+            if suggest {
+                return Err(format!(
+                    "{} looks like a typo. Did you mean {}@{}? If your address is right, submit it again.",
+                    email, local, suggestion
+                ));
+            }
+        }
+        let Some(resolver) = &self.resolver else {
+            return Ok(());
+        };
+        match resolver.accepts_mail(domain).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("{} does not accept email.", domain)),
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "Failed to look up an email domain");
+                Ok(())
+            }
+        }
+    }
+
+    fn is_blocked(&self, domain: &str) -> bool {
+        let mut candidate = domain;
+        loop {
+            if self.blocked_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) => candidate = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Whether the address has not been sent back with a suggestion lately.
+/// Either way, the next submission of it is let through. Kept in the
+/// database so that it holds whichever instance gets the resubmission.
+async fn suggest_once(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, anyhow::Error> {
+    let address = email.as_ref().to_lowercase();
+    let previous = sqlx::query!(
+        r#"DELETE FROM typo_suggestions WHERE address = $1 RETURNING expires_at"#,
+        address
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up an earlier typo suggestion")?;
+    if previous.is_some_and(|p| p.expires_at > Utc::now()) {
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO typo_suggestions (address, expires_at) VALUES ($1, $2)
+        ON CONFLICT (address) DO UPDATE SET expires_at = EXCLUDED.expires_at
+        "#,
+        address,
+        Utc::now() + SUGGESTION_TTL
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a typo suggestion")?;
+    Ok(true)
+}
+
+/// The common provider the domain is most likely a typo of, if any.
+fn suggest_domain(domain: &str) -> Option<&'static str> {
+    if COMMON_DOMAINS.contains(&domain) || KNOWN_DOMAINS.contains(&domain) {
+        return None;
+    }
+    let max_distance = if domain.len() > 10 { 2 } else { 1 };
+    COMMON_DOMAINS
+        .iter()
+        .map(|candidate| (edit_distance(domain, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent characters each count as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Answers from a fixed list of domains, for tests and offline installs.
+pub struct StaticResolver {
+    domains: HashSet<String>,
+}
+
+impl StaticResolver {
+    pub fn new(domains: &[String]) -> Self {
+        Self {
+            domains: domains.iter().map(|d| d.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl DomainResolver for StaticResolver {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move { Ok(self.domains.contains(domain)) })
+    }
+}
+
+/// Looks domains up with hickory, which retries over TCP when an answer
+/// does not fit in a UDP packet and moves on to the next nameserver when
+/// one does not answer.
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsResolver {
+    /// Without a nameserver, uses the ones from /etc/resolv.conf.
+    pub fn new(nameserver: Option<SocketAddr>, timeout: Duration) -> Result<Self, anyhow::Error> {
+        let (config, mut options) = match nameserver {
+            Some(nameserver) => (
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(
+                        &[nameserver.ip()],
+                        nameserver.port(),
+                        true,
+                    ),
+                ),
+                ResolverOpts::default(),
+            ),
+            None => hickory_resolver::system_conf::read_system_conf()
+                .context("Failed to read the system resolver configuration")?,
+        };
+        options.timeout = timeout;
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+        })
+    }
+}
+
+impl DomainResolver for DnsResolver {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move {
+            // Fully qualified, so the search domains are not tried.
+            let name = format!("{}.", domain.trim_end_matches('.'));
+            match self.resolver.mx_lookup(name.as_str()).await {
+                Ok(records) if records.iter().next().is_some() => return Ok(true),
+                Ok(_) => {}
+                Err(e) if is_missing_domain(&e) => return Ok(false),
+                Err(e) if is_missing_records(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+            match self.resolver.lookup_ip(name.as_str()).await {
+                Ok(addresses) => Ok(addresses.iter().next().is_some()),
+                Err(e) if is_missing_records(&e) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+fn is_missing_records(error: &ResolveError) -> bool {
+    matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+fn is_missing_domain(error: &ResolveError) -> bool {
+    matches!(
+        error.kind(),
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NXDomain,
+            ..
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hickory_resolver::proto::op::{Message, MessageType};
+    use hickory_resolver::proto::rr::{rdata::A, RData, Record, RecordType};
+    use hickory_resolver::proto::serialize::binary::BinEncodable;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use sqlx::PgPool;
+
+    use super::{
+        edit_distance, suggest_domain, DeliverabilityChecker, DnsResolver, DomainResolver,
+        StaticResolver,
+    };
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    fn email(address: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(address.to_string()).unwrap()
+    }
+
+    /// Never connects: only typo suggestions need the database.
+    fn unused_pool() -> PgPool {
+        PgPoolOptions::new().connect_lazy_with(PgConnectOptions::new())
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_as_one_edit() {
+        assert_eq!(edit_distance("gmail.com", "gmail.com"), 0);
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gmail.con", "gmail.com"), 1);
+        assert_eq!(edit_distance("hotmal.co", "hotmail.com"), 2);
+    }
+
+    #[test]
+    fn typos_of_common_domains_get_a_suggestion() {
+        assert_eq!(suggest_domain("gmial.com"), Some("gmail.com"));
+        assert_eq!(suggest_domain("yahooo.com"), Some("yahoo.com"));
+        assert_eq!(suggest_domain("outlok.com"), Some("outlook.com"));
+        assert_eq!(suggest_domain("gmail.com"), None);
+        assert_eq!(suggest_domain("ymail.com"), None);
+        assert_eq!(suggest_domain("example.com"), None);
+    }
+
+    #[test]
+    fn real_providers_are_not_taken_for_typos() {
+        for domain in [
+            "protonmail.ch",
+            "yahoo.co.jp",
+            "hotmail.co.jp",
+            "email.com",
+            "gmx.net",
+            "live.fr",
+        ] {
+            assert_eq!(suggest_domain(domain), None, "{}", domain);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_domains_and_their_subdomains_are_rejected() {
+        let pool = unused_pool();
+        let checker = DeliverabilityChecker::new(None, &["Mailinator.com".into()], false);
+        assert_err!(checker.check(&pool, &email("monkey@mailinator.com")).await);
+        assert_err!(
+            checker
+                .check(&pool, &email("monkey@eu.mailinator.com"))
+                .await
+        );
+        assert_ok!(
+            checker
+                .check(&pool, &email("monkey@notmailinator.com"))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn domains_that_do_not_resolve_are_rejected() {
+        let pool = unused_pool();
+        let resolver = Arc::new(StaticResolver::new(&["example.com".into()]));
+        let checker = DeliverabilityChecker::new(Some(resolver), &[], false);
+        assert_ok!(checker.check(&pool, &email("monkey@example.com")).await);
+        assert_err!(checker.check(&pool, &email("monkey@example.invalid")).await);
+    }
+
+    #[tokio::test]
+    async fn the_dns_resolver_falls_back_to_address_records() {
+        let nameserver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = nameserver.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (length, peer) = nameserver.recv_from(&mut buffer).await.unwrap();
+                let query = Message::from_vec(&buffer[..length]).unwrap();
+                let mut reply = Message::new();
+                reply
+                    .set_id(query.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_available(true)
+                    .add_queries(query.queries().to_vec());
+                let question = &query.queries()[0];
+                if question.query_type() == RecordType::A {
+                    reply.add_answer(Record::from_rdata(
+                        question.name().clone(),
+                        60,
+                        RData::A(A::new(127, 0, 0, 1)),
+                    ));
+                }
+                nameserver
+                    .send_to(&reply.to_bytes().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+        let resolver = DnsResolver::new(Some(address), std::time::Duration::from_secs(1)).unwrap();
+
+        assert!(resolver.accepts_mail("example.com").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_dns_resolver_rejects_domains_that_do_not_exist() {
+        let nameserver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = nameserver.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (length, peer) = nameserver.recv_from(&mut buffer).await.unwrap();
+                let query = Message::from_vec(&buffer[..length]).unwrap();
+                let mut reply = Message::error_msg(
+                    query.id(),
+                    query.op_code(),
+                    hickory_resolver::proto::op::ResponseCode::NXDomain,
+                );
+                reply.add_queries(query.queries().to_vec());
+                nameserver
+                    .send_to(&reply.to_bytes().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+        let resolver = DnsResolver::new(Some(address), std::time::Duration::from_secs(1)).unwrap();
+
+        assert!(!resolver.accepts_mail("example.invalid").await.unwrap());
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use regex::Regex;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    custom_fields::get_merge_values,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    merge_tags::{render_html_merge_tags, render_merge_tags},
+    preferences::{add_preferences_footer, get_preferences_token, preferences_url},
+};
+
+pub const DIGEST_INTERVAL_DAYS: i32 = 7;
+
+struct DigestIssue {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+/// Sends a digest to every weekly digest reader whose digest is due.
+#[tracing::instrument(skip_all)]
+pub async fn send_due_digests(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    while try_send_next_digest(pool, email_client, base_url).await? {}
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(subscriber_id = tracing::field::Empty))]
+async fn try_send_next_digest(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, email
+        FROM subscriptions
+        WHERE
+            frequency = 'weekly_digest' AND
+            status = 'confirmed' AND
+            next_digest_at <= now() AND
+            (paused_until IS NULL OR paused_until <= now())
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for due digests")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(false);
+    };
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(subscriber.id));
+    let issues = sqlx::query_as!(
+        DigestIssue,
+        r#"
+        SELECT i.title, i.html_content, i.text_content
+        FROM digest_items d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_id = $1 AND i.delivery_status <> 'cancelled'
+        ORDER BY i.published_at
+        "#,
+        subscriber.id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let mut next_digest_in_hours = DIGEST_INTERVAL_DAYS * 24;
+    if !issues.is_empty() {
+        let merge_values = get_merge_values(&mut *transaction, &subscriber.email).await?;
+        let (subject, html_content, text_content) = compose_digest(&issues, |template, html| {
+            if html {
+                render_html_merge_tags(template, &merge_values)
+            } else {
+                render_merge_tags(template, &merge_values)
+            }
+        });
+        let (html_content, text_content) =
+            match get_preferences_token(&mut transaction, &subscriber.email).await? {
+                Some(token) => add_preferences_footer(
+                    &html_content,
+                    &text_content,
+                    &preferences_url(base_url, &token),
+                ),
+                None => (html_content, text_content),
+            };
+        let sent = match SubscriberEmail::parse(subscriber.email) {
+            Ok(email) => email_client
+                .send_email(&email, &subject, &html_content, &text_content)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(anyhow::anyhow!(e)),
+        };
+        if let Err(e) = sent {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a weekly digest. Retrying in an hour."
+            );
+            next_digest_in_hours = 1;
+        } else {
+            clear_digest(&mut transaction, subscriber.id).await?;
+        }
+    }
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET next_digest_at = now() + make_interval(hours => $2)
+        WHERE id = $1
+        "#,
+        subscriber.id,
+        next_digest_in_hours
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+async fn clear_digest(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM digest_items WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+fn body_regex() -> &'static Regex {
+    static BODY: OnceLock<Regex> = OnceLock::new();
+    BODY.get_or_init(|| Regex::new(r"(?is)<body[^>]*>(.*)</body>").expect("Invalid body regex"))
+}
+
+/// Issues are concatenated under their title. Only the `<body>` of full HTML
+/// documents is kept, so the digest stays a single document.
+fn compose_digest(
+    issues: &[DigestIssue],
+    render: impl Fn(&str, bool) -> String,
+) -> (String, String, String) {
+    let subject = format!("Your weekly digest: {} new issue(s)", issues.len());
+    let mut html_content = String::from("<html><body>");
+    let mut text_content = String::new();
+    for issue in issues {
+        let body = body_regex()
+            .captures(&issue.html_content)
+            .and_then(|c| c.get(1))
+            .map_or(issue.html_content.as_str(), |m| m.as_str());
+        html_content.push_str(&format!(
+            "<h1>{}</h1>{}<hr>",
+            htmlescape::encode_minimal(&render(&issue.title, false)),
+            render(body, true)
+        ));
+        text_content.push_str(&format!(
+            "{}\n\n{}\n\n",
+            render(&issue.title, false),
+            render(&issue.text_content, false)
+        ));
+    }
+    html_content.push_str("</body></html>");
+    (subject, html_content, text_content.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issues_are_combined_into_one_document() {
+        let issues = [
+            DigestIssue {
+                title: "First".into(),
+                html_content: "<html><body><p>One</p></body></html>".into(),
+                text_content: "One".into(),
+            },
+            DigestIssue {
+                title: "Second".into(),
+                html_content: "<p>Two</p>".into(),
+                text_content: "Two".into(),
+            },
+        ];
+        let (subject, html, text) = compose_digest(&issues, |s, _| s.to_string());
+        assert_eq!(subject, "Your weekly digest: 2 new issue(s)");
+        assert_eq!(
+            html,
+            "<html><body><h1>First</h1><p>One</p><hr><h1>Second</h1><p>Two</p><hr></body></html>"
+        );
+        assert_eq!(text, "First\n\nOne\n\nSecond\n\nTwo");
+    }
+}
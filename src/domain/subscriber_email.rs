@@ -3,11 +3,38 @@ use validator::ValidateEmail;
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
+    /// Trims the address and stores its domain lowercased and, for
+    /// internationalised domains, punycode encoded. The local part is kept
+    /// as typed, since that is where the mail has to go.
     pub fn parse(email: String) -> Result<SubscriberEmail, String> {
-        if !ValidateEmail::validate_email(&email) {
-            return Err(format!("{} is not a valid subscriber email.", email));
+        let invalid = || format!("{} is not a valid subscriber email.", email);
+        let trimmed = email.trim();
+        let (local, domain) = trimmed.rsplit_once('@').ok_or_else(invalid)?;
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        let normalised = format!("{}@{}", local, domain);
+        if !ValidateEmail::validate_email(&normalised) {
+            return Err(invalid());
         }
-        Ok(Self(email))
+        Ok(Self(normalised))
+    }
+
+    /// The form used to tell whether two addresses belong to the same
+    /// person: the whole address lowercased and, for the domains listed in
+    /// `alias_folding_domains`, with dots and `+tag` suffixes dropped from
+    /// the local part.
+    pub fn key(&self, alias_folding_domains: &[String]) -> String {
+        let (local, domain) = self.0.rsplit_once('@').unwrap_or(("", &self.0));
+        let mut local = local.to_lowercase();
+        if alias_folding_domains
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(domain))
+        {
+            if let Some((base, _tag)) = local.split_once('+') {
+                local = base.to_string();
+            }
+            local.retain(|c| c != '.');
+        }
+        format!("{}@{}", local, domain)
     }
 }
 
@@ -57,6 +84,35 @@ mod tests {
         assert_err!(SubscriberEmail::parse("@test.com".to_string()));
     }
 
+    #[test]
+    fn email_is_trimmed_and_the_domain_lowercased() {
+        let email = SubscriberEmail::parse("  Monkey.Face@Example.COM \n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Monkey.Face@example.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_punycode_encoded() {
+        let email = SubscriberEmail::parse("monkey@BÜCHER.example".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "monkey@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn keys_ignore_case() {
+        let a = SubscriberEmail::parse("Monkey@example.com".to_string()).unwrap();
+        let b = SubscriberEmail::parse("monkey@EXAMPLE.com".to_string()).unwrap();
+        assert_eq!(a.key(&[]), b.key(&[]));
+    }
+
+    #[test]
+    fn aliases_are_only_folded_for_configured_domains() {
+        let folding = vec!["gmail.com".to_string()];
+        let gmail = SubscriberEmail::parse("Monkey.Face+news@gmail.com".to_string()).unwrap();
+        assert_eq!(gmail.key(&folding), "monkeyface@gmail.com");
+        assert_eq!(gmail.key(&[]), "monkey.face+news@gmail.com");
+        let other = SubscriberEmail::parse("monkey.face+news@example.com".to_string()).unwrap();
+        assert_eq!(other.key(&folding), "monkey.face+news@example.com");
+    }
+
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_passed(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
@@ -1,12 +1,22 @@
 use std::time::Duration;
 
+use secrecy::Secret;
 use sqlx::{Executor, PgPool, Postgres, Row, Transaction};
 use tracing::{field::display, Span};
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::Settings,
+    custom_fields::get_merge_values,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    merge_tags::{render_html_merge_tags, render_merge_tags},
+    preferences::{add_preferences_footer, get_preferences_token, preferences_url},
     startup::get_connection_pool,
+    tracking::{
+        generate_tracking_token, inject_open_pixel, rewrite_links, store_tracking_token,
+        UtmParameters,
+    },
 };
 
 pub enum ExecutionOutcome {
@@ -14,36 +24,79 @@ pub enum ExecutionOutcome {
     EmptyQueue,
 }
 
+enum DeliveryOutcome {
+    Delivered,
+    Failed,
+    /// The subscriber stopped receiving issues after this one was queued.
+    Skipped,
+}
+
 #[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty), err)]
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(pool).await?;
     if task.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
+    let (mut transaction, issue_id, email, subject_variant, still_subscribed) = task.unwrap();
     Span::current()
         .record("newsletter_issue_id", &display(issue_id))
         .record("subscriber_email", &display(&email));
-    match SubscriberEmail::parse(email.clone()) {
+    let outcome = match SubscriberEmail::parse(email.clone()) {
+        Ok(_) if !still_subscribed => DeliveryOutcome::Skipped,
         Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
+            let issue = get_issue(pool, issue_id, subject_variant).await?;
+            let merge_values = get_merge_values(pool, email.as_ref()).await?;
+            let subject = render_merge_tags(&issue.subject, &merge_values);
+            let mut html_content = render_html_merge_tags(&issue.html_content, &merge_values);
+            let mut text_content = render_merge_tags(&issue.text_content, &merge_values);
+            if let Some(utm) = &issue.utm {
+                html_content = utm.tag_html_links(&html_content);
+                text_content = utm.tag_text_links(&text_content);
+            }
+            if issue.track_opens || issue.track_clicks {
+                let tracking_token = generate_tracking_token();
+                store_tracking_token(
+                    &mut transaction,
+                    &tracking_token,
+                    issue_id,
+                    email.as_ref(),
+                    subject_variant,
                 )
+                .await?;
+                if issue.track_clicks {
+                    html_content =
+                        rewrite_links(&html_content, base_url, &tracking_token, hmac_secret);
+                }
+                if issue.track_opens {
+                    html_content = inject_open_pixel(&html_content, base_url, &tracking_token);
+                }
+            }
+            // Added last, so the link is neither tagged nor tracked.
+            if let Some(token) = get_preferences_token(&mut transaction, email.as_ref()).await? {
+                (html_content, text_content) = add_preferences_footer(
+                    &html_content,
+                    &text_content,
+                    &preferences_url(base_url, &token),
+                );
+            }
+            match email_client
+                .send_email(&email, &subject, &html_content, &text_content)
                 .await
             {
-                tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to confirmed subscriber. Skipping."
-                )
+                Ok(()) => DeliveryOutcome::Delivered,
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issue to confirmed subscriber. Skipping."
+                    );
+                    DeliveryOutcome::Failed
+                }
             }
         }
         Err(e) => {
@@ -51,26 +104,49 @@ pub async fn try_execute_task(
                 error.cause_chain = ?e,
                 error.message = %e,
             "Skipping a confirmed subscriber. Their stored contact details are incorect"
-            )
+            );
+            DeliveryOutcome::Failed
         }
-    }
-    delete_task(transaction, issue_id, &email).await?;
+    };
+    delete_task(transaction, issue_id, &email, subject_variant, outcome).await?;
 
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+type Task = (
+    Transaction<'static, Postgres>,
+    Uuid,
+    String,
+    Option<i16>,
+    bool,
+);
+
+// Subscribers can unsubscribe, pause or switch to the digest while their
+// delivery waits in the queue, so whether they still get the issue is checked
+// again when it is sent.
 #[tracing::instrument(skip_all)]
-async fn dequeue_task(
-    pool: &PgPool,
-) -> Result<Option<(Transaction<'static, Postgres>, Uuid, String)>, anyhow::Error> {
+async fn dequeue_task(pool: &PgPool) -> Result<Option<Task>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
 
     let res = transaction
         .fetch_optional(sqlx::query!(
             r#"
-        SELECT newsletter_issue_id, subscriber_email
-        FROM issue_delivery_queue
-        FOR UPDATE
+        SELECT
+            q.newsletter_issue_id, q.subscriber_email, q.subject_variant,
+            EXISTS (
+                SELECT 1 FROM subscriptions s
+                JOIN list_subscriptions l ON l.subscriber_id = s.id AND l.list_id = i.list_id
+                WHERE
+                    (s.email = q.subscriber_email OR s.email_key = lower(q.subscriber_email)) AND
+                    s.status = 'confirmed' AND
+                    l.status = 'confirmed' AND
+                    (s.paused_until IS NULL OR s.paused_until <= now()) AND
+                    s.frequency = 'every_issue'
+            ) as still_subscribed
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE i.delivery_status = 'in_progress' AND NOT q.held
+        FOR UPDATE OF q
         SKIP LOCKED
         LIMIT 1
         "#
@@ -80,7 +156,15 @@ async fn dequeue_task(
     if let Some(r) = res {
         let issue_id: Uuid = r.try_get("newsletter_issue_id")?;
         let email: String = r.try_get("subscriber_email")?;
-        Ok(Some((transaction, issue_id, email)))
+        let subject_variant: Option<i16> = r.try_get("subject_variant")?;
+        let still_subscribed: bool = r.try_get("still_subscribed")?;
+        Ok(Some((
+            transaction,
+            issue_id,
+            email,
+            subject_variant,
+            still_subscribed,
+        )))
     } else {
         Ok(None)
     }
@@ -91,6 +175,8 @@ async fn delete_task(
     mut transaction: Transaction<'static, Postgres>,
     issue_id: Uuid,
     email: &str,
+    subject_variant: Option<i16>,
+    outcome: DeliveryOutcome,
 ) -> Result<(), anyhow::Error> {
     transaction
         .execute(sqlx::query!(
@@ -104,33 +190,132 @@ async fn delete_task(
             email
         ))
         .await?;
+    record_delivery_outcome(&mut transaction, issue_id, subject_variant, outcome).await?;
     transaction.commit().await?;
     Ok(())
 }
 
+// Once the last task of an issue is gone the issue is marked as completed, so
+// the admin pages can tell a finished delivery apart from one still running.
+// The issue row is locked first: workers deleting the last tasks in parallel
+// then check for leftovers one after the other, each seeing the deletions the
+// others committed, so one of them always sees an empty queue.
+#[tracing::instrument(skip_all)]
+async fn record_delivery_outcome(
+    transaction: &mut Transaction<'static, Postgres>,
+    issue_id: Uuid,
+    subject_variant: Option<i16>,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    if let (Some(variant), DeliveryOutcome::Delivered) = (subject_variant, &outcome) {
+        transaction
+            .execute(sqlx::query!(
+                r#"
+        UPDATE issue_subject_variants
+        SET sent_count = sent_count + 1
+        WHERE newsletter_issue_id = $1 AND variant = $2
+        "#,
+                issue_id,
+                variant
+            ))
+            .await?;
+    }
+    transaction
+        .execute(sqlx::query!(
+            r#"SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1 FOR UPDATE"#,
+            issue_id
+        ))
+        .await?;
+    let (delivered, undelivered) = match outcome {
+        DeliveryOutcome::Delivered => (1, 0),
+        DeliveryOutcome::Failed => (0, 1),
+        DeliveryOutcome::Skipped => (0, 0),
+    };
+    transaction
+        .execute(sqlx::query!(
+            r#"
+        UPDATE newsletter_issues
+        SET
+            delivered_count = delivered_count + $2,
+            undelivered_count = undelivered_count + $3,
+            delivery_status = CASE
+                WHEN delivery_status = 'in_progress' AND NOT EXISTS (
+                    SELECT 1 FROM issue_delivery_queue WHERE newsletter_issue_id = $1
+                ) THEN 'completed'
+                ELSE delivery_status
+            END
+        WHERE newsletter_issue_id = $1
+        "#,
+            issue_id,
+            delivered,
+            undelivered
+        ))
+        .await?;
+    Ok(())
+}
+
 struct NewsletterIssue {
-    title: String,
+    subject: String,
     text_content: String,
     html_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+    utm: Option<UtmParameters>,
 }
 
+// Subscribers that are part of an A/B test sample get their own subject
+// variant, the rest of the audience gets the winning one - if any.
 #[tracing::instrument(skip_all)]
-async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
-    let issue = sqlx::query_as!(
-        NewsletterIssue,
-        r#"SELECT title, text_content, html_content 
-    FROM newsletter_issues 
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subject_variant: Option<i16>,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"SELECT
+        COALESCE(
+            (
+                SELECT subject FROM issue_subject_variants v
+                WHERE v.newsletter_issue_id = i.newsletter_issue_id
+                AND v.variant = COALESCE($2, i.ab_winner)
+            ),
+            title
+        ) as "subject!",
+        text_content, html_content, track_opens, track_clicks,
+        utm_tagging, utm_source, utm_medium, utm_campaign
+    FROM newsletter_issues i
     WHERE newsletter_issue_id = $1"#,
-        issue_id
+        issue_id,
+        subject_variant
     )
     .fetch_one(pool)
     .await?;
-    Ok(issue)
+    let utm = match (r.utm_tagging, r.utm_source, r.utm_medium, r.utm_campaign) {
+        (true, Some(source), Some(medium), Some(campaign)) => Some(UtmParameters {
+            source,
+            medium,
+            campaign,
+        }),
+        _ => None,
+    };
+    Ok(NewsletterIssue {
+        subject: r.subject,
+        text_content: r.text_content,
+        html_content: r.html_content,
+        track_opens: r.track_opens,
+        track_clicks: r.track_clicks,
+        utm,
+    })
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        match try_execute_task(&pool, &email_client, &base_url, &hmac_secret).await {
             Ok(ExecutionOutcome::TaskCompleted) => {}
             Ok(ExecutionOutcome::EmptyQueue) => {
                 tokio::time::sleep(Duration::from_secs(10)).await;
@@ -145,5 +330,11 @@ async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyh
 pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
     let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+    )
+    .await
 }
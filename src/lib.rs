@@ -1,11 +1,33 @@
+pub mod ab_test;
 pub mod authentication;
+pub mod bot_protection;
+pub mod client_ip;
 pub mod configuration;
+pub mod confirmation_reminders;
+pub mod consent;
+pub mod custom_fields;
+pub mod deliverability;
+pub mod digest;
 pub mod domain;
 pub mod email_client;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod lists;
+pub mod merge_tags;
+pub mod outbox;
+pub mod preferences;
+pub mod privacy;
+pub mod rate_limit;
 pub mod routes;
+pub mod scheduled_sweeps;
+pub mod segments;
 pub mod session_state;
+pub mod signup_cleanup;
 pub mod startup;
+pub mod subscriber_import;
+pub mod subscriber_keys;
+pub mod tags;
 pub mod telemetry;
+pub mod tracking;
 pub mod utils;
+pub mod welcome_series;
@@ -0,0 +1,136 @@
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// The list that signups without an explicit `list` parameter join.
+pub const DEFAULT_LIST_SLUG: &str = "default";
+
+#[derive(Debug)]
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    /// `None` when the list follows the global setting.
+    pub opt_in: Option<OptIn>,
+}
+
+impl MailingList {
+    pub fn opt_in(&self, global_default: OptIn) -> OptIn {
+        self.opt_in.unwrap_or(global_default)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptIn {
+    /// Subscribers are confirmed as soon as they sign up.
+    Single,
+    /// Subscribers have to click a confirmation link first.
+    Double,
+    /// Confirmed by an administrator on consent given elsewhere, as noted in
+    /// the consent events. Only ever recorded on memberships, lists cannot
+    /// use it.
+    #[serde(skip)]
+    Manual,
+}
+
+impl OptIn {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptIn::Single => "single",
+            OptIn::Double => "double",
+            OptIn::Manual => "manual",
+        }
+    }
+}
+
+impl TryFrom<String> for OptIn {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "single" => Ok(Self::Single),
+            "double" => Ok(Self::Double),
+            other => Err(format!(
+                "{} is not a supported opt-in mode. Use either 'single' or 'double'.",
+                other
+            )),
+        }
+    }
+}
+
+pub fn is_valid_slug(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 64
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+struct ListRow {
+    list_id: Uuid,
+    slug: String,
+    name: String,
+    opt_in: Option<String>,
+}
+
+impl From<ListRow> for MailingList {
+    fn from(row: ListRow) -> Self {
+        Self {
+            list_id: row.list_id,
+            slug: row.slug,
+            name: row.name,
+            // The column is constrained to valid values
+            opt_in: row.opt_in.and_then(|o| OptIn::try_from(o).ok()),
+        }
+    }
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_list_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    let row = sqlx::query_as!(
+        ListRow,
+        r#"SELECT list_id, slug, name, opt_in FROM lists WHERE slug = $1"#,
+        slug
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(MailingList::from))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        ListRow,
+        r#"SELECT list_id, slug, name, opt_in FROM lists ORDER BY created_at, name"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(MailingList::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_slug, OptIn};
+
+    #[test]
+    fn lowercase_words_separated_by_dashes_are_valid() {
+        assert!(is_valid_slug("weekly-digest-2"));
+    }
+
+    #[test]
+    fn empty_uppercase_or_spaced_slugs_are_rejected() {
+        for slug in ["", "Weekly", "weekly digest", "weekly_digest", "caf\u{e9}"] {
+            assert!(!is_valid_slug(slug), "{slug:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn opt_in_modes_round_trip() {
+        for mode in [OptIn::Single, OptIn::Double] {
+            assert_eq!(OptIn::try_from(mode.as_str().to_string()), Ok(mode));
+        }
+        assert!(OptIn::try_from("triple".to_string()).is_err());
+    }
+}
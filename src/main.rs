@@ -3,6 +3,9 @@ use std::fmt::{Debug, Display};
 use monkey_letter::{
     configuration,
     issue_delivery_worker::run_worker_until_stopped,
+    outbox::run_outbox_worker_until_stopped,
+    scheduled_sweeps::run_sweeps_until_stopped,
+    signup_cleanup::run_cleanup_until_stopped,
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -16,10 +19,16 @@ async fn main() -> anyhow::Result<()> {
 
     let server = Application::build(config.clone()).await?;
     let server_task = tokio::spawn(server.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(config));
+    let worker_task = tokio::spawn(run_worker_until_stopped(config.clone()));
+    let outbox_task = tokio::spawn(run_outbox_worker_until_stopped(config.clone()));
+    let sweeps_task = tokio::spawn(run_sweeps_until_stopped(config.clone()));
+    let cleanup_task = tokio::spawn(run_cleanup_until_stopped(config));
     tokio::select! {
         o = server_task => report_exit("API", o),
-        o = worker_task => report_exit("Background_worker", o)
+        o = worker_task => report_exit("Background_worker", o),
+        o = outbox_task => report_exit("Outbox_worker", o),
+        o = sweeps_task => report_exit("Scheduled_sweeps", o),
+        o = cleanup_task => report_exit("Signup_cleanup", o)
     }
     Ok(())
 }
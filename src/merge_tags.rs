@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+fn merge_tag_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"\{\{\s*([a-z][a-z0-9_]*)\s*(?:\|([^}]*))?\}\}")
+            .expect("Invalid merge tag regex")
+    })
+}
+
+/// Replaces `{{ key }}` and `{{ key | fallback }}` merge tags with the
+/// recipient's values. Missing values fall back to the fallback, or to an
+/// empty string.
+pub fn render_merge_tags(template: &str, values: &HashMap<String, String>) -> String {
+    render(template, values, |s| s.to_string())
+}
+
+/// Same as [`render_merge_tags`], HTML-escaping the inserted values. They
+/// are escaped for attributes too, as tags can be used inside one.
+pub fn render_html_merge_tags(template: &str, values: &HashMap<String, String>) -> String {
+    render(template, values, htmlescape::encode_attribute)
+}
+
+fn render(
+    template: &str,
+    values: &HashMap<String, String>,
+    escape: impl Fn(&str) -> String,
+) -> String {
+    merge_tag_regex()
+        .replace_all(template, |caps: &Captures| {
+            let value = values
+                .get(&caps[1])
+                .filter(|v| !v.is_empty())
+                .map(String::as_str)
+                .or_else(|| caps.get(2).map(|fallback| fallback.as_str().trim()))
+                .unwrap_or_default();
+            escape(value)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> HashMap<String, String> {
+        HashMap::from([
+            ("name".to_string(), "Ursula <Le Guin>".to_string()),
+            ("plan".to_string(), "pro".to_string()),
+        ])
+    }
+
+    #[test]
+    fn known_tags_are_replaced() {
+        assert_eq!(
+            render_merge_tags("Hi {{name}}, you are on {{ plan }}.", &values()),
+            "Hi Ursula <Le Guin>, you are on pro."
+        );
+    }
+
+    #[test]
+    fn missing_values_use_the_fallback_or_nothing() {
+        assert_eq!(
+            render_merge_tags("{{ company | your company }}/{{ city }}", &values()),
+            "your company/"
+        );
+    }
+
+    #[test]
+    fn html_values_are_escaped() {
+        assert_eq!(
+            render_html_merge_tags("<p>{{name}}</p>", &values()),
+            "<p>Ursula&#x20;&lt;Le&#x20;Guin&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn html_values_cannot_break_out_of_attributes() {
+        let values = HashMap::from([(
+            "company".to_string(),
+            r#"x" onmouseover="alert(1)"#.to_string(),
+        )]);
+        assert_eq!(
+            render_html_merge_tags(r#"<a title="{{ company }}">"#, &values),
+            r#"<a title="x&quot;&#x20;onmouseover&#x3D;&quot;alert&#x28;1&#x29;">"#
+        );
+    }
+
+    #[test]
+    fn text_that_is_not_a_merge_tag_is_left_alone() {
+        let template = "{{ Not A Tag }} and {single}";
+        assert_eq!(render_merge_tags(template, &values()), template);
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    startup::get_connection_pool,
+};
+
+/// Messages that still fail after this many attempts are left in the outbox
+/// with their last error, for someone to look at.
+pub const MAX_ATTEMPTS: i16 = 6;
+
+pub struct OutboxMessage<'a> {
+    pub kind: &'a str,
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+}
+
+/// Writes an email to the outbox. Use the transaction of the change that
+/// triggers the email, so neither can exist without the other.
+#[tracing::instrument(skip_all, fields(kind = %message.kind))]
+pub async fn enqueue_email(
+    executor: impl PgExecutor<'_>,
+    message: OutboxMessage<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let message_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO outbox (message_id, kind, recipient, subject, html_content, text_content)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        message_id,
+        message.kind,
+        message.recipient.as_ref(),
+        message.subject,
+        message.html_content,
+        message.text_content
+    )
+    .execute(executor)
+    .await?;
+    Ok(message_id)
+}
+
+/// Delivers every message that is due. Failed messages are retried later,
+/// with an exponential backoff.
+#[tracing::instrument(skip_all)]
+pub async fn process_outbox(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<(), anyhow::Error> {
+    while try_send_next_message(pool, email_client).await? {}
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(message_id = tracing::field::Empty))]
+async fn try_send_next_message(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let message = sqlx::query!(
+        r#"
+        SELECT message_id, recipient, subject, html_content, text_content, attempts
+        FROM outbox
+        WHERE sent_at IS NULL AND attempts < $1 AND next_attempt_at <= now()
+        ORDER BY next_attempt_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        MAX_ATTEMPTS
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for outbox messages")?;
+    let Some(message) = message else {
+        return Ok(false);
+    };
+    tracing::Span::current().record("message_id", &tracing::field::display(message.message_id));
+    let sent = match SubscriberEmail::parse(message.recipient) {
+        Ok(recipient) => email_client
+            .send_email(
+                &recipient,
+                &message.subject,
+                &message.html_content,
+                &message.text_content,
+            )
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(anyhow::anyhow!(e)),
+    };
+    match sent {
+        Ok(()) => {
+            sqlx::query!(
+                r#"UPDATE outbox SET sent_at = now() WHERE message_id = $1"#,
+                message.message_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                attempts = message.attempts + 1,
+                "Failed to deliver an outbox message"
+            );
+            sqlx::query!(
+                r#"
+                UPDATE outbox
+                SET
+                    attempts = attempts + 1,
+                    next_attempt_at = now() + make_interval(mins => $2),
+                    last_error = $3
+                WHERE message_id = $1
+                "#,
+                message.message_id,
+                retry_delay_minutes(message.attempts),
+                e.to_string()
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+/// 1, 2, 4, 8... minutes after each failed attempt.
+fn retry_delay_minutes(previous_attempts: i16) -> i32 {
+    1 << previous_attempts.clamp(0, 10)
+}
+
+async fn outbox_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+    loop {
+        if let Err(e) = process_outbox(&pool, &email_client).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to process the outbox"
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+pub async fn run_outbox_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    outbox_loop(connection_pool, email_client).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::retry_delay_minutes;
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let delays: Vec<_> = (0..5).map(retry_delay_minutes).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16]);
+    }
+}
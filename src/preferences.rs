@@ -0,0 +1,199 @@
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{PgConnection, PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{utils::insert_before_body_end, welcome_series::cancel_welcome_series};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    EveryIssue,
+    WeeklyDigest,
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+}
+
+impl TryFrom<String> for Frequency {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "every_issue" => Ok(Self::EveryIssue),
+            "weekly_digest" => Ok(Self::WeeklyDigest),
+            other => Err(format!(
+                "{} is not a supported frequency. Use either 'every_issue' or 'weekly_digest'.",
+                other
+            )),
+        }
+    }
+}
+
+fn generate_preferences_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// Every subscriber has a single, long-lived preferences token. It is created
+/// the first time it is needed.
+#[tracing::instrument(skip(connection))]
+pub async fn get_preferences_token(
+    connection: &mut PgConnection,
+    subscriber_email: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO preference_tokens (preference_token, subscriber_id)
+        SELECT $1, id FROM subscriptions WHERE email = $2
+        ON CONFLICT (subscriber_id) DO NOTHING
+        "#,
+        generate_preferences_token(),
+        subscriber_email
+    )
+    .execute(&mut *connection)
+    .await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT t.preference_token
+        FROM preference_tokens t JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE s.email = $1
+        "#,
+        subscriber_email
+    )
+    .fetch_optional(&mut *connection)
+    .await?;
+    Ok(row.map(|r| r.preference_token))
+}
+
+#[tracing::instrument(skip(executor, preferences_token))]
+pub async fn get_subscriber_id_from_preferences_token(
+    executor: impl PgExecutor<'_>,
+    preferences_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT subscriber_id FROM preference_tokens WHERE preference_token = $1"#,
+        preferences_token
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.subscriber_id))
+}
+
+pub fn preferences_url(base_url: &str, preferences_token: &str) -> String {
+    format!("{}/preferences?token={}", base_url, preferences_token)
+}
+
+/// Adds a "manage your preferences" link at the bottom of an email.
+pub fn add_preferences_footer(
+    html_content: &str,
+    text_content: &str,
+    preferences_url: &str,
+) -> (String, String) {
+    let html_footer = format!(
+        r#"<p><a href="{}">Manage your preferences or unsubscribe</a></p>"#,
+        htmlescape::encode_minimal(preferences_url)
+    );
+    (
+        insert_before_body_end(html_content, &html_footer),
+        format!(
+            "{}\n\n--\nManage your preferences or unsubscribe: {}",
+            text_content, preferences_url
+        ),
+    )
+}
+
+/// Keeps an audit trail of what subscribers change on their preferences page.
+#[tracing::instrument(skip(executor))]
+pub async fn record_preference_change(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    field: &str,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO preference_changes (change_id, subscriber_id, field, old_value, new_value)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        field,
+        old_value,
+        new_value
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Stops every email to the subscriber, on every list.
+#[tracing::instrument(skip(transaction))]
+pub async fn unsubscribe_from_everything(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    current_status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"UPDATE list_subscriptions SET status = 'unsubscribed' WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM digest_items WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    cancel_welcome_series(transaction, subscriber_id).await?;
+    record_preference_change(
+        &mut **transaction,
+        subscriber_id,
+        "status",
+        Some(current_status),
+        Some("unsubscribed"),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_footer_goes_at_the_end_of_both_bodies() {
+        let (html, text) = add_preferences_footer(
+            "<html><body><p>Issue</p></body></html>",
+            "Issue",
+            "http://localhost/preferences?token=abc",
+        );
+        assert_eq!(
+            html,
+            r#"<html><body><p>Issue</p><p><a href="http://localhost/preferences?token=abc">Manage your preferences or unsubscribe</a></p></body></html>"#
+        );
+        assert!(text.starts_with("Issue\n\n--\n"));
+        assert!(text.ends_with("http://localhost/preferences?token=abc"));
+    }
+
+    #[test]
+    fn preferences_tokens_are_long_and_random() {
+        let token = generate_preferences_token();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, generate_preferences_token());
+    }
+}
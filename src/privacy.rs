@@ -0,0 +1,385 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use serde_json::{json, Value};
+use sha3::Sha3_256;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// How long the link sent to a subscriber keeps working.
+pub const PRIVACY_TOKEN_TTL_HOURS: i64 = 24;
+
+fn generate_privacy_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+pub fn privacy_url(base_url: &str, privacy_token: &str) -> String {
+    format!("{}/privacy/manage?token={}", base_url, privacy_token)
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn create_privacy_token(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let token = generate_privacy_token();
+    sqlx::query!(
+        r#"INSERT INTO privacy_tokens (privacy_token, subscriber_id) VALUES ($1, $2)"#,
+        token,
+        subscriber_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+/// Expired tokens are treated as unknown.
+#[tracing::instrument(skip(executor, privacy_token))]
+pub async fn get_subscriber_id_from_privacy_token(
+    executor: impl PgExecutor<'_>,
+    privacy_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT subscriber_id FROM privacy_tokens
+        WHERE privacy_token = $1 AND created_at > $2
+        "#,
+        privacy_token,
+        Utc::now() - chrono::Duration::hours(PRIVACY_TOKEN_TTL_HOURS)
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.subscriber_id))
+}
+
+/// Suppressed addresses are stored hashed, so erasing someone does not leave
+/// their address behind. The hash is taken of the address key (see
+/// `SubscriberEmail::key`), so aliases of the address are suppressed too,
+/// and keyed with the application secret, so it cannot be reversed by
+/// hashing a list of known addresses.
+pub fn suppression_hash(hmac_secret: &Secret<String>, email_key: &str) -> String {
+    let mut mac = Hmac::<Sha3_256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(email_key.trim().to_lowercase().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[tracing::instrument(skip(executor, hmac_secret, email_key))]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    hmac_secret: &Secret<String>,
+    email_key: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email_hash FROM suppressions WHERE email_hash = $1"#,
+        suppression_hash(hmac_secret, email_key)
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.is_some())
+}
+
+fn timestamp(at: Option<DateTime<Utc>>) -> Value {
+    at.map(|at| Value::String(at.to_rfc3339()))
+        .unwrap_or(Value::Null)
+}
+
+/// Everything stored about a subscriber, for access requests.
+#[tracing::instrument(skip(pool))]
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Value, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT email, name, status, subscribed_at, frequency, paused_until
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let email = subscriber.email.clone();
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.slug, ls.status, ls.opt_in, ls.subscribed_at, ls.consented_at
+        FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let tags = sqlx::query!(
+        r#"
+        SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let fields = sqlx::query!(
+        r#"
+        SELECT f.key, v.value FROM subscriber_field_values v
+        JOIN custom_fields f ON f.field_id = v.field_id
+        WHERE v.subscriber_id = $1
+        ORDER BY f.key
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let tokens = sqlx::query!(
+        r#"
+        SELECT l.slug, t.created_at, t.used_at
+        FROM subscription_tokens t JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscriber_id = $1
+        ORDER BY t.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let consent = sqlx::query!(
+        r#"
+        SELECT l.slug as "slug?", c.event_type, c.ip_address, c.user_agent,
+            c.form_id, c.consent_text_version, c.note, c.occurred_at
+        FROM consent_events c LEFT JOIN lists l ON l.list_id = c.list_id
+        WHERE c.subscriber_id = $1
+        ORDER BY c.occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let preference_changes = sqlx::query!(
+        r#"
+        SELECT field, old_value, new_value, changed_at FROM preference_changes
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let pending_deliveries = sqlx::query!(
+        r#"
+        SELECT n.title FROM issue_delivery_queue q
+        JOIN newsletter_issues n ON n.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.subscriber_email = $1
+        ORDER BY n.title
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+    let emails_sent = sqlx::query!(
+        r#"
+        SELECT kind, subject, created_at, sent_at FROM outbox
+        WHERE recipient = $1
+        ORDER BY created_at
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+    let welcome_emails = sqlx::query!(
+        r#"
+        SELECT w.subject, d.sent_at FROM welcome_deliveries d
+        JOIN welcome_steps w ON w.step_id = d.step_id
+        WHERE d.subscriber_id = $1
+        ORDER BY d.sent_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let tracking_events = sqlx::query!(
+        r#"
+        SELECT n.title, e.event_type, e.url, e.first_occurred_at, e.last_occurred_at,
+            e.occurrences
+        FROM email_events e
+        JOIN newsletter_issues n ON n.newsletter_issue_id = e.newsletter_issue_id
+        WHERE e.subscriber_email = $1
+        ORDER BY e.first_occurred_at
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(json!({
+        "subscriber": {
+            "email": subscriber.email,
+            "name": subscriber.name,
+            "status": subscriber.status,
+            "subscribed_at": subscriber.subscribed_at.to_rfc3339(),
+            "frequency": subscriber.frequency,
+            "paused_until": timestamp(subscriber.paused_until),
+        },
+        "lists": lists.into_iter().map(|l| json!({
+            "list": l.slug,
+            "status": l.status,
+            "opt_in": l.opt_in,
+            "subscribed_at": l.subscribed_at.to_rfc3339(),
+            "consented_at": timestamp(l.consented_at),
+        })).collect::<Vec<_>>(),
+        "tags": tags.into_iter().map(|t| t.tag).collect::<Vec<_>>(),
+        "custom_fields": fields.into_iter().map(|f| (f.key, Value::String(f.value))).collect::<serde_json::Map<_, _>>(),
+        "confirmation_tokens": tokens.into_iter().map(|t| json!({
+            "list": t.slug,
+            "created_at": t.created_at.to_rfc3339(),
+            "used_at": timestamp(t.used_at),
+        })).collect::<Vec<_>>(),
+        "consent_events": consent.into_iter().map(|c| json!({
+            "list": c.slug,
+            "event": c.event_type,
+            "ip_address": c.ip_address,
+            "user_agent": c.user_agent,
+            "form_id": c.form_id,
+            "consent_text_version": c.consent_text_version,
+            "note": c.note,
+            "occurred_at": c.occurred_at.to_rfc3339(),
+        })).collect::<Vec<_>>(),
+        "preference_changes": preference_changes.into_iter().map(|p| json!({
+            "field": p.field,
+            "old_value": p.old_value,
+            "new_value": p.new_value,
+            "changed_at": p.changed_at.to_rfc3339(),
+        })).collect::<Vec<_>>(),
+        "pending_deliveries": pending_deliveries.into_iter().map(|d| d.title).collect::<Vec<_>>(),
+        "emails": emails_sent.into_iter().map(|e| json!({
+            "kind": e.kind,
+            "subject": e.subject,
+            "queued_at": e.created_at.to_rfc3339(),
+            "sent_at": timestamp(e.sent_at),
+        })).collect::<Vec<_>>(),
+        "welcome_emails": welcome_emails.into_iter().map(|w| json!({
+            "subject": w.subject,
+            "sent_at": w.sent_at.to_rfc3339(),
+        })).collect::<Vec<_>>(),
+        "tracking_events": tracking_events.into_iter().map(|e| json!({
+            "issue": e.title,
+            "event": e.event_type,
+            "url": e.url,
+            "first_occurred_at": e.first_occurred_at.to_rfc3339(),
+            "last_occurred_at": e.last_occurred_at.to_rfc3339(),
+            "occurrences": e.occurrences,
+        })).collect::<Vec<_>>(),
+    }))
+}
+
+/// Removes a subscriber and everything tied to them: rows that reference
+/// the subscriber go with it, the rest is found by address. Open and click
+/// events are kept for the issue statistics but no longer point to the
+/// address.
+/// With a suppression reason, a hash of the address is kept in the
+/// suppression list. Returns whether there was such a subscriber.
+#[tracing::instrument(skip(transaction, hmac_secret))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+    suppression_reason: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let Some(subscriber) = sqlx::query!(
+        r#"SELECT email, email_key FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    else {
+        return Ok(false);
+    };
+    let email = subscriber.email;
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!("DELETE FROM outbox WHERE recipient = $1", email)
+        .execute(&mut **transaction)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM confirmation_sends WHERE email_key = $1",
+        subscriber.email_key
+    )
+    .execute(&mut **transaction)
+    .await?;
+    let placeholder = format!("erased-{}", Uuid::new_v4());
+    sqlx::query!(
+        "UPDATE email_events SET subscriber_email = $1 WHERE subscriber_email = $2",
+        placeholder,
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE tracking_tokens SET subscriber_email = $1 WHERE subscriber_email = $2",
+        placeholder,
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut **transaction)
+        .await?;
+    if let Some(reason) = suppression_reason {
+        sqlx::query!(
+            r#"
+            INSERT INTO suppressions (email_hash, reason) VALUES ($1, $2)
+            ON CONFLICT (email_hash) DO NOTHING
+            "#,
+            suppression_hash(hmac_secret, &subscriber.email_key),
+            reason
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(s: &str) -> Secret<String> {
+        Secret::new(s.to_string())
+    }
+
+    #[test]
+    fn suppression_hashes_ignore_case_and_surrounding_whitespace() {
+        let secret = secret("secret");
+        assert_eq!(
+            suppression_hash(&secret, " Monkey@Gmail.com "),
+            suppression_hash(&secret, "monkey@gmail.com")
+        );
+        assert_ne!(
+            suppression_hash(&secret, "monkey@gmail.com"),
+            suppression_hash(&secret, "other@gmail.com")
+        );
+    }
+
+    #[test]
+    fn suppression_hashes_do_not_contain_the_address() {
+        let hash = suppression_hash(&secret("secret"), "monkey@gmail.com");
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("monkey"));
+    }
+
+    #[test]
+    fn suppression_hashes_depend_on_the_secret() {
+        assert_ne!(
+            suppression_hash(&secret("one secret"), "monkey@gmail.com"),
+            suppression_hash(&secret("another secret"), "monkey@gmail.com")
+        );
+    }
+}
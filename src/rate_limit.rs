@@ -0,0 +1,184 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::RETRY_AFTER,
+    web, HttpResponse,
+};
+use anyhow::Context;
+use futures_util::future::LocalBoxFuture;
+use redis::aio::ConnectionManager;
+
+use crate::{
+    client_ip::client_ip,
+    configuration::{BucketSettings, RateLimitSettings},
+};
+
+/// Routes sharing a rate limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteGroup {
+    Login,
+    Subscribe,
+    Confirm,
+    Webhooks,
+    Api,
+}
+
+impl RouteGroup {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RouteGroup::Login => "login",
+            RouteGroup::Subscribe => "subscribe",
+            RouteGroup::Confirm => "confirm",
+            RouteGroup::Webhooks => "webhooks",
+            RouteGroup::Api => "api",
+        }
+    }
+}
+
+/// Refills the bucket for the time elapsed since the last request, then
+/// takes a token if there is one. Runs atomically in Redis, on the Redis
+/// clock, so every app instance shares the same buckets.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_per_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated_at) * refill_per_ms)
+local retry_after_ms = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    retry_after_ms = math.ceil((1 - tokens) / refill_per_ms)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / refill_per_ms))
+return retry_after_ms
+"#;
+
+/// Token buckets stored in Redis, one per route group and client IP.
+pub struct RateLimiter {
+    connection: ConnectionManager,
+    settings: RateLimitSettings,
+}
+
+impl RateLimiter {
+    pub async fn connect(
+        redis_url: &str,
+        settings: RateLimitSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_url).context("Invalid Redis URL")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(Self {
+            connection,
+            settings,
+        })
+    }
+
+    fn bucket(&self, group: RouteGroup) -> &BucketSettings {
+        match group {
+            RouteGroup::Login => &self.settings.login,
+            RouteGroup::Subscribe => &self.settings.subscribe,
+            RouteGroup::Confirm => &self.settings.confirm,
+            RouteGroup::Webhooks => &self.settings.webhooks,
+            RouteGroup::Api => &self.settings.api,
+        }
+    }
+
+    /// Takes a token from the client's bucket. Returns how long to wait
+    /// when the bucket is empty.
+    #[tracing::instrument(name = "Taking a rate limit token", skip(self))]
+    pub async fn acquire(
+        &self,
+        group: RouteGroup,
+        client: &str,
+    ) -> Result<Option<std::time::Duration>, anyhow::Error> {
+        let bucket = self.bucket(group);
+        let key = format!("{}:{}:{}", self.settings.key_prefix, group.as_str(), client);
+        let retry_after_ms: u64 = redis::cmd("EVAL")
+            .arg(TOKEN_BUCKET_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(bucket.capacity)
+            .arg(bucket.refill_per_ms())
+            .query_async(&mut self.connection.clone())
+            .await
+            .context("Failed to run the token bucket script")?;
+        Ok((retry_after_ms > 0).then(|| std::time::Duration::from_millis(retry_after_ms)))
+    }
+}
+
+/// Limits how often a client can call the wrapped routes, with the bucket
+/// configured for `group`. Clients over the limit get a 429 telling them
+/// when to retry. Requests go through when Redis cannot be reached.
+pub struct RateLimit(pub RouteGroup);
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            group: self.0,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    group: RouteGroup,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let group = self.group;
+        Box::pin(async move {
+            let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+            let client = client_ip(req.request()).map(|ip| ip.to_string());
+            if let (Some(limiter), Some(client)) = (limiter, client) {
+                if limiter.settings.enabled {
+                    match limiter.acquire(group, &client).await {
+                        Ok(None) => {}
+                        Ok(Some(retry_after)) => {
+                            let seconds = retry_after.as_millis().div_ceil(1000);
+                            let response = HttpResponse::TooManyRequests()
+                                .insert_header((RETRY_AFTER, seconds.to_string()))
+                                .body("Too many requests. Please try again later.");
+                            return Ok(req.into_response(response).map_into_right_body());
+                        }
+                        Err(e) => {
+                            tracing::warn!(error.cause_chain = ?e, "Failed to apply a rate limit");
+                        }
+                    }
+                }
+            }
+            service.call(req).await.map(|r| r.map_into_left_body())
+        })
+    }
+}
@@ -3,13 +3,19 @@ mod health_check;
 mod home;
 mod login;
 mod newsletters;
+mod preferences;
+mod privacy;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
 pub use newsletters::*;
+pub use preferences::*;
+pub use privacy::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use tracking::*;
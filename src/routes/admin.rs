@@ -1,9 +1,27 @@
+mod consent;
 mod dashboard;
+mod fields;
+mod imports;
+mod issues;
+mod lists;
 mod logout;
 mod newsletter;
 mod password;
+mod segments;
+mod subscribers;
+mod tags;
+mod welcome;
 
+pub use consent::*;
 pub use dashboard::admin_dashboard;
+pub use fields::*;
+pub use imports::*;
+pub use issues::*;
+pub use lists::*;
 pub use logout::logout;
 pub use newsletter::*;
 pub use password::*;
+pub use segments::*;
+pub use subscribers::*;
+pub use tags::*;
+pub use welcome::*;
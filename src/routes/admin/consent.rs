@@ -0,0 +1,5 @@
+mod export;
+mod get;
+
+pub use export::export_consent_events;
+pub use get::consent_events;
@@ -0,0 +1,65 @@
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::get::QueryParams;
+use crate::{
+    consent::{get_consent_events, ConsentEvent},
+    utils::e500,
+};
+
+/// CSV of the consent records of one subscriber, or of everyone when no
+/// email is given.
+pub async fn export_consent_events(
+    pool: web::Data<PgPool>,
+    query: web::Query<QueryParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = query
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|e| !e.is_empty());
+    let events = get_consent_events(&pool, email).await.map_err(e500)?;
+    let body = to_csv(&events).map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("consent_events.csv".into())],
+        })
+        .body(body))
+}
+
+fn to_csv(events: &[ConsentEvent]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "email",
+        "list",
+        "event",
+        "ip_address",
+        "user_agent",
+        "form_id",
+        "consent_text_version",
+        "note",
+        "occurred_at",
+    ])?;
+    for event in events {
+        writer.write_record([
+            event.email.as_str(),
+            event.list.as_deref().unwrap_or_default(),
+            event.event_type.as_str(),
+            event.ip_address.as_deref().unwrap_or_default(),
+            event.user_agent.as_deref().unwrap_or_default(),
+            event.form_id.as_deref().unwrap_or_default(),
+            event.consent_text_version.as_deref().unwrap_or_default(),
+            event.note.as_deref().unwrap_or_default(),
+            &event.occurred_at.to_rfc3339(),
+        ])?;
+    }
+    writer
+        .into_inner()
+        .context("Failed to write the CSV export.")
+}
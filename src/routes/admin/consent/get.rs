@@ -0,0 +1,87 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{consent::get_consent_events, utils::e500};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    #[serde(default)]
+    pub(super) email: Option<String>,
+}
+
+/// Consent history of one subscriber, looked up by email address.
+pub async fn consent_events(
+    pool: web::Data<PgPool>,
+    query: web::Query<QueryParams>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = query
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|e| !e.is_empty());
+    let mut results_html = String::new();
+    if let Some(email) = email {
+        let events = get_consent_events(&pool, Some(email)).await.map_err(e500)?;
+        if events.is_empty() {
+            writeln!(
+                results_html,
+                "<p>No consent records for {}.</p>",
+                htmlescape::encode_minimal(email)
+            )
+            .unwrap();
+        } else {
+            let mut rows_html = String::new();
+            for event in events {
+                writeln!(
+                    rows_html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    event.occurred_at.to_rfc3339(),
+                    htmlescape::encode_minimal(&event.event_type),
+                    htmlescape::encode_minimal(event.list.as_deref().unwrap_or_default()),
+                    htmlescape::encode_minimal(event.ip_address.as_deref().unwrap_or_default()),
+                    htmlescape::encode_minimal(event.user_agent.as_deref().unwrap_or_default()),
+                    htmlescape::encode_minimal(event.form_id.as_deref().unwrap_or_default()),
+                    htmlescape::encode_minimal(
+                        event.consent_text_version.as_deref().unwrap_or_default()
+                    ),
+                    htmlescape::encode_minimal(event.note.as_deref().unwrap_or_default()),
+                )
+                .unwrap();
+            }
+            write!(
+                results_html,
+                r#"<table>
+        <tr><th>When</th><th>Event</th><th>List</th><th>IP address</th><th>User agent</th><th>Form</th><th>Consent text</th><th>Note</th></tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/consent/export?email={}">Export as CSV</a></p>"#,
+                urlencoding::encode(email)
+            )
+            .unwrap();
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Consent records</title>
+</head>
+<body>
+    <form action="/admin/consent" method="get">
+        <label>Subscriber email:
+            <input type="text" name="email" value="{}">
+        </label>
+        <button type="submit">Look up</button>
+    </form>
+    {results_html}
+    <p><a href="/admin/consent/export">Export all consent records as CSV</a></p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(email.unwrap_or_default())
+        )))
+}
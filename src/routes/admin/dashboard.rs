@@ -25,6 +25,16 @@ pub async fn admin_dashboard(
     <p>Available actions:</p>
     <ol>
         <li><a href="/admin/newsletter">Send Newsletter</a></li>
+        <li><a href="/admin/issues">Newsletter issues</a></li>
+        <li><a href="/admin/lists">Mailing lists</a></li>
+        <li><a href="/admin/tags">Subscriber tags</a></li>
+        <li><a href="/admin/segments">Segments</a></li>
+        <li><a href="/admin/fields">Custom fields</a></li>
+        <li><a href="/admin/welcome">Welcome series</a></li>
+        <li><a href="/admin/consent">Consent records</a></li>
+        <li><a href="/admin/subscribers">Subscribers</a></li>
+        <li><a href="/admin/subscribers/import">Import subscribers</a></li>
+        <li><a href="/admin/subscribers/export">Export subscribers (CSV)</a></li>
         <li><a href="/admin/password">Change password</a></li>
         <li>
         <form name="logoutForm" action="/admin/logout" method="post">
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::list_fields;
+pub use post::{create_field, set_field_value};
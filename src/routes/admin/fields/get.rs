@@ -0,0 +1,98 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{custom_fields::get_custom_fields, utils::e500};
+
+pub async fn list_fields(
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let fields = get_custom_fields(pool.get_ref()).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    let mut key_options_html = String::new();
+    for field in fields {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            field.key,
+            htmlescape::encode_minimal(&field.label),
+            field.field_type.as_str(),
+            htmlescape::encode_minimal(&field.options.join(", ")),
+            if field.required { "yes" } else { "no" }
+        )
+        .unwrap();
+        writeln!(
+            key_options_html,
+            r#"<option value="{}">{}</option>"#,
+            field.key,
+            htmlescape::encode_minimal(&field.label)
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Custom Fields</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Key</th><th>Label</th><th>Type</th><th>Options</th><th>Required</th></tr>
+        {rows_html}
+    </table>
+    <p>Use <code>{{{{ key }}}}</code> or <code>{{{{ key | fallback }}}}</code> in an issue to insert a subscriber's value.</p>
+    <h2>New field</h2>
+    <form action="/admin/fields" method="post">
+        <label>Key:
+            <input type="text" name="key" placeholder="company_size">
+        </label>
+        <label>Label:
+            <input type="text" name="label" placeholder="Company size">
+        </label>
+        <label>Type:
+            <select name="field_type">
+                <option value="text">Text</option>
+                <option value="number">Number</option>
+                <option value="date">Date</option>
+                <option value="choice">Single choice</option>
+            </select>
+        </label>
+        <label>Options (comma separated, single choice only):
+            <input type="text" name="options">
+        </label>
+        <label>
+            <input type="checkbox" name="required" value="true">
+            Required on signup
+        </label>
+        <button type="submit">Add field</button>
+    </form>
+    <h2>Set a subscriber's value</h2>
+    <form action="/admin/fields/values" method="post">
+        <label>Subscriber email:
+            <input type="email" name="email">
+        </label>
+        <label>Field:
+            <select name="key">
+                {key_options_html}
+            </select>
+        </label>
+        <label>Value:
+            <input type="text" name="value">
+        </label>
+        <button type="submit">Save value</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,135 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    custom_fields::{get_custom_fields, is_valid_key, set_field_values, FieldType},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FieldForm {
+    key: String,
+    label: String,
+    field_type: String,
+    #[serde(default)]
+    options: String,
+    #[serde(default)]
+    required: Option<String>,
+}
+
+#[tracing::instrument(name = "Create a custom field", skip(form, pool), fields(key = %form.key))]
+pub async fn create_field(
+    form: web::Form<FieldForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let key = form.key.trim();
+    let label = form.label.trim();
+    let options: Vec<String> = form
+        .options
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .map(str::to_string)
+        .collect();
+    let field_type = match FieldType::try_from(form.field_type.clone()) {
+        Ok(field_type) => field_type,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/fields"));
+        }
+    };
+    let error = if !is_valid_key(key) {
+        Some("The key must be snake_case, start with a letter and can't be name, email, list or tags.")
+    } else if label.is_empty() {
+        Some("The field needs a label.")
+    } else if field_type == FieldType::Choice && options.is_empty() {
+        Some("A single choice field needs at least one option.")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        FlashMessage::error(error).send();
+        return Ok(see_other("/admin/fields"));
+    }
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO custom_fields (field_id, key, label, field_type, options, required)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (key) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        key,
+        label,
+        field_type.as_str(),
+        &options,
+        form.required.is_some()
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .rows_affected();
+    if inserted == 0 {
+        FlashMessage::error(format!("A field with the key {} already exists.", key)).send();
+    } else {
+        FlashMessage::info(format!("The field {} has been added.", label)).send();
+    }
+    Ok(see_other("/admin/fields"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ValueForm {
+    email: String,
+    key: String,
+    value: String,
+}
+
+#[tracing::instrument(name = "Set a custom field value", skip(form, pool), fields(email = %form.email, key = %form.key))]
+pub async fn set_field_value(
+    form: web::Form<ValueForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let fields = get_custom_fields(pool.get_ref()).await.map_err(e500)?;
+    let Some(field) = fields.iter().find(|f| f.key == form.key) else {
+        FlashMessage::error(format!("{} is not a custom field.", form.key)).send();
+        return Ok(see_other("/admin/fields"));
+    };
+    let value = match field.parse_value(&serde_json::Value::String(form.value.clone())) {
+        Ok(value) => value,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/fields"));
+        }
+    };
+    let Some(subscriber) = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1"#,
+        form.email.trim()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(e500)?
+    else {
+        FlashMessage::error("There is no subscriber with this email address.").send();
+        return Ok(see_other("/admin/fields"));
+    };
+    match value {
+        Some(value) => set_field_values(pool.get_ref(), subscriber.id, &[(field.field_id, value)])
+            .await
+            .context("Failed to save the custom field value")
+            .map_err(e500)?,
+        None => {
+            sqlx::query!(
+                r#"DELETE FROM subscriber_field_values WHERE subscriber_id = $1 AND field_id = $2"#,
+                subscriber.id,
+                field.field_id
+            )
+            .execute(pool.get_ref())
+            .await
+            .map_err(e500)?;
+        }
+    }
+    FlashMessage::info(format!("{} has been updated.", field.label)).send();
+    Ok(see_other("/admin/fields"))
+}
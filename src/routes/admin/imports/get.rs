@@ -0,0 +1,230 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{lists::get_lists, subscriber_import::write_rejection_report, utils::e500};
+
+struct ImportSummary {
+    import_id: Uuid,
+    source: String,
+    list_name: String,
+    mark_confirmed: bool,
+    consent_note: Option<String>,
+    imported: i32,
+    skipped_duplicates: i32,
+    skipped_suppressed: i32,
+    rejected_invalid: i32,
+    failed: i32,
+    status: String,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl ImportSummary {
+    fn status_html(&self) -> String {
+        match (self.status.as_str(), &self.error) {
+            ("running", _) => "Running".to_string(),
+            ("failed", Some(error)) => format!("Failed: {}", htmlescape::encode_minimal(error)),
+            ("failed", None) => "Failed".to_string(),
+            _ => "Completed".to_string(),
+        }
+    }
+}
+
+pub async fn import_form(
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let mut lists_html = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        writeln!(
+            lists_html,
+            r#"<option value="{}">{}</option>"#,
+            list.slug,
+            htmlescape::encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+    let mut rows_html = String::new();
+    for import in get_import_summaries(&pool, None).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/imports/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            import.import_id,
+            import.created_at.format("%Y-%m-%d %H:%M"),
+            import.source,
+            htmlescape::encode_minimal(&import.list_name),
+            import.status_html(),
+            import.imported,
+            import.skipped_duplicates
+                + import.skipped_suppressed
+                + import.rejected_invalid
+                + import.failed
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <p>Upload a CSV file with <code>email</code> and <code>name</code> columns.
+    Imported subscribers get a confirmation email, unless they are imported as confirmed.</p>
+    <form action="/admin/subscribers/import" method="post" enctype="multipart/form-data">
+        <label>File:
+            <input type="file" name="file" accept=".csv,text/csv">
+        </label>
+        <br>
+        <label>List:
+            <select name="list">
+                {lists_html}
+            </select>
+        </label>
+        <br>
+        <label><input type="checkbox" name="confirmed" value="on"> Already confirmed</label>
+        <br>
+        <label>Consent note (required for confirmed imports):
+            <input type="text" name="consent_note" placeholder="Where and when they opted in">
+        </label>
+        <br>
+        <button type="submit">Import</button>
+    </form>
+    <table>
+        <tr><th>Imported at</th><th>Source</th><th>List</th><th>Status</th><th>Imported</th><th>Not imported</th></tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn import_details(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(import) = get_import_summaries(&pool, Some(*import_id))
+        .await
+        .map_err(e500)?
+        .pop()
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let confirmed_html = match (import.mark_confirmed, &import.consent_note) {
+        (true, Some(note)) => format!(
+            "<li>Imported as confirmed: {}</li>",
+            htmlescape::encode_minimal(note)
+        ),
+        _ => "<li>Imported subscribers were sent a confirmation email</li>".to_string(),
+    };
+    // Shows the progress of an import that is still running.
+    let refresh_html = if import.status == "running" {
+        r#"<meta http-equiv="refresh" content="5">"#
+    } else {
+        ""
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Import report</title>
+    {refresh_html}
+</head>
+<body>
+    {msg_html}
+    <ul>
+        <li>List: {}</li>
+        <li>Status: {}</li>
+        {confirmed_html}
+        <li>Imported: {}</li>
+        <li>Skipped duplicates: {}</li>
+        <li>Skipped suppressed addresses: {}</li>
+        <li>Rejected invalid rows: {}</li>
+        <li>Rows that failed to import: {}</li>
+    </ul>
+    <p><a href="/admin/subscribers/imports/{}/report">Download the rows that were not imported</a></p>
+    <p><a href="/admin/subscribers/import">&lt;- Back</a></p>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&import.list_name),
+            import.status_html(),
+            import.imported,
+            import.skipped_duplicates,
+            import.skipped_suppressed,
+            import.rejected_invalid,
+            import.failed,
+            import.import_id,
+        )))
+}
+
+/// The rows of an import that were skipped or rejected, with the reason.
+pub async fn import_report(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    let mut body = Vec::new();
+    write_rejection_report(&pool, import_id, &mut body)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "import_{}_report.csv",
+                import_id
+            ))],
+        })
+        .body(body))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_import_summaries(
+    pool: &PgPool,
+    import_id: Option<Uuid>,
+) -> Result<Vec<ImportSummary>, anyhow::Error> {
+    let imports = sqlx::query_as!(
+        ImportSummary,
+        r#"
+        SELECT
+            i.import_id, i.source, l.name as list_name, i.mark_confirmed, i.consent_note,
+            i.imported, i.skipped_duplicates, i.skipped_suppressed, i.rejected_invalid,
+            i.failed, i.status, i.error, i.created_at
+        FROM subscriber_imports i JOIN lists l ON l.list_id = i.list_id
+        WHERE $1::uuid IS NULL OR i.import_id = $1
+        ORDER BY i.created_at DESC
+        LIMIT 50
+        "#,
+        import_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber imports.")?;
+    Ok(imports)
+}
@@ -0,0 +1,134 @@
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    configuration::SubscriptionSettings,
+    lists::DEFAULT_LIST_SLUG,
+    startup::{ApplicationBaseUrl, HmacSecret},
+    subscriber_import::{create_import, run_import, ImportError, ImportOptions},
+    utils::{e400, e500, see_other},
+};
+
+const MAX_UPLOAD_BYTES: usize = 200 * 1024 * 1024;
+const MAX_FIELD_BYTES: usize = 64 * 1024;
+
+/// The uploaded file, written to disk as it arrives. It is removed again
+/// once the import is over.
+struct SpooledUpload {
+    path: PathBuf,
+}
+
+impl SpooledUpload {
+    fn new() -> Self {
+        Self {
+            path: std::env::temp_dir().join(format!("monkey_letter_import_{}.csv", Uuid::new_v4())),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SpooledUpload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Spools the upload to disk and imports it in the background, redirecting
+/// to the import's page where its progress shows.
+#[tracing::instrument(name = "Import subscribers from an upload", skip_all)]
+pub async fn upload_import(
+    mut payload: Multipart,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let upload = SpooledUpload::new();
+    let mut uploaded_bytes = 0;
+    let mut options = ImportOptions {
+        list_slug: DEFAULT_LIST_SLUG.to_string(),
+        mark_confirmed: false,
+        consent_note: None,
+    };
+    while let Some(mut field) = payload.try_next().await.map_err(e400)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            let mut file = tokio::fs::File::create(upload.path()).await.map_err(e500)?;
+            while let Some(chunk) = field.try_next().await.map_err(e400)? {
+                uploaded_bytes += chunk.len();
+                if uploaded_bytes > MAX_UPLOAD_BYTES {
+                    FlashMessage::error("The file is too large to import.").send();
+                    return Ok(see_other("/admin/subscribers/import"));
+                }
+                file.write_all(&chunk).await.map_err(e500)?;
+            }
+            file.flush().await.map_err(e500)?;
+            continue;
+        }
+        let mut value = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(e400)? {
+            if value.len() + chunk.len() > MAX_FIELD_BYTES {
+                return Err(e400(format!("The {} field is too large.", name)));
+            }
+            value.extend_from_slice(&chunk);
+        }
+        match name.as_str() {
+            "list" => options.list_slug = String::from_utf8_lossy(&value).into_owned(),
+            "confirmed" => options.mark_confirmed = true,
+            "consent_note" => {
+                options.consent_note = Some(String::from_utf8_lossy(&value).into_owned())
+            }
+            _ => {}
+        }
+    }
+    if uploaded_bytes == 0 {
+        FlashMessage::error("Choose a CSV file to import.").send();
+        return Ok(see_other("/admin/subscribers/import"));
+    }
+    let csv = tokio::fs::File::open(upload.path())
+        .await
+        .map_err(e500)?
+        .into_std()
+        .await;
+    let job = match create_import(&pool, &options, "upload").await {
+        Ok(job) => job,
+        Err(ImportError::ValidationError(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+        Err(e) => return Err(e500(e)),
+    };
+    let location = format!("/admin/subscribers/imports/{}", job.import_id);
+    let pool = pool.into_inner();
+    let hmac_secret = hmac_secret.into_inner();
+    tokio::spawn(async move {
+        if let Err(e) = run_import(
+            &pool,
+            &base_url.0,
+            &settings,
+            &hmac_secret.0,
+            job,
+            BufReader::new(csv),
+        )
+        .await
+        {
+            tracing::error!(error.cause_chain = ?e, "Failed to import subscribers");
+        }
+        drop(upload);
+    });
+    FlashMessage::info("The import has started.").send();
+    Ok(see_other(&location))
+}
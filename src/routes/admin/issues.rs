@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{issue_details, list_issues};
+pub use post::{cancel_issue, pause_issue, resume_issue};
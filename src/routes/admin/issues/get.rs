@@ -0,0 +1,327 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::e500;
+
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: String,
+    delivery_status: String,
+}
+
+pub async fn list_issues(
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let issues = get_issues(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for issue in issues {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/issues/{}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+            issue.newsletter_issue_id,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at,
+            issue.delivery_status
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter Issues</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Title</th><th>Published at</th><th>Delivery</th></tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub struct IssueDetails {
+    pub title: String,
+    pub list_name: String,
+    pub published_at: String,
+    pub delivery_status: String,
+    pub delivered_count: i32,
+    pub undelivered_count: i32,
+    pub pending_count: i64,
+    pub track_opens: bool,
+    pub unique_opens: i64,
+    pub track_clicks: bool,
+    pub ab_metric: Option<String>,
+    pub ab_decide_at: Option<DateTime<Utc>>,
+    pub ab_winner: Option<i16>,
+}
+
+pub struct SubjectVariant {
+    pub variant: i16,
+    pub subject: String,
+    pub sent_count: i32,
+    pub engaged_count: Option<i32>,
+}
+
+pub struct LinkClicks {
+    pub url: String,
+    pub unique_clicks: i64,
+    pub total_clicks: i64,
+}
+
+pub async fn issue_details(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let Some(issue) = get_issue_details(&pool, issue_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let mut actions_html = String::new();
+    let actions: &[&str] = match issue.delivery_status.as_str() {
+        "in_progress" => &["pause", "cancel"],
+        "paused" => &["resume", "cancel"],
+        _ => &[],
+    };
+    for action in actions {
+        writeln!(
+            actions_html,
+            r#"<form action="/admin/issues/{issue_id}/{action}" method="post"><button type="submit">{action}</button></form>"#
+        )
+        .unwrap();
+    }
+    let opens_html = if issue.track_opens {
+        format!(
+            "<li>Unique opens: {} ({})</li>",
+            issue.unique_opens,
+            rate(issue.unique_opens, issue.delivered_count)
+        )
+    } else {
+        "<li>Open tracking is disabled for this issue</li>".to_string()
+    };
+    let mut clicks_html = String::new();
+    if issue.track_clicks {
+        let links = get_link_clicks(&pool, issue_id).await.map_err(e500)?;
+        writeln!(
+            clicks_html,
+            "<table><tr><th>Link</th><th>Unique clicks</th><th>Total clicks</th></tr>"
+        )
+        .unwrap();
+        for link in links {
+            writeln!(
+                clicks_html,
+                "<tr><td>{}</td><td>{} ({})</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&link.url),
+                link.unique_clicks,
+                rate(link.unique_clicks, issue.delivered_count),
+                link.total_clicks
+            )
+            .unwrap();
+        }
+        writeln!(clicks_html, "</table>").unwrap();
+    }
+    let mut ab_test_html = String::new();
+    if let Some(metric) = &issue.ab_metric {
+        let variants = get_subject_variants(&pool, issue_id).await.map_err(e500)?;
+        let status = match (issue.ab_winner, issue.ab_decide_at) {
+            (Some(_), _) => "The winner has been sent to the rest of the audience.".to_string(),
+            (None, Some(decide_at)) => format!(
+                "The winner will be picked after {}.",
+                decide_at.format("%Y-%m-%d %H:%M UTC")
+            ),
+            (None, None) => String::new(),
+        };
+        writeln!(
+            ab_test_html,
+            "<h2>Subject line A/B test ({metric} rate)</h2><p>{status}</p>\
+            <table><tr><th>Subject</th><th>Sent</th><th>Engaged</th><th></th></tr>"
+        )
+        .unwrap();
+        for variant in variants {
+            let engaged = match variant.engaged_count {
+                Some(count) => format!("{} ({})", count, rate(count.into(), variant.sent_count)),
+                None => "pending".to_string(),
+            };
+            let winner = if issue.ab_winner == Some(variant.variant) {
+                "winner"
+            } else {
+                ""
+            };
+            writeln!(
+                ab_test_html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&variant.subject),
+                variant.sent_count,
+                engaged,
+                winner
+            )
+            .unwrap();
+        }
+        writeln!(ab_test_html, "</table>").unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter Issue</title>
+</head>
+<body>
+    {msg_html}
+    <h1>{title}</h1>
+    <p>List: {list_name}</p>
+    <p>Published at: {published_at}</p>
+    <p>Delivery: {delivery_status}</p>
+    <ul>
+        <li>Delivered: {delivered_count}</li>
+        <li>Not delivered: {undelivered_count}</li>
+        <li>Pending: {pending_count}</li>
+        {opens_html}
+    </ul>
+    {clicks_html}
+    {ab_test_html}
+    {actions_html}
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+            list_name = htmlescape::encode_minimal(&issue.list_name),
+            published_at = issue.published_at,
+            delivery_status = issue.delivery_status,
+            delivered_count = issue.delivered_count,
+            undelivered_count = issue.undelivered_count,
+            pending_count = issue.pending_count,
+        )))
+}
+
+fn rate(count: i64, delivered_count: i32) -> String {
+    if delivered_count == 0 {
+        return "n/a".to_string();
+    }
+    format!("{:.1}%", count as f64 * 100.0 / delivered_count as f64)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, published_at, delivery_status
+        FROM newsletter_issues
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve newsletter issues.")?;
+    Ok(issues)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue_details(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<IssueDetails>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueDetails,
+        r#"
+        SELECT
+            title,
+            (SELECT name FROM lists l WHERE l.list_id = i.list_id) as "list_name!",
+            published_at,
+            delivery_status,
+            delivered_count,
+            undelivered_count,
+            (
+                SELECT COUNT(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            ) as "pending_count!",
+            track_opens,
+            (
+                SELECT COUNT(*) FROM email_events e
+                WHERE e.newsletter_issue_id = i.newsletter_issue_id AND e.event_type = 'open'
+            ) as "unique_opens!",
+            track_clicks,
+            ab_metric,
+            ab_decide_at,
+            ab_winner
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve newsletter issue.")?;
+    Ok(issue)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_link_clicks(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<LinkClicks>, anyhow::Error> {
+    let links = sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT
+            url,
+            COUNT(*) as "unique_clicks!",
+            SUM(occurrences) as "total_clicks!"
+        FROM email_events
+        WHERE newsletter_issue_id = $1 AND event_type = 'click'
+        GROUP BY url
+        ORDER BY 2 DESC, url
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve link clicks.")?;
+    Ok(links)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_subject_variants(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<SubjectVariant>, anyhow::Error> {
+    let variants = sqlx::query_as!(
+        SubjectVariant,
+        r#"
+        SELECT variant, subject, sent_count, engaged_count
+        FROM issue_subject_variants
+        WHERE newsletter_issue_id = $1
+        ORDER BY variant
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subject line variants.")?;
+    Ok(variants)
+}
@@ -0,0 +1,140 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    utils::{e500, see_other},
+};
+
+#[tracing::instrument(name = "Pause an issue delivery", skip(pool, user_id), fields(user_id=%*user_id))]
+pub async fn pause_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let updated = transition_issue(&pool, issue_id, &["in_progress"], "paused")
+        .await
+        .map_err(e500)?;
+    if updated {
+        FlashMessage::info("The issue delivery has been paused.").send();
+    } else {
+        FlashMessage::error("Only an issue that is being delivered can be paused.").send();
+    }
+    Ok(see_other(&format!("/admin/issues/{issue_id}")))
+}
+
+#[tracing::instrument(name = "Resume an issue delivery", skip(pool, user_id), fields(user_id=%*user_id))]
+pub async fn resume_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let updated = transition_issue(&pool, issue_id, &["paused"], "in_progress")
+        .await
+        .map_err(e500)?;
+    if updated {
+        FlashMessage::info("The issue delivery has been resumed.").send();
+    } else {
+        FlashMessage::error("Only a paused issue can be resumed.").send();
+    }
+    Ok(see_other(&format!("/admin/issues/{issue_id}")))
+}
+
+#[tracing::instrument(name = "Cancel an issue delivery", skip(pool, user_id), fields(user_id=%*user_id))]
+pub async fn cancel_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let cancelled = cancel_delivery(&pool, issue_id).await.map_err(e500)?;
+    match cancelled {
+        Some(n_removed) => FlashMessage::info(format!(
+            "The issue delivery has been cancelled - {n_removed} pending email(s) will not be sent."
+        ))
+        .send(),
+        None => {
+            FlashMessage::error("Only an issue that is being delivered or paused can be cancelled.")
+                .send()
+        }
+    }
+    Ok(see_other(&format!("/admin/issues/{issue_id}")))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn transition_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    from: &[&str],
+    to: &str,
+) -> Result<bool, anyhow::Error> {
+    let from: Vec<String> = from.iter().map(|s| s.to_string()).collect();
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET delivery_status = $3
+        WHERE newsletter_issue_id = $1 AND delivery_status = ANY($2)
+        "#,
+        issue_id,
+        &from,
+        to
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the issue delivery status.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns the number of queued deliveries that were dropped, or `None` if
+/// the issue was not in a state that allows cancelling.
+#[tracing::instrument(skip(pool))]
+async fn cancel_delivery(pool: &PgPool, issue_id: Uuid) -> Result<Option<u64>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres conn from pool")?;
+    let updated = transaction
+        .execute(sqlx::query!(
+            r#"
+        UPDATE newsletter_issues
+        SET delivery_status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND delivery_status IN ('in_progress', 'paused')
+        "#,
+            issue_id
+        ))
+        .await
+        .context("Failed to mark the issue as cancelled.")?;
+    if updated.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let n_removed = transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1"#,
+            issue_id
+        ))
+        .await
+        .context("Failed to remove pending deliveries.")?
+        .rows_affected();
+    transaction
+        .execute(sqlx::query!(
+            r#"
+        UPDATE newsletter_issues
+        SET undelivered_count = undelivered_count + $2
+        WHERE newsletter_issue_id = $1
+        "#,
+            issue_id,
+            n_removed as i32
+        ))
+        .await
+        .context("Failed to record the undelivered count.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to cancel the issue.")?;
+    Ok(Some(n_removed))
+}
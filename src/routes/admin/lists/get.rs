@@ -0,0 +1,100 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{configuration::SubscriptionSettings, utils::e500};
+
+struct ListSummary {
+    slug: String,
+    name: String,
+    opt_in: Option<String>,
+    confirmed_count: i64,
+    pending_count: i64,
+}
+
+pub async fn list_lists(
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let lists = get_list_summaries(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for list in lists {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&list.name),
+            list.slug,
+            list.opt_in
+                .unwrap_or_else(|| format!("default ({})", settings.default_opt_in.as_str())),
+            list.confirmed_count,
+            list.pending_count
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailing Lists</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Slug</th><th>Opt-in</th><th>Confirmed</th><th>Pending</th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/lists" method="post">
+        <label>Name:
+            <input type="text" placeholder="Enter the list name" name="name">
+        </label>
+        <label>Slug:
+            <input type="text" placeholder="lowercase-with-dashes" name="slug">
+        </label>
+        <label>Opt-in:
+            <select name="opt_in">
+                <option value="" selected>Default ({default_opt_in})</option>
+                <option value="double">Double opt-in</option>
+                <option value="single">Single opt-in</option>
+            </select>
+        </label>
+        <button type="submit">Create list</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            default_opt_in = settings.default_opt_in.as_str(),
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_list_summaries(pool: &PgPool) -> Result<Vec<ListSummary>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.slug,
+            l.name,
+            l.opt_in,
+            COUNT(s.subscriber_id) FILTER (WHERE s.status = 'confirmed') as "confirmed_count!",
+            COUNT(s.subscriber_id) FILTER (WHERE s.status = 'pending_confirmation') as "pending_count!"
+        FROM lists l
+        LEFT JOIN list_subscriptions s ON s.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.created_at, l.name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve mailing lists.")?;
+    Ok(lists)
+}
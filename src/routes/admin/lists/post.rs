@@ -0,0 +1,60 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    lists::{is_valid_slug, OptIn},
+    utils::{e400, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+    slug: String,
+    #[serde(default)]
+    opt_in: String,
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(form, pool), fields(slug = %form.slug))]
+pub async fn create_list(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    let slug = form.slug.trim();
+    if name.is_empty() {
+        FlashMessage::error("The list needs a name.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    if !is_valid_slug(slug) {
+        FlashMessage::error("The slug can only contain lowercase letters, digits and dashes.")
+            .send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let opt_in = match form.opt_in.as_str() {
+        "" => None,
+        opt_in => Some(OptIn::try_from(opt_in.to_string()).map_err(e400)?),
+    };
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, opt_in)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug,
+        name,
+        opt_in.map(|o| o.as_str())
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .rows_affected();
+    if inserted == 0 {
+        FlashMessage::error(format!("A list with the slug {} already exists.", slug)).send();
+    } else {
+        FlashMessage::info(format!("The list {} has been created.", name)).send();
+    }
+    Ok(see_other("/admin/lists"))
+}
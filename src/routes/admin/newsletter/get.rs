@@ -1,15 +1,81 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
 use std::fmt::Write;
 use uuid::Uuid;
 
+use crate::{
+    lists::get_lists,
+    segments::{count_recipients, get_segments},
+    utils::e500,
+};
+
+/// Picking a list and segment and submitting the audience form shows how many
+/// subscribers the issue would currently go to.
+#[derive(serde::Deserialize)]
+pub struct AudienceQuery {
+    list: Option<String>,
+    segment: Option<String>,
+}
+
 pub async fn send_newsletter_form(
+    pool: web::Data<PgPool>,
+    query: web::Query<AudienceQuery>,
     flash_message: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_message.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
     }
+    let lists = get_lists(&pool).await.map_err(e500)?;
+    let segments = get_segments(&pool).await.map_err(e500)?;
+    let selected_list = lists.iter().find(|l| Some(&l.slug) == query.list.as_ref());
+    let selected_segment = segments
+        .iter()
+        .find(|s| Some(s.segment_id.to_string()) == query.segment);
+    let mut list_options_html = String::new();
+    for list in &lists {
+        let selected = if Some(list.list_id) == selected_list.map(|l| l.list_id) {
+            " selected"
+        } else {
+            ""
+        };
+        writeln!(
+            list_options_html,
+            r#"<option value="{}"{selected}>{}</option>"#,
+            list.slug,
+            htmlescape::encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+    let mut segment_options_html =
+        r#"<option value="">Every confirmed subscriber</option>"#.to_string();
+    for segment in &segments {
+        let selected = if Some(segment.segment_id) == selected_segment.map(|s| s.segment_id) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            segment_options_html,
+            r#"<option value="{}"{selected}>{}</option>"#,
+            segment.segment_id,
+            htmlescape::encode_minimal(&segment.name)
+        )
+        .unwrap();
+    }
+    let mut audience_html = String::new();
+    if let Some(list) = selected_list {
+        let count = count_recipients(&pool, Some(list.list_id), selected_segment)
+            .await
+            .map_err(e500)?;
+        write!(
+            audience_html,
+            "<p>This issue would currently go to {} recipient(s).</p>",
+            count
+        )
+        .unwrap();
+    }
     let idempotency_key = Uuid::new_v4().to_string();
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -22,7 +88,32 @@ pub async fn send_newsletter_form(
 </head>
 <body>
     {msg_html}
+    <form action="/admin/newsletters" method="get">
+        <label>Send to:
+            <select name="list">
+                {list_options_html}
+            </select>
+        </label>
+        <label>Segment:
+            <select name="segment">
+                {segment_options_html}
+            </select>
+        </label>
+        <button type="submit">Count recipients</button>
+    </form>
+    {audience_html}
     <form action="/admin/newsletters" method="post">
+        <label>Send to:
+            <select name="list">
+                {list_options_html}
+            </select>
+        </label>
+        <label>Segment:
+            <select name="segment">
+                {segment_options_html}
+            </select>
+        </label>
+        <br>
         <label>Title:<br>
             <input
                 type="text"
@@ -49,6 +140,54 @@ pub async fn send_newsletter_form(
             ></textarea>
         </label>
         <br>
+        <label>
+            <input type="checkbox" name="track_opens" value="true">
+            Track opens
+        </label>
+        <br>
+        <label>
+            <input type="checkbox" name="track_clicks" value="true">
+            Track link clicks (add <code>data-no-track</code> to a link to opt it out)
+        </label>
+        <br>
+        <fieldset>
+            <legend>
+                <label>
+                    <input type="checkbox" name="utm_tagging" value="true">
+                    Add UTM parameters to links
+                </label>
+            </legend>
+            <label>Source:
+                <input type="text" name="utm_source" value="newsletter">
+            </label>
+            <label>Medium:
+                <input type="text" name="utm_medium" value="email">
+            </label>
+            <label>Campaign:
+                <input type="text" name="utm_campaign" placeholder="Defaults to the issue slug">
+            </label>
+        </fieldset>
+        <br>
+        <fieldset>
+            <legend>Subject line A/B test</legend>
+            <label>Subject lines (one per line, leave empty to use the title):<br>
+                <textarea name="subject_variants" rows="4" cols="50"></textarea>
+            </label>
+            <br>
+            <label>Sample size (% of the audience):
+                <input type="number" name="ab_sample_percent" value="20" min="1" max="100">
+            </label>
+            <label>Pick the winner after (minutes):
+                <input type="number" name="ab_window_minutes" value="240" min="1">
+            </label>
+            <label>Winning metric:
+                <select name="ab_metric">
+                    <option value="open">Open rate</option>
+                    <option value="click">Click rate</option>
+                </select>
+            </label>
+        </fieldset>
+        <br>
         <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
         <button type="submit">Publish</button>
     </form>
@@ -1,12 +1,17 @@
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
-use sqlx::{Executor, PgPool, Postgres, Transaction};
+use sqlx::{Executor, PgPool, Postgres, QueryBuilder, Transaction};
 use uuid::Uuid;
 
 use crate::{
+    ab_test::AbTestMetric,
     authentication::UserId,
+    custom_fields::get_custom_fields,
     idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    lists::{get_list_by_slug, DEFAULT_LIST_SLUG},
+    segments::{get_segment, push_audience, Segment},
+    tracking::{slugify, UtmParameters},
     utils::{e400, e500, see_other},
 };
 
@@ -16,6 +21,112 @@ pub struct FormData {
     text_content: String,
     html_content: String,
     idempotency_key: String,
+    #[serde(default)]
+    list: Option<String>,
+    #[serde(default)]
+    segment: Option<String>,
+    #[serde(default)]
+    track_opens: Option<String>,
+    #[serde(default)]
+    track_clicks: Option<String>,
+    #[serde(default)]
+    utm_tagging: Option<String>,
+    #[serde(default)]
+    utm_source: Option<String>,
+    #[serde(default)]
+    utm_medium: Option<String>,
+    #[serde(default)]
+    utm_campaign: Option<String>,
+    #[serde(default)]
+    subject_variants: Option<String>,
+    #[serde(default)]
+    ab_sample_percent: Option<String>,
+    #[serde(default)]
+    ab_window_minutes: Option<String>,
+    #[serde(default)]
+    ab_metric: Option<String>,
+}
+
+struct DeliveryOptions {
+    track_opens: bool,
+    track_clicks: bool,
+    utm: Option<UtmParameters>,
+}
+
+struct AbTestOptions {
+    subjects: Vec<String>,
+    sample_percent: i32,
+    window_minutes: i32,
+    metric: AbTestMetric,
+}
+
+impl FormData {
+    fn delivery_options(&self) -> DeliveryOptions {
+        let utm = self.utm_tagging.as_ref().map(|_| {
+            let value = |field: &Option<String>| {
+                field
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+            };
+            let campaign = value(&self.utm_campaign)
+                .or_else(|| Some(slugify(&self.title)).filter(|slug| !slug.is_empty()))
+                .unwrap_or_else(|| "newsletter-issue".into());
+            UtmParameters {
+                source: value(&self.utm_source).unwrap_or_else(|| "newsletter".into()),
+                medium: value(&self.utm_medium).unwrap_or_else(|| "email".into()),
+                campaign,
+            }
+        });
+        DeliveryOptions {
+            track_opens: self.track_opens.is_some(),
+            track_clicks: self.track_clicks.is_some(),
+            utm,
+        }
+    }
+
+    /// An A/B test is only run when at least two subject lines are given.
+    fn ab_test(&self) -> Result<Option<AbTestOptions>, String> {
+        let subjects: Vec<String> = self
+            .subject_variants
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        match subjects.len() {
+            0 => return Ok(None),
+            1 => return Err("Enter at least two subject lines to run an A/B test.".into()),
+            _ => {}
+        }
+        let number = |field: &Option<String>, default: i32| match field.as_deref().map(str::trim) {
+            None | Some("") => Ok(default),
+            Some(value) => value.parse::<i32>().map_err(|_| value.to_string()),
+        };
+        let sample_percent = number(&self.ab_sample_percent, 20)
+            .ok()
+            .filter(|p| (1..=100).contains(p))
+            .ok_or("The A/B test sample must be between 1% and 100% of the audience.")?;
+        let window_minutes = number(&self.ab_window_minutes, 240)
+            .ok()
+            .filter(|m| *m > 0)
+            .ok_or("The A/B test window must be at least one minute long.")?;
+        let metric = self
+            .ab_metric
+            .clone()
+            .map(AbTestMetric::try_from)
+            .transpose()?
+            .unwrap_or(AbTestMetric::Opens);
+        Ok(Some(AbTestOptions {
+            subjects,
+            sample_percent,
+            window_minutes,
+            metric,
+        }))
+    }
 }
 
 #[tracing::instrument(name = "Publish a newsletter issue", skip(form, user_id, pool ) fields(user_id=%*user_id))]
@@ -24,11 +135,48 @@ pub async fn send_newsletter(
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let mut options = form.delivery_options();
+    let ab_test = match form.ab_test() {
+        Ok(ab_test) => ab_test,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+    // The winner can only be picked if the chosen metric is being tracked.
+    match ab_test.as_ref().map(|ab| ab.metric) {
+        Some(AbTestMetric::Opens) => options.track_opens = true,
+        Some(AbTestMetric::Clicks) => options.track_clicks = true,
+        None => {}
+    }
+    let list_slug = form.list.as_deref().unwrap_or(DEFAULT_LIST_SLUG);
+    let Some(list) = get_list_by_slug(pool.get_ref(), list_slug)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("The selected mailing list does not exist.").send();
+        return Ok(see_other("/admin/newsletters"));
+    };
+    let segment = match form.segment.as_deref().filter(|s| !s.is_empty()) {
+        None => None,
+        Some(segment_id) => {
+            let segment_id = Uuid::parse_str(segment_id).map_err(e400)?;
+            let Some(segment) = get_segment(pool.get_ref(), segment_id)
+                .await
+                .map_err(e500)?
+            else {
+                FlashMessage::error("The selected segment does not exist.").send();
+                return Ok(see_other("/admin/newsletters"));
+            };
+            Some(segment)
+        }
+    };
     let FormData {
         title,
         text_content,
         html_content,
         idempotency_key,
+        ..
     } = form.0;
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
     let mut transaction = match try_processing(&pool, &idempotency_key, **user_id)
@@ -41,14 +189,34 @@ pub async fn send_newsletter(
             return Ok(res);
         }
     };
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newsletter issue detail")
-        .map_err(e500)?;
-    enqueue_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .context("Failed to enqueue delivery tasks")
-        .map_err(e500)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        list.list_id,
+        segment.as_ref(),
+        &title,
+        &text_content,
+        &html_content,
+        &options,
+    )
+    .await
+    .context("Failed to store newsletter issue detail")
+    .map_err(e500)?;
+    if let Some(ab_test) = &ab_test {
+        insert_subject_variants(&mut transaction, issue_id, ab_test)
+            .await
+            .context("Failed to store the subject line variants")
+            .map_err(e500)?;
+    }
+    enqueue_delivery_tasks(
+        &mut transaction,
+        issue_id,
+        list.list_id,
+        segment.as_ref(),
+        ab_test.as_ref(),
+    )
+    .await
+    .context("Failed to enqueue delivery tasks")
+    .map_err(e500)?;
 
     success_message().send();
     let response = see_other("/admin/newsletters");
@@ -61,10 +229,14 @@ pub async fn send_newsletter(
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    segment: Option<&Segment>,
     title: &str,
     text_content: &str,
     html_content: &str,
+    options: &DeliveryOptions,
 ) -> Result<Uuid, sqlx::Error> {
+    let utm = options.utm.as_ref();
     let newsletter_id = Uuid::new_v4();
     let query = sqlx::query!(
         r#"INSERT INTO newsletter_issues (
@@ -72,33 +244,127 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
+            published_at,
+            track_opens,
+            track_clicks,
+            utm_tagging,
+            utm_source,
+            utm_medium,
+            utm_campaign,
+            list_id,
+            segment_id
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, now(), $5, $6, $7, $8, $9, $10, $11, $12)
         "#,
         newsletter_id,
         title,
         text_content,
-        html_content
+        html_content,
+        options.track_opens,
+        options.track_clicks,
+        utm.is_some(),
+        utm.map(|u| u.source.as_str()),
+        utm.map(|u| u.medium.as_str()),
+        utm.map(|u| u.campaign.as_str()),
+        list_id,
+        segment.map(|s| s.segment_id)
     );
     transaction.execute(query).await?;
     Ok(newsletter_id)
 }
 
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+async fn insert_subject_variants(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    ab_test: &AbTestOptions,
 ) -> Result<(), sqlx::Error> {
+    for (variant, subject) in ab_test.subjects.iter().enumerate() {
+        let query = sqlx::query!(
+            r#"
+            INSERT INTO issue_subject_variants (newsletter_issue_id, variant, subject)
+            VALUES ($1, $2, $3)
+            "#,
+            newsletter_issue_id,
+            variant as i16,
+            subject
+        );
+        transaction.execute(query).await?;
+    }
     let query = sqlx::query!(
         r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id,
-            subscriber_email
+        UPDATE newsletter_issues
+        SET
+            ab_metric = $2,
+            ab_decide_at = now() + make_interval(mins => $3)
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        ab_test.metric.event_type(),
+        ab_test.window_minutes
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// The audience is the list's confirmed members, narrowed down to the
+/// segment if one was picked.
+///
+/// A/B tests only cover subscribers receiving every issue.
+///
+/// With an A/B test, every delivery starts out held back. A random sample is
+/// then released, spread evenly across the subject variants; the rest of the
+/// audience is released once a winner has been picked.
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_id: Uuid,
+    segment: Option<&Segment>,
+    ab_test: Option<&AbTestOptions>,
+) -> Result<(), anyhow::Error> {
+    let fields = get_custom_fields(&mut **transaction).await?;
+    let mut builder = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, held) SELECT ",
+    );
+    builder.push_bind(newsletter_issue_id);
+    builder.push(", s.email, ");
+    builder.push_bind(ab_test.is_some());
+    push_audience(&mut builder, Some(list_id), segment, &fields)?;
+    builder.push(" AND s.frequency = 'every_issue'");
+    transaction.execute(builder.build()).await?;
+    // Weekly digest readers get the issue with their next digest instead.
+    let mut builder = QueryBuilder::new(
+        "INSERT INTO digest_items (subscriber_id, newsletter_issue_id) SELECT s.id, ",
+    );
+    builder.push_bind(newsletter_issue_id);
+    push_audience(&mut builder, Some(list_id), segment, &fields)?;
+    builder.push(" AND s.frequency = 'weekly_digest'");
+    transaction.execute(builder.build()).await?;
+    let Some(ab_test) = ab_test else {
+        return Ok(());
+    };
+    let query = sqlx::query!(
+        r#"
+        WITH sample AS (
+            SELECT
+                subscriber_email,
+                row_number() OVER (ORDER BY random()) - 1 AS rn,
+                COUNT(*) OVER () AS total
+            FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1
         )
-        SELECT $1, email FROM subscriptions WHERE status = 'confirmed'
+        UPDATE issue_delivery_queue q
+        SET held = false, subject_variant = (s.rn % $2::int)::smallint
+        FROM sample s
+        WHERE
+            q.newsletter_issue_id = $1 AND
+            q.subscriber_email = s.subscriber_email AND
+            s.rn < GREATEST(CEIL(s.total * $3::int / 100.0)::bigint, $2::int)
         "#,
-        newsletter_issue_id
+        newsletter_issue_id,
+        ab_test.subjects.len() as i32,
+        ab_test.sample_percent
     );
     transaction.execute(query).await?;
     Ok(())
@@ -0,0 +1,85 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    segments::{count_recipients, get_segments},
+    utils::e500,
+};
+
+pub async fn list_segments(
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let segments = get_segments(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for segment in segments {
+        let recipients = count_recipients(&pool, None, Some(&segment))
+            .await
+            .map_err(e500)?;
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&segment.name),
+            htmlescape::encode_minimal(&segment.tag_expression),
+            htmlescape::encode_minimal(&segment.field_conditions).replace('\n', "<br>"),
+            segment
+                .subscribed_before
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            segment
+                .opened_within_days
+                .map(|d| format!("{} days", d))
+                .unwrap_or_default(),
+            recipients
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Segments</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Tags</th><th>Custom fields</th><th>Subscribed before</th><th>Opened within</th><th>Confirmed subscribers</th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/segments" method="post">
+        <label>Name:
+            <input type="text" name="name">
+        </label>
+        <br>
+        <label>Tag expression (e.g. <code>rust and (beta or not churned)</code>):
+            <input type="text" name="tag_expression">
+        </label>
+        <br>
+        <label>Subscribed before:
+            <input type="date" name="subscribed_before">
+        </label>
+        <br>
+        <label>Opened an issue in the last
+            <input type="number" name="opened_within_days" min="1"> days
+        </label>
+        <br>
+        <label>Custom field conditions, one per line (e.g. <code>plan = pro</code>, <code>age &gt; 30</code>):<br>
+            <textarea name="field_conditions" rows="4" cols="50"></textarea>
+        </label>
+        <br>
+        <button type="submit">Save segment</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,98 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use chrono::NaiveDate;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    custom_fields::get_custom_fields,
+    segments::Segment,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+    #[serde(default)]
+    tag_expression: String,
+    #[serde(default)]
+    subscribed_before: String,
+    #[serde(default)]
+    opened_within_days: String,
+    #[serde(default)]
+    field_conditions: String,
+}
+
+impl TryFrom<FormData> for Segment {
+    type Error = String;
+
+    fn try_from(form: FormData) -> Result<Self, Self::Error> {
+        let subscribed_before = match form.subscribed_before.trim() {
+            "" => None,
+            date => Some(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| format!("{} is not a valid date.", date))?,
+            ),
+        };
+        let opened_within_days = match form.opened_within_days.trim() {
+            "" => None,
+            days => Some(
+                days.parse()
+                    .map_err(|_| format!("{} is not a valid number of days.", days))?,
+            ),
+        };
+        Ok(Segment {
+            segment_id: Uuid::new_v4(),
+            name: form.name.trim().to_string(),
+            tag_expression: form.tag_expression.trim().to_string(),
+            subscribed_before,
+            opened_within_days,
+            field_conditions: form.field_conditions.trim().to_string(),
+        })
+    }
+}
+
+#[tracing::instrument(name = "Create a segment", skip(form, pool), fields(name = %form.name))]
+pub async fn create_segment(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let fields = get_custom_fields(pool.get_ref()).await.map_err(e500)?;
+    let segment = match Segment::try_from(form.0).and_then(|s| s.validate(&fields).map(|_| s)) {
+        Ok(segment) => segment,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/segments"));
+        }
+    };
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO segments (
+            segment_id,
+            name,
+            tag_expression,
+            subscribed_before,
+            opened_within_days,
+            field_conditions
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        segment.segment_id,
+        segment.name,
+        segment.tag_expression,
+        segment.subscribed_before,
+        segment.opened_within_days,
+        segment.field_conditions
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .rows_affected();
+    if inserted == 0 {
+        FlashMessage::error(format!("A segment named {} already exists.", segment.name)).send();
+    } else {
+        FlashMessage::info(format!("The segment {} has been saved.", segment.name)).send();
+    }
+    Ok(see_other("/admin/segments"))
+}
@@ -0,0 +1,10 @@
+mod export;
+mod get;
+mod post;
+
+pub use export::export_subscribers;
+pub use get::{list_subscribers, subscriber_details};
+pub use post::{
+    confirm_subscriber_manually, delete_subscriber, rename_subscriber,
+    resend_subscriber_confirmation, unsubscribe_subscriber,
+};
@@ -0,0 +1,464 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use super::export::{non_empty, parse_date, STATUSES};
+use crate::utils::{e400, e500};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    /// Part of the email address or name.
+    #[serde(default)]
+    q: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    page: Option<i64>,
+}
+
+struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub(super) struct SubscriberDetails {
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+struct Membership {
+    name: String,
+    status: String,
+    opt_in: String,
+    consented_at: Option<DateTime<Utc>>,
+}
+
+struct HistoryEntry {
+    occurred_at: DateTime<Utc>,
+    event: String,
+    detail: String,
+}
+
+struct Search {
+    pattern: Option<String>,
+    status: Option<String>,
+    from: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+/// Matches the text anywhere, with LIKE wildcards taken literally.
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub async fn list_subscribers(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let status = non_empty(&query.status).map(str::to_string);
+    if let Some(status) = &status {
+        if !STATUSES.contains(&status.as_str()) {
+            return Err(e400(format!("{} is not a subscriber status.", status)));
+        }
+    }
+    let search = Search {
+        pattern: non_empty(&query.q).map(like_pattern),
+        status,
+        from: non_empty(&query.from)
+            .map(parse_date)
+            .transpose()
+            .map_err(e400)?,
+        until: non_empty(&query.to)
+            .map(parse_date)
+            .transpose()
+            .map_err(e400)?
+            .map(|to| to + chrono::Duration::days(1)),
+    };
+    // Pages past the end are empty, as long as their offset fits in an i64.
+    let page = query.page.unwrap_or(1).clamp(1, i64::MAX / PAGE_SIZE);
+    let (total, subscribers) = search_subscribers(&pool, &search, page)
+        .await
+        .map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let mut rows_html = String::new();
+    for subscriber in subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            subscriber.id,
+            htmlescape::encode_minimal(&subscriber.email),
+            htmlescape::encode_minimal(&subscriber.name),
+            subscriber.status,
+            subscriber.subscribed_at.format("%Y-%m-%d")
+        )
+        .unwrap();
+    }
+    let value = |v: &Option<String>| htmlescape::encode_attribute(non_empty(v).unwrap_or_default());
+    let mut status_html = String::from(r#"<option value="">Any</option>"#);
+    for status in STATUSES {
+        let selected = if search.status.as_deref() == Some(status) {
+            " selected"
+        } else {
+            ""
+        };
+        write!(
+            status_html,
+            r#"<option value="{status}"{selected}>{status}</option>"#
+        )
+        .unwrap();
+    }
+    let last_page = ((total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    let page_link = |page: i64| {
+        format!(
+            "/admin/subscribers?q={}&status={}&from={}&to={}&page={}",
+            urlencoding::encode(non_empty(&query.q).unwrap_or_default()),
+            urlencoding::encode(non_empty(&query.status).unwrap_or_default()),
+            urlencoding::encode(non_empty(&query.from).unwrap_or_default()),
+            urlencoding::encode(non_empty(&query.to).unwrap_or_default()),
+            page
+        )
+    };
+    let mut pages_html = format!("<p>Page {page} of {last_page} ({total} subscribers)");
+    if page > 1 {
+        write!(
+            pages_html,
+            r#" <a href="{}">Previous</a>"#,
+            htmlescape::encode_attribute(&page_link(page - 1))
+        )
+        .unwrap();
+    }
+    if page < last_page {
+        write!(
+            pages_html,
+            r#" <a href="{}">Next</a>"#,
+            htmlescape::encode_attribute(&page_link(page + 1))
+        )
+        .unwrap();
+    }
+    pages_html.push_str("</p>");
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/subscribers" method="get">
+        <label>Email or name:
+            <input type="text" name="q" value="{q}">
+        </label>
+        <label>Status:
+            <select name="status">{status_html}</select>
+        </label>
+        <label>Signed up from:
+            <input type="date" name="from" value="{from}">
+        </label>
+        <label>to:
+            <input type="date" name="to" value="{to}">
+        </label>
+        <button type="submit">Search</button>
+    </form>
+    <table>
+        <tr><th>Email</th><th>Name</th><th>Status</th><th>Signed up</th></tr>
+        {rows_html}
+    </table>
+    {pages_html}
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            q = value(&query.q),
+            from = value(&query.from),
+            to = value(&query.to),
+        )))
+}
+
+#[tracing::instrument(skip(pool, search))]
+async fn search_subscribers(
+    pool: &PgPool,
+    search: &Search,
+    page: i64,
+) -> Result<(i64, Vec<SubscriberSummary>), anyhow::Error> {
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "count!" FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2) AND
+            ($3::timestamptz IS NULL OR subscribed_at >= $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at < $4)
+        "#,
+        search.pattern,
+        search.status,
+        search.from,
+        search.until
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the subscribers.")?
+    .count;
+    let subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT id, email, name, status, subscribed_at FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2) AND
+            ($3::timestamptz IS NULL OR subscribed_at >= $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at < $4)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $5 OFFSET $6
+        "#,
+        search.pattern,
+        search.status,
+        search.from,
+        search.until,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscribers.")?;
+    Ok((total, subscribers))
+}
+
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber_details(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let memberships = get_memberships(&pool, subscriber_id).await.map_err(e500)?;
+    let mut lists_html = String::new();
+    for membership in &memberships {
+        writeln!(
+            lists_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&membership.name),
+            membership.status,
+            membership.opt_in,
+            membership
+                .consented_at
+                .map(|at| at.to_rfc3339())
+                .unwrap_or_default()
+        )
+        .unwrap();
+    }
+    let mut history_html = String::new();
+    for entry in get_history(&pool, subscriber_id, &subscriber.email)
+        .await
+        .map_err(e500)?
+    {
+        writeln!(
+            history_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            entry.occurred_at.to_rfc3339(),
+            htmlescape::encode_minimal(&entry.event),
+            htmlescape::encode_minimal(&entry.detail)
+        )
+        .unwrap();
+    }
+    let action = |action: &str, label: &str| {
+        format!(
+            r#"<form action="/admin/subscribers/{subscriber_id}/{action}" method="post"><button type="submit">{label}</button></form>"#
+        )
+    };
+    let mut actions_html = String::new();
+    if memberships
+        .iter()
+        .any(|m| m.status == "pending_confirmation")
+    {
+        actions_html.push_str(&action("confirm", "Confirm manually"));
+        actions_html.push_str(&action("resend", "Resend confirmation email"));
+    }
+    if subscriber.status != "unsubscribed" {
+        actions_html.push_str(&action("unsubscribe", "Unsubscribe"));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <ul>
+        <li>Email: {email}</li>
+        <li>Name: {name}</li>
+        <li>Status: {status}</li>
+        <li>Signed up: {subscribed_at}</li>
+    </ul>
+    {actions_html}
+    <form action="/admin/subscribers/{subscriber_id}/name" method="post">
+        <label>Name:
+            <input type="text" name="name" value="{name_attribute}">
+        </label>
+        <button type="submit">Rename</button>
+    </form>
+    <form action="/admin/subscribers/{subscriber_id}/delete" method="post">
+        <button type="submit">Delete subscriber</button>
+    </form>
+    <h2>Lists</h2>
+    <table>
+        <tr><th>List</th><th>Status</th><th>Opt-in</th><th>Consented at</th></tr>
+        {lists_html}
+    </table>
+    <h2>History</h2>
+    <table>
+        <tr><th>When</th><th>Event</th><th>Details</th></tr>
+        {history_html}
+    </table>
+    <p><a href="/admin/consent?email={email_query}">Consent records</a></p>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            email = htmlescape::encode_minimal(&subscriber.email),
+            name = htmlescape::encode_minimal(&subscriber.name),
+            name_attribute = htmlescape::encode_attribute(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+            email_query = urlencoding::encode(&subscriber.email),
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+pub(super) async fn get_subscriber_details(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberDetails,
+        r#"SELECT email, name, status, subscribed_at FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+    Ok(subscriber)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_memberships(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<Membership>, anyhow::Error> {
+    let memberships = sqlx::query_as!(
+        Membership,
+        r#"
+        SELECT l.name, ls.status, ls.opt_in, ls.consented_at
+        FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the list memberships.")?;
+    Ok(memberships)
+}
+
+/// Consent, preference changes, emails and newsletter activity, newest first.
+#[tracing::instrument(skip(pool, email))]
+async fn get_history(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    email: &str,
+) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    let history = sqlx::query_as!(
+        HistoryEntry,
+        r#"
+        SELECT occurred_at as "occurred_at!", event as "event!", detail as "detail!" FROM (
+            SELECT
+                c.occurred_at,
+                'consent: ' || c.event_type as event,
+                concat_ws(', ', l.slug, 'form ' || c.form_id, 'from ' || c.ip_address, c.note)
+                    as detail
+            FROM consent_events c LEFT JOIN lists l ON l.list_id = c.list_id
+            WHERE c.subscriber_id = $1
+            UNION ALL
+            SELECT
+                changed_at,
+                'preference change',
+                field || ': ' || coalesce(old_value, '') || ' -> ' || coalesce(new_value, '')
+            FROM preference_changes
+            WHERE subscriber_id = $1
+            UNION ALL
+            SELECT
+                coalesce(sent_at, created_at),
+                'email: ' || kind,
+                subject || CASE WHEN sent_at IS NULL THEN ' (queued)' ELSE '' END
+            FROM outbox
+            WHERE recipient = $2
+            UNION ALL
+            SELECT d.sent_at, 'email: welcome series', w.subject
+            FROM welcome_deliveries d JOIN welcome_steps w ON w.step_id = d.step_id
+            WHERE d.subscriber_id = $1
+            UNION ALL
+            SELECT e.first_occurred_at, 'newsletter ' || e.event_type, n.title
+            FROM email_events e
+            JOIN newsletter_issues n ON n.newsletter_issue_id = e.newsletter_issue_id
+            WHERE e.subscriber_email = $2
+        ) history
+        ORDER BY occurred_at DESC
+        LIMIT 200
+        "#,
+        subscriber_id,
+        email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber history.")?;
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::like_pattern;
+
+    #[test]
+    fn like_wildcards_are_matched_literally() {
+        assert_eq!(like_pattern("monkey"), "%monkey%");
+        assert_eq!(like_pattern("100%_off"), r"%100\%\_off%");
+    }
+}
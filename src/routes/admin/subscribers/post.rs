@@ -0,0 +1,221 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::get::get_subscriber_details;
+use crate::{
+    authentication::UserId,
+    consent::record_consent_note,
+    domain::{SubscriberEmail, SubscriberName},
+    lists::OptIn,
+    preferences::{record_preference_change, unsubscribe_from_everything},
+    privacy::erase_subscriber,
+    routes::{
+        confirm_membership, generate_subscription_token, queue_confirmation_email, store_token,
+    },
+    startup::{ApplicationBaseUrl, HmacSecret},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct RenameFormData {
+    name: String,
+}
+
+fn details_location(subscriber_id: Uuid) -> String {
+    format!("/admin/subscribers/{subscriber_id}")
+}
+
+async fn pending_list_ids(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT list_id FROM list_subscriptions
+        WHERE subscriber_id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.list_id).collect())
+}
+
+#[tracing::instrument(name = "Confirm a subscriber manually", skip(pool, user_id), fields(user_id=%*user_id))]
+pub async fn confirm_subscriber_manually(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let list_ids = pending_list_ids(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?;
+    if list_ids.is_empty() {
+        FlashMessage::error("This subscriber has nothing left to confirm.").send();
+        return Ok(see_other(&details_location(subscriber_id)));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET used_at = now()
+        WHERE subscriber_id = $1 AND used_at IS NULL
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(e500)?;
+    for list_id in list_ids {
+        confirm_membership(&mut transaction, subscriber_id, list_id, OptIn::Manual)
+            .await
+            .map_err(e500)?;
+        record_consent_note(
+            &mut *transaction,
+            subscriber_id,
+            list_id,
+            "admin_confirmation",
+            "Confirmed by an administrator",
+        )
+        .await
+        .map_err(e500)?;
+    }
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("The subscriber has been confirmed.").send();
+    Ok(see_other(&details_location(subscriber_id)))
+}
+
+#[tracing::instrument(name = "Resend a confirmation email", skip(pool, base_url, user_id), fields(user_id=%*user_id))]
+pub async fn resend_subscriber_confirmation(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber_details(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let list_ids = pending_list_ids(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?;
+    if list_ids.is_empty() {
+        FlashMessage::error("This subscriber has nothing left to confirm.").send();
+        return Ok(see_other(&details_location(subscriber_id)));
+    }
+    let email = SubscriberEmail::parse(subscriber.email).map_err(e500)?;
+    for list_id in list_ids {
+        let token = generate_subscription_token();
+        store_token(&mut transaction, subscriber_id, list_id, &token)
+            .await
+            .map_err(e500)?;
+        queue_confirmation_email(&mut transaction, &email, &base_url.0, &token)
+            .await
+            .map_err(e500)?;
+    }
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("A new confirmation email has been sent.").send();
+    Ok(see_other(&details_location(subscriber_id)))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(pool, user_id), fields(user_id=%*user_id))]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber_details(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if subscriber.status == "unsubscribed" {
+        FlashMessage::error("This subscriber is already unsubscribed.").send();
+        return Ok(see_other(&details_location(subscriber_id)));
+    }
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    unsubscribe_from_everything(&mut transaction, subscriber_id, &subscriber.status)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("The subscriber has been unsubscribed.").send();
+    Ok(see_other(&details_location(subscriber_id)))
+}
+
+#[tracing::instrument(name = "Rename a subscriber", skip(form, pool, user_id), fields(user_id=%*user_id))]
+pub async fn rename_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<RenameFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let Some(subscriber) = get_subscriber_details(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let name = match SubscriberName::parse(form.0.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&details_location(subscriber_id)));
+        }
+    };
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET name = $2 WHERE id = $1"#,
+        subscriber_id,
+        name.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(e500)?;
+    record_preference_change(
+        &mut *transaction,
+        subscriber_id,
+        "name",
+        Some(&subscriber.name),
+        Some(name.as_ref()),
+    )
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("The name has been updated.").send();
+    Ok(see_other(&details_location(subscriber_id)))
+}
+
+/// Removes the subscriber and their data. Unlike an erasure request, the
+/// address can sign up or be imported again.
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(pool, hmac_secret, user_id),
+    fields(user_id=%*user_id)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    if !erase_subscriber(&mut transaction, &hmac_secret.0, subscriber_id, None)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("The subscriber has been deleted.").send();
+    Ok(see_other("/admin/subscribers"))
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::list_tags;
+pub use post::{update_subscriber_tags, update_tags};
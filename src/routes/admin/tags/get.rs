@@ -0,0 +1,80 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::utils::e500;
+
+struct TagSummary {
+    tag: String,
+    subscriber_count: i64,
+}
+
+pub async fn list_tags(
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let tags = get_tag_summaries(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for tag in tags {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&tag.tag),
+            tag.subscriber_count
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber Tags</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Tag</th><th>Subscribers</th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/tags" method="post">
+        <label>Subscriber email:
+            <input type="email" name="email">
+        </label>
+        <label>Tags (comma separated):
+            <input type="text" name="tags">
+        </label>
+        <button type="submit" name="action" value="add">Add tags</button>
+        <button type="submit" name="action" value="remove">Remove tags</button>
+    </form>
+    <p><a href="/admin/segments">Segments</a></p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_tag_summaries(pool: &PgPool) -> Result<Vec<TagSummary>, anyhow::Error> {
+    let tags = sqlx::query_as!(
+        TagSummary,
+        r#"
+        SELECT tag, COUNT(*) as "subscriber_count!"
+        FROM subscriber_tags
+        GROUP BY tag
+        ORDER BY tag
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscriber tags.")?;
+    Ok(tags)
+}
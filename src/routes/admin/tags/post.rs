@@ -0,0 +1,117 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::SubscriptionSettings,
+    domain::SubscriberEmail,
+    routes::get_subscriber_id,
+    tags::{add_tags, get_tags, parse_tag, parse_tag_list, remove_tags},
+    utils::{e400, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    tags: String,
+    action: String,
+}
+
+#[tracing::instrument(name = "Update subscriber tags", skip(form, pool, settings), fields(email = %form.email))]
+pub async fn update_tags(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tags = match parse_tag_list(&form.tags) {
+        Ok(tags) if !tags.is_empty() => tags,
+        Ok(_) => {
+            FlashMessage::error("Enter at least one tag.").send();
+            return Ok(see_other("/admin/tags"));
+        }
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/tags"));
+        }
+    };
+    let Some(subscriber_id) = get_subscriber_id_by_email(&pool, &settings, &form.email)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("There is no subscriber with this email address.").send();
+        return Ok(see_other("/admin/tags"));
+    };
+    match form.action.as_str() {
+        "add" => add_tags(pool.get_ref(), subscriber_id, &tags).await,
+        "remove" => remove_tags(pool.get_ref(), subscriber_id, &tags).await,
+        _ => return Err(e400("The action must be either add or remove.")),
+    }
+    .map_err(e500)?;
+    FlashMessage::info("The subscriber's tags have been updated.").send();
+    Ok(see_other("/admin/tags"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagUpdate {
+    email: String,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberTags {
+    email: String,
+    tags: Vec<String>,
+}
+
+/// JSON counterpart of the tags form, for scripts and integrations.
+#[tracing::instrument(name = "Update subscriber tags via the API", skip(body, pool, settings), fields(email = %body.email))]
+pub async fn update_subscriber_tags(
+    body: web::Json<TagUpdate>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let parse = |tags: &[String]| {
+        tags.iter()
+            .map(|t| parse_tag(t))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let add = parse(&body.add).map_err(e400)?;
+    let remove = parse(&body.remove).map_err(e400)?;
+    let Some(subscriber_id) = get_subscriber_id_by_email(&pool, &settings, &body.email)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    add_tags(&mut *transaction, subscriber_id, &add)
+        .await
+        .map_err(e500)?;
+    remove_tags(&mut *transaction, subscriber_id, &remove)
+        .await
+        .map_err(e500)?;
+    let tags = get_tags(&mut *transaction, subscriber_id)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    Ok(HttpResponse::Ok().json(SubscriberTags {
+        email: body.0.email,
+        tags,
+    }))
+}
+
+#[tracing::instrument(skip(pool, settings))]
+async fn get_subscriber_id_by_email(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+    email: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    match SubscriberEmail::parse(email.to_string()) {
+        Ok(email) => get_subscriber_id(pool, &email.key(&settings.alias_folding_domains)).await,
+        Err(_) => Ok(None),
+    }
+}
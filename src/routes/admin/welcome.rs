@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::list_welcome_steps;
+pub use post::create_welcome_step;
@@ -0,0 +1,101 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::utils::e500;
+
+struct WelcomeStepSummary {
+    position: i32,
+    subject: String,
+    delay_hours: i32,
+    sent_count: i64,
+    scheduled_count: i64,
+}
+
+pub async fn list_welcome_steps(
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let steps = get_welcome_step_summaries(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for step in steps {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}h</td><td>{}</td><td>{}</td></tr>",
+            step.position,
+            htmlescape::encode_minimal(&step.subject),
+            step.delay_hours,
+            step.sent_count,
+            step.scheduled_count
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Welcome series</title>
+</head>
+<body>
+    {msg_html}
+    <p>New subscribers get these emails once they confirm, in order.</p>
+    <table>
+        <tr><th>Step</th><th>Subject</th><th>Sent after</th><th>Sent</th><th>Scheduled</th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/welcome" method="post">
+        <label>Subject:
+            <input type="text" name="subject">
+        </label>
+        <br>
+        <label>Hours after confirmation:
+            <input type="number" name="delay_hours" min="0" value="0">
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea name="html_content" rows="10" cols="50"></textarea>
+        </label>
+        <br>
+        <label>Text content:<br>
+            <textarea name="text_content" rows="10" cols="50"></textarea>
+        </label>
+        <br>
+        <button type="submit">Add step</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_welcome_step_summaries(
+    pool: &PgPool,
+) -> Result<Vec<WelcomeStepSummary>, anyhow::Error> {
+    let steps = sqlx::query_as!(
+        WelcomeStepSummary,
+        r#"
+        SELECT
+            w.position,
+            w.subject,
+            w.delay_hours,
+            (SELECT COUNT(*) FROM welcome_deliveries d WHERE d.step_id = w.step_id) as "sent_count!",
+            (SELECT COUNT(*) FROM welcome_queue q WHERE q.step_id = w.step_id) as "scheduled_count!"
+        FROM welcome_steps w
+        ORDER BY w.position
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the welcome series.")?;
+    Ok(steps)
+}
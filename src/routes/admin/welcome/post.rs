@@ -0,0 +1,79 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    subject: String,
+    html_content: String,
+    text_content: String,
+    delay_hours: String,
+}
+
+/// Steps are appended to the end of the series, so each one has to go out
+/// no earlier than the one before it.
+#[tracing::instrument(name = "Add a welcome series step", skip(form, pool), fields(subject = %form.subject))]
+pub async fn create_welcome_step(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subject = form.subject.trim();
+    if subject.is_empty() || form.html_content.trim().is_empty() {
+        FlashMessage::error("A welcome email needs a subject and some content.").send();
+        return Ok(see_other("/admin/welcome"));
+    }
+    let Ok(delay_hours) = form.delay_hours.trim().parse::<i32>() else {
+        FlashMessage::error(format!(
+            "{} is not a valid number of hours.",
+            form.delay_hours
+        ))
+        .send();
+        return Ok(see_other("/admin/welcome"));
+    };
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let last = sqlx::query!(
+        r#"
+        SELECT position, delay_hours FROM welcome_steps
+        ORDER BY position DESC
+        LIMIT 1
+        FOR UPDATE
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(e500)?;
+    let (position, min_delay) = last.map_or((1, 0), |l| (l.position + 1, l.delay_hours));
+    if delay_hours < min_delay {
+        FlashMessage::error(format!(
+            "Step {} has to go out at least {} hours after confirmation.",
+            position, min_delay
+        ))
+        .send();
+        return Ok(see_other("/admin/welcome"));
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO welcome_steps (step_id, position, subject, html_content, text_content, delay_hours)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        position,
+        subject,
+        form.html_content,
+        form.text_content,
+        delay_hours
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info(format!(
+        "Step {} has been added to the welcome series.",
+        position
+    ))
+    .send();
+    Ok(see_other("/admin/welcome"))
+}
@@ -1,10 +1,15 @@
-use actix_web::{error::InternalError, http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::{
+    error::InternalError, http::StatusCode, web, HttpRequest, HttpResponse, ResponseError,
+};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{self, validate_credentials, Credentials},
+    authentication::{
+        self, validate_credentials, Credentials, LoginAttempt, LoginGate, LoginThrottle,
+    },
+    client_ip::client_ip,
     routes::error_chain_fmt,
     session_state::TypedSession,
     utils::see_other,
@@ -14,6 +19,8 @@ use crate::{
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    Throttled(String),
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -34,20 +41,39 @@ pub struct FormData {
     password: Secret<String>,
 }
 
-#[tracing::instrument(skip(form, pool, session), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
+#[tracing::instrument(skip(form, pool, session, request, throttle), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    request: HttpRequest,
+    throttle: web::Data<LoginThrottle>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
+    let ip_address = client_ip(&request).map(|ip| ip.to_string());
+    tracing::Span::current().record("username", &tracing::field::display(&username));
+    let locks_account = match throttle
+        .begin_attempt(pool.get_ref(), &username, ip_address.as_deref())
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?
+    {
+        LoginAttempt::Started { locks_account } => locks_account,
+        LoginAttempt::Refused(gate) => {
+            let message = gate.message().unwrap_or_default();
+            return Err(login_redirect(LoginError::Throttled(message)));
+        }
+    };
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            throttle
+                .clear(pool.get_ref(), &username)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
             session.renew();
             session
                 .insert_user_id(user_id)
@@ -56,7 +82,17 @@ pub async fn login(
         }
         Err(e) => {
             let e = match e {
-                authentication::AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                authentication::AuthError::InvalidCredentials(_) => {
+                    failed_login(
+                        &throttle,
+                        &pool,
+                        &username,
+                        ip_address.as_deref(),
+                        locks_account,
+                        e.into(),
+                    )
+                    .await
+                }
                 authentication::AuthError::UnexpectedError(_) => {
                     LoginError::UnexpectedError(e.into())
                 }
@@ -66,6 +102,31 @@ pub async fn login(
     }
 }
 
+/// Tells the admin about a lockout this failure caused, and the client when
+/// it got them locked out. The attempt was already recorded as a failure.
+async fn failed_login(
+    throttle: &LoginThrottle,
+    pool: &PgPool,
+    username: &str,
+    ip_address: Option<&str>,
+    locks_account: bool,
+    e: anyhow::Error,
+) -> LoginError {
+    if locks_account {
+        if let Err(e) = throttle.notify_lockout(pool, username, ip_address).await {
+            return LoginError::UnexpectedError(e.into());
+        }
+    }
+    let gate = match throttle.check(pool, username, ip_address).await {
+        Ok(gate) => gate,
+        Err(e) => return LoginError::UnexpectedError(e.into()),
+    };
+    match gate {
+        LoginGate::LockedOut(..) => LoginError::Throttled(gate.message().unwrap_or_default()),
+        _ => LoginError::AuthError(e),
+    }
+}
+
 fn login_redirect(e: LoginError) -> InternalError<LoginError> {
     FlashMessage::error(e.to_string()).send();
     let response = see_other("/login");
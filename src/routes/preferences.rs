@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::preferences_form;
+pub use post::{unsubscribe, update_preferences};
@@ -0,0 +1,186 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{preferences::get_subscriber_id_from_preferences_token, utils::e500};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    token: String,
+}
+
+pub(super) struct SubscriberPreferences {
+    pub name: String,
+    pub email: String,
+    pub status: String,
+    pub frequency: String,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+pub(super) struct ListMembership {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub status: Option<String>,
+}
+
+pub async fn preferences_form(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber_id) = get_subscriber_id_from_preferences_token(&**pool, &query.token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    let subscriber = get_subscriber_preferences(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    let lists = get_list_memberships(&**pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    let token = htmlescape::encode_attribute(&query.token);
+    if subscriber.status == "unsubscribed" {
+        return Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(format!(
+                r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {msg_html}
+    <p>{} is unsubscribed and will not receive any more emails.</p>
+</body>
+</html>"#,
+                htmlescape::encode_minimal(&subscriber.email)
+            )));
+    }
+    let mut lists_html = String::new();
+    for list in lists {
+        writeln!(
+            lists_html,
+            r#"<label><input type="checkbox" name="list-{}" value="on"{}> {}</label><br>"#,
+            list.slug,
+            if list.status.as_deref() == Some("confirmed") {
+                " checked"
+            } else {
+                ""
+            },
+            htmlescape::encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+    let checked = |frequency: &str| {
+        if subscriber.frequency == frequency {
+            " checked"
+        } else {
+            ""
+        }
+    };
+    let paused_html = match subscriber.paused_until {
+        Some(until) if until > Utc::now() => format!(
+            "<p>Your emails are paused until {}.</p>",
+            until.format("%Y-%m-%d")
+        ),
+        _ => String::new(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {msg_html}
+    <p>Preferences for {email}</p>
+    {paused_html}
+    <form action="/preferences" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>Name:
+            <input type="text" name="name" value="{name}">
+        </label>
+        <fieldset>
+            <legend>Lists</legend>
+            {lists_html}
+        </fieldset>
+        <fieldset>
+            <legend>How often</legend>
+            <label><input type="radio" name="frequency" value="every_issue"{every_issue}> Every issue</label>
+            <label><input type="radio" name="frequency" value="weekly_digest"{weekly_digest}> A weekly digest</label>
+        </fieldset>
+        <label>Pause emails for
+            <input type="number" name="pause_weeks" min="0" max="52"> week(s) (0 to resume)
+        </label>
+        <button type="submit">Save preferences</button>
+    </form>
+    <form action="/preferences/unsubscribe" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <button type="submit">Unsubscribe from everything</button>
+    </form>
+    <p><a href="/privacy">Download or delete your data</a></p>
+</body>
+</html>"#,
+            email = htmlescape::encode_minimal(&subscriber.email),
+            name = htmlescape::encode_attribute(&subscriber.name),
+            every_issue = checked("every_issue"),
+            weekly_digest = checked("weekly_digest"),
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+pub(super) async fn get_subscriber_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<SubscriberPreferences, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberPreferences,
+        r#"
+        SELECT name, email, status, frequency, paused_until
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the subscriber's preferences.")?;
+    Ok(subscriber)
+}
+
+#[tracing::instrument(skip(executor))]
+pub(super) async fn get_list_memberships(
+    executor: impl sqlx::PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListMembership>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT l.list_id, l.slug, l.name, ls.status as "status?"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls
+            ON ls.list_id = l.list_id AND ls.subscriber_id = $1
+        ORDER BY l.created_at, l.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to retrieve the subscriber's lists.")?;
+    Ok(lists)
+}
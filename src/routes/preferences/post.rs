@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::get::{get_list_memberships, get_subscriber_preferences};
+use crate::{
+    consent::record_consent_note,
+    digest::DIGEST_INTERVAL_DAYS,
+    domain::SubscriberName,
+    lists::OptIn,
+    preferences::{
+        get_subscriber_id_from_preferences_token, record_preference_change,
+        unsubscribe_from_everything, Frequency,
+    },
+    routes::{add_to_list, confirm_membership},
+    utils::{e400, e500, see_other},
+};
+
+const MAX_PAUSE_WEEKS: i64 = 52;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: String,
+    name: String,
+    frequency: String,
+    #[serde(default)]
+    pause_weeks: String,
+    /// Checked list boxes, posted as `list-<slug>=on`.
+    #[serde(flatten)]
+    lists: HashMap<String, String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeFormData {
+    token: String,
+}
+
+fn preferences_location(token: &str) -> String {
+    format!("/preferences?token={}", urlencoding::encode(token))
+}
+
+#[tracing::instrument(name = "Update subscriber preferences", skip_all)]
+pub async fn update_preferences(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let Some(subscriber_id) = get_subscriber_id_from_preferences_token(&**pool, &form.token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let location = preferences_location(&form.token);
+    let name = match SubscriberName::parse(form.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&location));
+        }
+    };
+    let frequency = Frequency::try_from(form.frequency).map_err(e400)?;
+    let pause_weeks = match form.pause_weeks.trim() {
+        "" => None,
+        weeks => match weeks.parse::<i64>() {
+            Ok(weeks) if (0..=MAX_PAUSE_WEEKS).contains(&weeks) => Some(weeks),
+            _ => {
+                FlashMessage::error(format!(
+                    "You can pause your emails for up to {} weeks.",
+                    MAX_PAUSE_WEEKS
+                ))
+                .send();
+                return Ok(see_other(&location));
+            }
+        },
+    };
+    let current = get_subscriber_preferences(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    if current.status == "unsubscribed" {
+        return Ok(see_other(&location));
+    }
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    if name.as_ref() != current.name {
+        sqlx::query!(
+            r#"UPDATE subscriptions SET name = $2 WHERE id = $1"#,
+            subscriber_id,
+            name.as_ref()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(e500)?;
+        record_preference_change(
+            &mut *transaction,
+            subscriber_id,
+            "name",
+            Some(&current.name),
+            Some(name.as_ref()),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    if frequency.as_str() != current.frequency {
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET
+                frequency = $2,
+                next_digest_at = CASE WHEN $2 = 'weekly_digest'
+                    THEN now() + make_interval(days => $3)
+                    ELSE NULL END
+            WHERE id = $1
+            "#,
+            subscriber_id,
+            frequency.as_str(),
+            DIGEST_INTERVAL_DAYS
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(e500)?;
+        record_preference_change(
+            &mut *transaction,
+            subscriber_id,
+            "frequency",
+            Some(&current.frequency),
+            Some(frequency.as_str()),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    if let Some(weeks) = pause_weeks {
+        let paused_until = (weeks > 0).then(|| Utc::now() + Duration::weeks(weeks));
+        sqlx::query!(
+            r#"UPDATE subscriptions SET paused_until = $2 WHERE id = $1"#,
+            subscriber_id,
+            paused_until
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(e500)?;
+        record_preference_change(
+            &mut *transaction,
+            subscriber_id,
+            "paused_until",
+            current.paused_until.map(|d| d.to_rfc3339()).as_deref(),
+            paused_until.map(|d| d.to_rfc3339()).as_deref(),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    update_list_memberships(&mut transaction, subscriber_id, &form.lists)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("Your preferences have been saved.").send();
+    Ok(see_other(&location))
+}
+
+/// Lists are joined or left depending on whether their box was checked.
+/// The subscriber already confirmed their address, so joins are confirmed
+/// straight away, as a single opt-in given on the preferences page.
+async fn update_list_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    checked: &HashMap<String, String>,
+) -> Result<(), anyhow::Error> {
+    let memberships = get_list_memberships(&mut **transaction, subscriber_id).await?;
+    for list in memberships {
+        let subscribed = list.status.as_deref() == Some("confirmed");
+        let wanted = checked.contains_key(&format!("list-{}", list.slug));
+        if subscribed == wanted {
+            continue;
+        }
+        let status = if wanted {
+            add_to_list(transaction, list.list_id, subscriber_id)
+                .await
+                .context("Failed to add a list membership")?;
+            confirm_membership(transaction, subscriber_id, list.list_id, OptIn::Single)
+                .await
+                .context("Failed to confirm a list membership")?;
+            record_consent_note(
+                &mut **transaction,
+                subscriber_id,
+                list.list_id,
+                "preferences",
+                "Joined on the preferences page",
+            )
+            .await
+            .context("Failed to record consent to a list")?;
+            "confirmed"
+        } else {
+            sqlx::query!(
+                r#"
+                UPDATE list_subscriptions SET status = 'unsubscribed', pending_since = NULL
+                WHERE list_id = $1 AND subscriber_id = $2
+                "#,
+                list.list_id,
+                subscriber_id
+            )
+            .execute(&mut **transaction)
+            .await
+            .context("Failed to leave a list")?;
+            "unsubscribed"
+        };
+        record_preference_change(
+            &mut **transaction,
+            subscriber_id,
+            &format!("list:{}", list.slug),
+            list.status.as_deref(),
+            Some(status),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Unsubscribe from every list", skip_all)]
+pub async fn unsubscribe(
+    form: web::Form<UnsubscribeFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber_id) = get_subscriber_id_from_preferences_token(&**pool, &form.token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let current = get_subscriber_preferences(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    unsubscribe_from_everything(&mut transaction, subscriber_id, &current.status)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("You have been unsubscribed.").send();
+    Ok(see_other(&preferences_location(&form.token)))
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{export_data, manage_data_form, privacy_request_form};
+pub use post::{erase_data, request_privacy_link};
@@ -0,0 +1,102 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    privacy::{export_subscriber_data, get_subscriber_id_from_privacy_token},
+    utils::e500,
+};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    token: String,
+}
+
+pub async fn privacy_request_form(
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter your address and we will email you a link to download or delete the data we hold about you.</p>
+    <form action="/privacy" method="post">
+        <label>Email:
+            <input type="text" name="email">
+        </label>
+        <button type="submit">Send me a link</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn manage_data_form(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if get_subscriber_id_from_privacy_token(&**pool, &query.token)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let token = htmlescape::encode_attribute(&query.token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    <p><a href="/privacy/export?token={token}">Download everything we store about you</a> (JSON)</p>
+    <p>Deleting your data unsubscribes you from every list. It cannot be undone.</p>
+    <form action="/privacy/erase" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <button type="submit">Delete my data</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Export subscriber data", skip_all)]
+pub async fn export_data(
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber_id) = get_subscriber_id_from_privacy_token(&**pool, &query.token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let data = export_subscriber_data(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("my_data.json".into())],
+        })
+        .json(data))
+}
@@ -0,0 +1,118 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    bot_protection::{record_confirmation_send, BotProtection, SendAllowance},
+    configuration::SubscriptionSettings,
+    consent::ConsentContext,
+    domain::SubscriberEmail,
+    outbox::{enqueue_email, OutboxMessage},
+    privacy::{
+        create_privacy_token, erase_subscriber, get_subscriber_id_from_privacy_token, privacy_url,
+        PRIVACY_TOKEN_TTL_HOURS,
+    },
+    routes::get_subscriber_id,
+    startup::{ApplicationBaseUrl, HmacSecret},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct RequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EraseFormData {
+    token: String,
+}
+
+/// Emails a link to the data page, so only the owner of the address can use
+/// it. The response is the same whether or not the address is known.
+/// Links count towards the same send limits as confirmation emails.
+#[tracing::instrument(name = "Request a privacy link", skip_all)]
+pub async fn request_privacy_link(
+    request: HttpRequest,
+    form: web::Form<RequestFormData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    protection: web::Data<BotProtection>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Ok(email) = SubscriberEmail::parse(form.0.email) {
+        let email_key = email.key(&settings.alias_folding_domains);
+        let ip_address = ConsentContext::from_request(&request).ip_address;
+        let mut transaction = pool.begin().await.map_err(e500)?;
+        let allowance = protection
+            .limits
+            .check(&mut *transaction, &email_key, ip_address.as_deref())
+            .await
+            .map_err(e500)?;
+        if allowance == SendAllowance::IpLimited {
+            FlashMessage::error("Too many requests from this address. Please try again later.")
+                .send();
+            return Ok(see_other("/privacy"));
+        }
+        let subscriber_id = get_subscriber_id(&mut *transaction, &email_key)
+            .await
+            .map_err(e500)?;
+        // The address already has recent links in its inbox.
+        if let Some(subscriber_id) = subscriber_id.filter(|_| allowance == SendAllowance::Allowed) {
+            let token = create_privacy_token(&mut *transaction, subscriber_id)
+                .await
+                .map_err(e500)?;
+            let url = privacy_url(&base_url.0, &token);
+            enqueue_email(
+                &mut *transaction,
+                OutboxMessage {
+                    kind: "privacy",
+                    recipient: &email,
+                    subject: "Your data",
+                    html_content: &format!(
+                        r#"Follow <a href="{}">this link</a> to download or delete your data.<br/>It expires in {} hours."#,
+                        htmlescape::encode_minimal(&url),
+                        PRIVACY_TOKEN_TTL_HOURS
+                    ),
+                    text_content: &format!(
+                        "Visit {} to download or delete your data.\nIt expires in {} hours.",
+                        url, PRIVACY_TOKEN_TTL_HOURS
+                    ),
+                },
+            )
+            .await
+            .map_err(e500)?;
+            record_confirmation_send(&mut *transaction, &email_key, ip_address.as_deref())
+                .await
+                .map_err(e500)?;
+        }
+        transaction.commit().await.map_err(e500)?;
+    }
+    FlashMessage::info("If that address is subscribed, we have emailed it a link.").send();
+    Ok(see_other("/privacy"))
+}
+
+#[tracing::instrument(name = "Erase subscriber data", skip_all)]
+pub async fn erase_data(
+    form: web::Form<EraseFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let Some(subscriber_id) = get_subscriber_id_from_privacy_token(&mut *transaction, &form.token)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    erase_subscriber(
+        &mut transaction,
+        &hmac_secret.0,
+        subscriber_id,
+        Some("erasure_request"),
+    )
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+    FlashMessage::info("Your data has been deleted.").send();
+    Ok(see_other("/privacy"))
+}
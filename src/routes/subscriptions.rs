@@ -1,4 +1,6 @@
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use std::collections::HashMap;
+
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
@@ -6,16 +8,52 @@ use sqlx::{Executor, PgPool, Postgres, Transaction};
 use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
+mod get;
+
+pub use get::subscribe_form;
+
+use super::confirm_membership;
 use crate::{
+    bot_protection::{
+        record_confirmation_send, BotProtection, SendAllowance, SignupChallenge, Verdict,
+    },
+    configuration::SubscriptionSettings,
+    consent::{record_confirmation_consent, record_signup_consent, ConsentContext},
+    custom_fields::{get_custom_fields, parse_field_values, set_field_values},
+    deliverability::DeliverabilityChecker,
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    lists::{get_list_by_slug, OptIn, DEFAULT_LIST_SLUG},
+    outbox::{enqueue_email, OutboxMessage},
     startup::ApplicationBaseUrl,
+    tags::{add_tags, parse_tag_list},
 };
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    #[serde(default)]
+    list: Option<String>,
+    #[serde(default)]
+    tags: Option<String>,
+    /// Which signup form was used, for the consent records.
+    #[serde(default)]
+    form_id: Option<String>,
+    /// Version of the consent text shown next to the form.
+    #[serde(default)]
+    consent_version: Option<String>,
+    /// The honeypot field, see `HONEYPOT_FIELD`.
+    #[serde(default)]
+    website: Option<String>,
+    #[serde(default)]
+    form_stamp: Option<String>,
+    /// For clients that do not send the CAPTCHA response under the field
+    /// name of the provider's widget.
+    #[serde(default)]
+    captcha_response: Option<String>,
+    /// Custom field values, keyed by field key.
+    #[serde(flatten)]
+    fields: HashMap<String, serde_json::Value>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -32,6 +70,8 @@ impl TryFrom<FormData> for NewSubscriber {
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("Too many signups from this address. Please try again later.")]
+    RateLimited,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -46,57 +86,242 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-// Could add pre check on status or exists
+/// Accepts both the HTML form and a JSON body with the same fields.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, db_pool, email_client, base_url),
+    skip(form, request, db_pool, base_url, settings, deliverability, protection),
     fields(
-        subscriber_name = %form.name,
-        subscriber_email = %form.email
+        subscriber_name = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty,
+        list = tracing::field::Empty
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    form: web::Either<web::Form<FormData>, web::Json<FormData>>,
+    request: HttpRequest,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    deliverability: web::Data<DeliverabilityChecker>,
+    protection: web::Data<BotProtection>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into()?;
+    let from_form = matches!(form, web::Either::Left(_));
+    let mut form = form.into_inner();
+    let list_slug = form
+        .list
+        .clone()
+        .unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    tracing::Span::current()
+        .record("subscriber_name", &tracing::field::display(&form.name))
+        .record("subscriber_email", &tracing::field::display(&form.email))
+        .record("list", &tracing::field::display(&list_slug));
+    let consent = ConsentContext::from_request(&request);
+    let captcha_field = protection
+        .captcha
+        .as_ref()
+        .and_then(|c| c.widget.as_ref())
+        .map(|w| w.provider.response_field());
+    let captcha_response = form.captcha_response.take().or_else(|| {
+        captcha_field
+            .and_then(|field| form.fields.get(field))
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    });
+    let form_stamp = form.form_stamp.take();
+    let challenge = SignupChallenge {
+        honeypot: form.website.as_deref(),
+        form_stamp: form_stamp.as_deref(),
+        captcha_response: captcha_response.as_deref(),
+        ip_address: consent.ip_address.as_deref(),
+        from_form,
+    };
+    match protection.check(&challenge).await? {
+        Verdict::Human => {}
+        Verdict::Bot(reason) => {
+            // Same response as a real signup, so bots learn nothing.
+            tracing::warn!(reason, "Dropped a signup that looks automated");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        Verdict::Rejected(message) => {
+            return Err(SubscribeError::ValidationError(message.to_string()))
+        }
+    }
+    let tags = parse_tag_list(form.tags.as_deref().unwrap_or_default())?;
+    let custom_fields = get_custom_fields(db_pool.get_ref())
+        .await
+        .context("Failed to load the custom fields")?;
+    let field_values = parse_field_values(&custom_fields, &std::mem::take(&mut form.fields))?;
+    let form_id = form.form_id.take();
+    let consent_version = form
+        .consent_version
+        .take()
+        .unwrap_or_else(|| settings.consent_text_version.clone());
+    let new_subscriber: NewSubscriber = form.try_into()?;
+    deliverability
+        .check(db_pool.get_ref(), &new_subscriber.email)
+        .await?;
+    let list = get_list_by_slug(db_pool.get_ref(), &list_slug)
+        .await
+        .context("Failed to look up the mailing list")?
+        .ok_or_else(|| {
+            SubscribeError::ValidationError(format!("{} is not a known mailing list.", list_slug))
+        })?;
 
     let mut transaction = db_pool
         .begin()
         .await
         .context("Failed to acquire a postgres conn from pool")?;
+    // Claimed along with the signup, so a rejected attempt can be retried.
+    if let Some(stamp) = form_stamp.as_deref().filter(|s| !s.is_empty()) {
+        if !protection
+            .claim_form_stamp(&mut *transaction, stamp)
+            .await?
+        {
+            return Err(SubscribeError::ValidationError(
+                "This form has already been submitted. Please reload the page to sign up again."
+                    .into(),
+            ));
+        }
+    }
 
-    let sub_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let email_key = new_subscriber.email.key(&settings.alias_folding_domains);
+    let sub_id = match insert_subscriber(&mut transaction, &new_subscriber, &email_key)
+        .await
+        .context("Failed inserting new subscriber.")?
+    {
+        Some(sub_id) => sub_id,
+        None => get_subscriber_id(&mut *transaction, &email_key)
+            .await
+            .context("Failed to look up an existing subscriber.")?
+            .context("The existing subscriber could not be found.")?,
+    };
+    // Repeat signups all get the same neutral response, whatever the
+    // current status of the address.
+    match get_list_status(&mut transaction, list.list_id, sub_id)
+        .await
+        .context("Failed to look up the list membership.")?
+        .as_deref()
+    {
+        Some("confirmed") => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store new sub.")?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+        Some("pending_confirmation") => {}
+        Some(_) => resubscribe(&mut transaction, list.list_id, sub_id)
+            .await
+            .context("Failed to restart the double opt-in.")?,
+        None => add_to_list(&mut transaction, list.list_id, sub_id)
+            .await
+            .context("Failed adding the subscriber to the mailing list.")?,
+    }
+    add_tags(&mut *transaction, sub_id, &tags)
+        .await
+        .context("Failed tagging the subscriber.")?;
+    set_field_values(&mut *transaction, sub_id, &field_values)
         .await
-        .context("Failed inserting new subscriber.")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, sub_id, &subscription_token)
+        .context("Failed saving the custom field values.")?;
+    record_signup_consent(
+        &mut *transaction,
+        sub_id,
+        list.list_id,
+        &consent,
+        form_id.as_deref(),
+        &consent_version,
+    )
+    .await
+    .context("Failed to record the consent given at signup.")?;
+    if list.opt_in(settings.default_opt_in) == OptIn::Single {
+        // The welcome email is held to the same limits as confirmation
+        // emails, so signups cannot be used to flood an inbox.
+        let allowance = protection
+            .limits
+            .check(&mut *transaction, &email_key, consent.ip_address.as_deref())
+            .await
+            .context("Failed to check the confirmation send limits")?;
+        if allowance == SendAllowance::IpLimited {
+            return Err(SubscribeError::RateLimited);
+        }
+        let series_scheduled =
+            confirm_membership(&mut transaction, sub_id, list.list_id, OptIn::Single)
+                .await
+                .context("Failed to confirm a single opt-in subscriber.")?;
+        record_confirmation_consent(&mut *transaction, sub_id, list.list_id, &consent)
+            .await
+            .context("Failed to record the consent confirmation.")?;
+        if allowance == SendAllowance::Allowed {
+            // A welcome series, when there is one, does the welcoming.
+            if !series_scheduled {
+                queue_welcome_email(&mut transaction, &new_subscriber.email, &list.name)
+                    .await
+                    .context("Failed to queue a welcome email")?;
+            }
+            record_confirmation_send(&mut *transaction, &email_key, consent.ip_address.as_deref())
+                .await
+                .context("Failed to record the confirmation send")?;
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store new sub.")?;
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let subscription_token =
+        match get_pending_token(&mut transaction, sub_id, list.list_id, settings.token_ttl())
+            .await
+            .context("Failed to look up a pending subscription token")?
+        {
+            Some(token) => token,
+            None => {
+                let token = generate_subscription_token();
+                store_token(&mut transaction, sub_id, list.list_id, &token)
+                    .await
+                    .context("Failed saving token to database")?;
+                token
+            }
+        };
+
+    match protection
+        .limits
+        .check(&mut *transaction, &email_key, consent.ip_address.as_deref())
         .await
-        .context("Failed saving token to database")?;
+        .context("Failed to check the confirmation send limits")?
+    {
+        SendAllowance::Allowed => {
+            queue_confirmation_email(
+                &mut transaction,
+                &new_subscriber.email,
+                &base_url.0,
+                &subscription_token,
+            )
+            .await
+            .context("Failed to queue a confirmation email")?;
+            record_confirmation_send(&mut *transaction, &email_key, consent.ip_address.as_deref())
+                .await
+                .context("Failed to record the confirmation send")?;
+        }
+        SendAllowance::IpLimited => return Err(SubscribeError::RateLimited),
+        // The address already has recent links in its inbox.
+        SendAllowance::EmailLimited => {}
+    }
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store new sub.")?;
-
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email")?;
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Returns `None` when a subscriber with the same key already exists, even
+/// if they were added by a signup running at the same time.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(transaction, new_subscriber)
@@ -104,21 +329,111 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    let query = sqlx::query!(
+    email_key: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation')
+        ON CONFLICT (email_key) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
+        email_key,
         new_subscriber.name.as_ref(),
         Utc::now()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
+/// Looks a subscriber up by the key of their address, see
+/// [`SubscriberEmail::key`].
+#[tracing::instrument(name = "Looking up an existing subscriber", skip_all)]
+pub async fn get_subscriber_id(
+    executor: impl Executor<'_, Database = Postgres>,
+    email_key: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email_key = $1"#,
+        email_key
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Looking up a list membership", skip(transaction))]
+pub async fn get_list_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT status FROM list_subscriptions
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.status))
+}
+
+/// Someone who unsubscribed has to confirm their address again before
+/// receiving anything.
+#[tracing::instrument(name = "Restarting the double opt-in", skip(transaction))]
+pub async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'pending_confirmation', pending_since = now(), reminders_sent = 0,
+            last_reminder_at = NULL
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'pending_confirmation'
+        WHERE id = $1 AND status = 'unsubscribed'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Adding the subscriber to a mailing list", skip(transaction))]
+pub async fn add_to_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, pending_since)
+        VALUES ($1, $2, 'pending_confirmation', now())
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING
+        "#,
+        list_id,
+        subscriber_id
     );
     transaction.execute(query).await?;
-    Ok(subscriber_id)
+    Ok(())
 }
+
 pub struct StoreTokenError(sqlx::Error);
 impl std::fmt::Display for StoreTokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -147,17 +462,51 @@ impl std::error::Error for StoreTokenError {
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)"#,
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+        VALUES ($1, $2, $3)
+        "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id
     );
     transaction.execute(query).await.map_err(StoreTokenError)?;
     Ok(())
 }
 
+/// Pending subscribers who sign up again get their existing token back,
+/// unless it has been used or is about to expire.
+#[tracing::instrument(name = "Looking up a pending subscription token", skip(transaction))]
+pub async fn get_pending_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    ttl: chrono::Duration,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT subscription_token FROM subscription_tokens
+        WHERE
+            subscriber_id = $1 AND
+            list_id = $2 AND
+            used_at IS NULL AND
+            created_at > $3
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        subscriber_id,
+        list_id,
+        Utc::now() - ttl / 2
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.subscription_token))
+}
+
 pub fn is_valid_name(s: &str) -> bool {
     // check empty str
     if s.trim().is_empty() {
@@ -174,38 +523,77 @@ pub fn is_valid_name(s: &str) -> bool {
     true
 }
 
+/// The email goes out through the outbox, once the transaction commits.
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    name = "Queue a confirmation email to a new subscriber",
+    skip(transaction, email, base_url, subscription_token)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn queue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-    email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &format!(
+) -> Result<(), sqlx::Error> {
+    let confirmation_link = confirmation_link(base_url, subscription_token);
+    enqueue_email(
+        &mut **transaction,
+        OutboxMessage {
+            kind: "confirmation",
+            recipient: email,
+            subject: "Welcome!",
+            html_content: &format!(
                 "Welcome to our news letter!<br/>\
                 Click <a href=\"{}\">HERE</a> to confirm your subscription.",
                 confirmation_link
             ),
-            &format!(
+            text_content: &format!(
                 "Welcome to our newsletter!\nVisit {} to confirm your subscriptions",
                 confirmation_link
             ),
-        )
-        .await
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sent instead of a confirmation email on single opt-in lists.
+#[tracing::instrument(
+    name = "Queue a welcome email to a new subscriber",
+    skip(transaction, email)
+)]
+pub async fn queue_welcome_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    list_name: &str,
+) -> Result<(), sqlx::Error> {
+    enqueue_email(
+        &mut **transaction,
+        OutboxMessage {
+            kind: "welcome",
+            recipient: email,
+            subject: "Welcome!",
+            html_content: &format!(
+                "Welcome to {}!<br/>You will receive our next issue in your inbox.",
+                htmlescape::encode_minimal(list_name)
+            ),
+            text_content: &format!(
+                "Welcome to {}!\nYou will receive our next issue in your inbox.",
+                list_name
+            ),
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+pub fn confirmation_link(base_url: &str, subscription_token: &str) -> String {
+    format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    )
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
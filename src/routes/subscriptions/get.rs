@@ -0,0 +1,75 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use chrono::Utc;
+
+use crate::bot_protection::{form_stamp, BotProtection, HONEYPOT_FIELD};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    list: Option<String>,
+    form_id: Option<String>,
+}
+
+/// The public signup form. It carries the bot checks: a honeypot field,
+/// a signed stamp of when it was rendered and, when enabled, a CAPTCHA.
+pub async fn subscribe_form(
+    query: web::Query<QueryParams>,
+    protection: web::Data<BotProtection>,
+) -> HttpResponse {
+    let mut hidden = String::new();
+    for (name, value) in [("list", &query.list), ("form_id", &query.form_id)] {
+        if let Some(value) = value {
+            hidden.push_str(&format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                name,
+                htmlescape::encode_attribute(value)
+            ));
+        }
+    }
+    let stamp = form_stamp(&protection.hmac_secret, Utc::now());
+    let (captcha_script, captcha_widget) =
+        match protection.captcha.as_ref().and_then(|c| c.widget.as_ref()) {
+            Some(widget) => (
+                format!(
+                    r#"<script src="{}" async defer></script>"#,
+                    widget.provider.script_url()
+                ),
+                format!(
+                    r#"<div class="{}" data-sitekey="{}"></div>"#,
+                    widget.provider.widget_class(),
+                    htmlescape::encode_attribute(&widget.site_key)
+                ),
+            ),
+            None => (String::new(), String::new()),
+        };
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribe</title>
+    {captcha_script}
+</head>
+<body>
+    <form action="/subscriptions" method="post">
+        {hidden}
+        <input type="hidden" name="form_stamp" value="{stamp}">
+        <label>Name:
+            <input type="text" name="name">
+        </label>
+        <label>Email:
+            <input type="text" name="email">
+        </label>
+        <div style="position: absolute; left: -10000px;" aria-hidden="true">
+            <label>Leave this empty:
+                <input type="text" name="{HONEYPOT_FIELD}" tabindex="-1" autocomplete="off">
+            </label>
+        </div>
+        {captcha_widget}
+        <button type="submit">Subscribe</button>
+    </form>
+</body>
+</html>"#,
+        ))
+}
@@ -1,9 +1,22 @@
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
 use anyhow::Context;
-use sqlx::PgPool;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use super::error_chain_fmt;
+use super::{error_chain_fmt, generate_subscription_token, queue_confirmation_email, store_token};
+use crate::{
+    bot_protection::{record_confirmation_send, BotProtection, SendAllowance},
+    configuration::SubscriptionSettings,
+    consent::{record_confirmation_consent, ConsentContext},
+    domain::SubscriberEmail,
+    lists::OptIn,
+    startup::ApplicationBaseUrl,
+    welcome_series::schedule_welcome_series,
+};
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
@@ -14,6 +27,8 @@ pub struct Parameters {
 pub enum ConfirmError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("Too many confirmation requests from this address. Please try again later.")]
+    RateLimited,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -28,46 +43,260 @@ impl ResponseError for ConfirmError {
     fn status_code(&self) -> StatusCode {
         match self {
             ConfirmError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ConfirmError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
             ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(query, db_pool))]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(query, request, db_pool, settings)
+)]
 pub async fn confirm(
     db_pool: web::Data<PgPool>,
     query: web::Query<Parameters>,
+    request: HttpRequest,
+    settings: web::Data<SubscriptionSettings>,
 ) -> Result<HttpResponse, ConfirmError> {
-    let sub_id = get_subscription_id_from_token(&db_pool, &query.subscription_token)
-        .await
-        .context("Failed to get subscription id from token")?;
-    let Some(id) = sub_id else {
-        return Ok(HttpResponse::Unauthorized().finish());
-    };
-    confirm_subscriber(&db_pool, id)
-        .await
-        .context("Failed to confirm subscriber")?;
-    Ok(HttpResponse::Ok().finish())
+    let token_state =
+        get_subscription_id_from_token(&db_pool, &query.subscription_token, settings.token_ttl())
+            .await
+            .context("Failed to get subscription id from token")?;
+    match token_state {
+        TokenState::Valid {
+            subscriber_id,
+            list_id,
+        } => {
+            confirm_subscriber(
+                &db_pool,
+                &query.subscription_token,
+                subscriber_id,
+                list_id,
+                &ConsentContext::from_request(&request),
+            )
+            .await
+            .context("Failed to confirm subscriber")?;
+            Ok(HttpResponse::Ok().finish())
+        }
+        TokenState::Used => Ok(stale_link_page(
+            "This confirmation link has already been used.",
+            &query.subscription_token,
+        )),
+        TokenState::Expired => Ok(stale_link_page(
+            "This confirmation link has expired.",
+            &query.subscription_token,
+        )),
+        TokenState::Unknown => Ok(HttpResponse::Unauthorized().finish()),
+    }
+}
+
+fn stale_link_page(message: &str, subscription_token: &str) -> HttpResponse {
+    HttpResponse::Gone()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirm your subscription</title>
+</head>
+<body>
+    <p>{message}</p>
+    <p>If you still want to confirm your subscription, we can send you a new link.</p>
+    <form action="/subscriptions/confirm/resend" method="post">
+        <input hidden type="text" name="subscription_token" value="{}">
+        <button type="submit">Send me a new link</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(subscription_token)
+        ))
+}
+
+/// The token can only be used once.
+pub async fn confirm_subscriber(
+    pool: &PgPool,
+    subscription_token: &str,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    consent: &ConsentContext,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET used_at = now()
+        WHERE subscription_token = $1
+        "#,
+        subscription_token
+    )
+    .execute(&mut *transaction)
+    .await?;
+    confirm_membership(&mut transaction, subscriber_id, list_id, OptIn::Double).await?;
+    record_confirmation_consent(&mut *transaction, subscriber_id, list_id, consent).await?;
+    transaction.commit().await
 }
 
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+/// Confirming a list membership also confirms the subscriber's email address
+/// and starts the welcome series. Returns whether any step of the series was
+/// scheduled.
+pub async fn confirm_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    opt_in: OptIn,
+) -> Result<bool, sqlx::Error> {
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"
+        UPDATE subscriptions
+        SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, now())
+        WHERE id = $1
+        "#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(&mut **transaction)
     .await?;
-    Ok(())
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'confirmed', opt_in = $3, consented_at = now(), pending_since = NULL
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id,
+        opt_in.as_str()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    schedule_welcome_series(transaction, subscriber_id).await
+}
+
+pub enum TokenState {
+    Valid { subscriber_id: Uuid, list_id: Uuid },
+    Used,
+    Expired,
+    Unknown,
 }
 
 pub async fn get_subscription_id_from_token(
     pool: &PgPool,
     token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
+    ttl: chrono::Duration,
+) -> Result<TokenState, sqlx::Error> {
     let res = sqlx::query!(
-        r#"SELECT subscriber_id from subscription_tokens WHERE subscription_token = $1"#,
+        r#"
+        SELECT subscriber_id, list_id, created_at, used_at
+        FROM subscription_tokens
+        WHERE subscription_token = $1
+        "#,
         token
     )
     .fetch_optional(pool)
     .await?;
-    Ok(res.map(|r| r.subscriber_id))
+    let state = match res {
+        None => TokenState::Unknown,
+        Some(r) if r.used_at.is_some() => TokenState::Used,
+        Some(r) if r.created_at + ttl < Utc::now() => TokenState::Expired,
+        Some(r) => TokenState::Valid {
+            subscriber_id: r.subscriber_id,
+            list_id: r.list_id,
+        },
+    };
+    Ok(state)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    subscription_token: String,
+}
+
+/// Sends a fresh link to whoever holds an old one, as long as the membership
+/// it was issued for still needs confirming. The page never tells which case
+/// applied, nor whether the address has hit its send limit.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, request, db_pool, base_url, protection)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    protection: web::Data<BotProtection>,
+) -> Result<HttpResponse, ConfirmError> {
+    let pending = sqlx::query!(
+        r#"
+        SELECT s.id, s.email, s.email_key, t.list_id, ls.status
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        JOIN list_subscriptions ls
+            ON ls.subscriber_id = t.subscriber_id AND ls.list_id = t.list_id
+        WHERE t.subscription_token = $1
+        "#,
+        form.subscription_token
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to look up the confirmation token")?;
+    let Some(pending) = pending else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    if pending.status == "pending_confirmation" {
+        let email = SubscriberEmail::parse(pending.email).map_err(ConfirmError::ValidationError)?;
+        let subscription_token = generate_subscription_token();
+        let ip_address = ConsentContext::from_request(&request).ip_address;
+        let mut transaction = db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a postgres conn from pool")?;
+        match protection
+            .limits
+            .check(&mut *transaction, &pending.email_key, ip_address.as_deref())
+            .await
+            .context("Failed to check the confirmation send limits")?
+        {
+            SendAllowance::Allowed => {
+                store_token(
+                    &mut transaction,
+                    pending.id,
+                    pending.list_id,
+                    &subscription_token,
+                )
+                .await
+                .context("Failed saving token to database")?;
+                queue_confirmation_email(
+                    &mut transaction,
+                    &email,
+                    &base_url.0,
+                    &subscription_token,
+                )
+                .await
+                .context("Failed to queue a confirmation email")?;
+                record_confirmation_send(
+                    &mut *transaction,
+                    &pending.email_key,
+                    ip_address.as_deref(),
+                )
+                .await
+                .context("Failed to record the confirmation send")?;
+            }
+            SendAllowance::IpLimited => return Err(ConfirmError::RateLimited),
+            SendAllowance::EmailLimited => {}
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the new confirmation token")?;
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirm your subscription</title>
+</head>
+<body>
+    <p>If your subscription still needs confirming, a new link is on its way to your inbox.</p>
+</body>
+</html>"#,
+    ))
 }
@@ -0,0 +1,120 @@
+use actix_web::{
+    http::header::{self, CacheControl, CacheDirective},
+    web, HttpResponse,
+};
+use sqlx::PgPool;
+
+use crate::{startup::HmacSecret, tracking::verify_click, utils::e400};
+
+// A transparent 1x1 GIF.
+const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct TrackingParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    url: String,
+    sig: String,
+}
+
+/// Always answers with the pixel - a recipient should never see a broken
+/// image because we failed to record their open.
+#[tracing::instrument(name = "Track an email open", skip(path, pool))]
+pub async fn track_open(
+    path: web::Path<TrackingParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(e) = record_open(&pool, &path.token).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record an email open"
+        );
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(TRACKING_PIXEL)
+}
+
+#[tracing::instrument(skip(pool, tracking_token))]
+async fn record_open(pool: &PgPool, tracking_token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            newsletter_issue_id,
+            subscriber_email,
+            event_type,
+            first_occurred_at,
+            last_occurred_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, 'open', now(), now()
+        FROM tracking_tokens
+        WHERE tracking_token = $1
+        ON CONFLICT (newsletter_issue_id, subscriber_email, event_type, url) DO UPDATE
+        SET
+            last_occurred_at = EXCLUDED.last_occurred_at,
+            occurrences = email_events.occurrences + 1
+        "#,
+        tracking_token
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Track a link click", skip(path, query, pool, hmac_secret))]
+pub async fn track_click(
+    path: web::Path<TrackingParameters>,
+    query: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    verify_click(&hmac_secret.0, &path.token, &query.url, &query.sig).map_err(e400)?;
+    if let Err(e) = record_click(&pool, &path.token, &query.url).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record a link click"
+        );
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, query.url.as_str()))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish())
+}
+
+#[tracing::instrument(skip(pool, tracking_token))]
+async fn record_click(pool: &PgPool, tracking_token: &str, url: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            newsletter_issue_id,
+            subscriber_email,
+            event_type,
+            url,
+            first_occurred_at,
+            last_occurred_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, 'click', $2, now(), now()
+        FROM tracking_tokens
+        WHERE tracking_token = $1
+        ON CONFLICT (newsletter_issue_id, subscriber_email, event_type, url) DO UPDATE
+        SET
+            last_occurred_at = EXCLUDED.last_occurred_at,
+            occurrences = email_events.occurrences + 1
+        "#,
+        tracking_token,
+        url
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
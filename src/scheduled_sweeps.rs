@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::{
+    ab_test::decide_ab_tests,
+    configuration::{Settings, SubscriptionSettings},
+    confirmation_reminders::send_confirmation_reminders,
+    digest::send_due_digests,
+    email_client::EmailClient,
+    startup::get_connection_pool,
+    welcome_series::send_due_welcome_emails,
+};
+
+/// How often the sweeps look for work that has come due.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs the jobs that come due over time, apart from the delivery worker so
+/// they do not hold up issue deliveries.
+async fn sweep_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    subscription_settings: SubscriptionSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        if let Err(e) = decide_ab_tests(&pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to pick the winners of subject line A/B tests"
+            );
+        }
+        if let Err(e) = send_due_digests(&pool, &email_client, &base_url).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the due weekly digests"
+            );
+        }
+        if let Err(e) = send_due_welcome_emails(&pool, &base_url).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the due welcome emails"
+            );
+        }
+        if let Err(e) = send_confirmation_reminders(&pool, &base_url, &subscription_settings).await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send confirmation reminders"
+            );
+        }
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+    }
+}
+
+pub async fn run_sweeps_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    sweep_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.subscriptions,
+    )
+    .await
+}
@@ -0,0 +1,229 @@
+mod expression;
+mod field_condition;
+
+pub use expression::TagExpression;
+pub use field_condition::FieldCondition;
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use sqlx::{PgExecutor, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::custom_fields::{get_custom_fields, CustomField};
+
+#[derive(Debug)]
+pub struct Segment {
+    pub segment_id: Uuid,
+    pub name: String,
+    pub tag_expression: String,
+    pub subscribed_before: Option<NaiveDate>,
+    pub opened_within_days: Option<i32>,
+    pub field_conditions: String,
+}
+
+impl Segment {
+    /// Checks the filters before the segment is saved.
+    pub fn validate(&self, fields: &[CustomField]) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("The segment needs a name.".into());
+        }
+        self.parse_tag_expression()?;
+        self.parse_field_conditions(fields)?;
+        if matches!(self.opened_within_days, Some(days) if days <= 0) {
+            return Err("The number of days for recent opens must be positive.".into());
+        }
+        Ok(())
+    }
+
+    fn parse_tag_expression(&self) -> Result<Option<TagExpression>, String> {
+        if self.tag_expression.trim().is_empty() {
+            return Ok(None);
+        }
+        TagExpression::parse(&self.tag_expression).map(Some)
+    }
+
+    /// One condition per line.
+    fn parse_field_conditions(
+        &self,
+        fields: &[CustomField],
+    ) -> Result<Vec<FieldCondition>, String> {
+        self.field_conditions
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| FieldCondition::parse(line, fields))
+            .collect()
+    }
+
+    /// Pushes `AND ...` conditions restricting subscribers (aliased as `s`)
+    /// to the members of this segment.
+    pub fn push_conditions(
+        &self,
+        builder: &mut QueryBuilder<'_, Postgres>,
+        fields: &[CustomField],
+    ) -> Result<(), String> {
+        if let Some(expression) = self.parse_tag_expression()? {
+            builder.push(" AND ");
+            expression.push_sql(builder);
+        }
+        for condition in self.parse_field_conditions(fields)? {
+            builder.push(" AND ");
+            condition.push_sql(builder);
+        }
+        if let Some(date) = self.subscribed_before {
+            builder.push(" AND s.subscribed_at < ");
+            builder.push_bind(date);
+        }
+        if let Some(days) = self.opened_within_days {
+            builder.push(
+                " AND EXISTS (SELECT 1 FROM email_events e \
+                WHERE e.subscriber_email = s.email AND e.event_type = 'open' \
+                AND e.last_occurred_at >= now() - make_interval(days => ",
+            );
+            builder.push_bind(days);
+            builder.push("))");
+        }
+        Ok(())
+    }
+}
+
+/// Pushes the `FROM ... WHERE ...` part of a query selecting the confirmed
+/// subscribers (aliased as `s`) of a list, or of any list when `list_id` is
+/// `None`, narrowed down to a segment. Subscribers who paused their
+/// subscription are left out.
+pub fn push_audience(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    list_id: Option<Uuid>,
+    segment: Option<&Segment>,
+    fields: &[CustomField],
+) -> Result<(), anyhow::Error> {
+    match list_id {
+        Some(list_id) => {
+            builder.push(
+                " FROM list_subscriptions l JOIN subscriptions s ON s.id = l.subscriber_id \
+                WHERE l.status = 'confirmed' AND l.list_id = ",
+            );
+            builder.push_bind(list_id);
+        }
+        None => {
+            builder.push(" FROM subscriptions s WHERE s.status = 'confirmed'");
+        }
+    }
+    builder.push(" AND (s.paused_until IS NULL OR s.paused_until <= now())");
+    if let Some(segment) = segment {
+        segment
+            .push_conditions(builder, fields)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("The segment {} is invalid", segment.name))?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool, segment))]
+pub async fn count_recipients(
+    pool: &PgPool,
+    list_id: Option<Uuid>,
+    segment: Option<&Segment>,
+) -> Result<i64, anyhow::Error> {
+    let fields = get_custom_fields(pool).await?;
+    let mut builder = QueryBuilder::new("SELECT COUNT(*)");
+    push_audience(&mut builder, list_id, segment, &fields)?;
+    let count = builder
+        .build_query_scalar::<i64>()
+        .fetch_one(pool)
+        .await
+        .context("Failed to count the recipients.")?;
+    Ok(count)
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_segment(
+    executor: impl PgExecutor<'_>,
+    segment_id: Uuid,
+) -> Result<Option<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT
+            segment_id,
+            name,
+            tag_expression,
+            subscribed_before,
+            opened_within_days,
+            field_conditions
+        FROM segments
+        WHERE segment_id = $1
+        "#,
+        segment_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_segments(pool: &PgPool) -> Result<Vec<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT
+            segment_id,
+            name,
+            tag_expression,
+            subscribed_before,
+            opened_within_days,
+            field_conditions
+        FROM segments
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Segment;
+    use chrono::NaiveDate;
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    fn segment(tag_expression: &str) -> Segment {
+        Segment {
+            segment_id: Uuid::new_v4(),
+            name: "Engaged rustaceans".into(),
+            tag_expression: tag_expression.into(),
+            subscribed_before: None,
+            opened_within_days: None,
+            field_conditions: String::new(),
+        }
+    }
+
+    #[test]
+    fn segments_without_a_tag_expression_are_valid() {
+        assert_ok!(segment(" ").validate(&[]));
+    }
+
+    #[test]
+    fn segments_with_a_malformed_tag_expression_are_rejected() {
+        assert_err!(segment("rust and").validate(&[]));
+    }
+
+    #[test]
+    fn recent_opens_need_a_positive_number_of_days() {
+        let mut segment = segment("rust");
+        segment.opened_within_days = Some(0);
+        assert_err!(segment.validate(&[]));
+    }
+
+    #[test]
+    fn every_filter_becomes_a_condition() {
+        let mut segment = segment("rust");
+        segment.subscribed_before = NaiveDate::from_ymd_opt(2024, 1, 1);
+        segment.opened_within_days = Some(90);
+        let mut builder = sqlx::QueryBuilder::new("");
+        segment.push_conditions(&mut builder, &[]).unwrap();
+        let sql = builder.sql();
+        assert!(sql.contains("st.tag = $1"));
+        assert!(sql.contains("s.subscribed_at < $2"));
+        assert!(sql.contains("make_interval(days => $3)"));
+    }
+}
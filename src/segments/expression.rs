@@ -0,0 +1,181 @@
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::tags::parse_tag;
+
+/// A boolean expression over subscriber tags, e.g.
+/// `rust and (beta or not churned)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpression {
+    Tag(String),
+    Not(Box<TagExpression>),
+    And(Box<TagExpression>, Box<TagExpression>),
+    Or(Box<TagExpression>, Box<TagExpression>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Tag(String),
+}
+
+impl TagExpression {
+    /// `not` binds tighter than `and`, which binds tighter than `or`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expression = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err("Unexpected input after the end of the tag expression.".into());
+        }
+        Ok(expression)
+    }
+
+    /// Pushes a SQL condition matching subscribers (aliased as `s`) for which
+    /// the expression holds.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            TagExpression::Tag(tag) => {
+                builder.push(
+                    "EXISTS (SELECT 1 FROM subscriber_tags st \
+                    WHERE st.subscriber_id = s.id AND st.tag = ",
+                );
+                builder.push_bind(tag.clone());
+                builder.push(")");
+            }
+            TagExpression::Not(inner) => {
+                builder.push("NOT (");
+                inner.push_sql(builder);
+                builder.push(")");
+            }
+            TagExpression::And(left, right) | TagExpression::Or(left, right) => {
+                let operator = if matches!(self, TagExpression::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                builder.push("(");
+                left.push_sql(builder);
+                builder.push(operator);
+                right.push_sql(builder);
+                builder.push(")");
+            }
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let spaced = s.replace('(', " ( ").replace(')', " ) ");
+    spaced
+        .split_whitespace()
+        .map(|word| match word.to_lowercase().as_str() {
+            "and" => Ok(Token::And),
+            "or" => Ok(Token::Or),
+            "not" => Ok(Token::Not),
+            "(" => Ok(Token::Open),
+            ")" => Ok(Token::Close),
+            _ => parse_tag(word).map(Token::Tag),
+        })
+        .collect()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next_is(&self, token: &Token) -> bool {
+        self.tokens.get(self.pos) == Some(token)
+    }
+
+    fn or(&mut self) -> Result<TagExpression, String> {
+        let mut expression = self.and()?;
+        while self.next_is(&Token::Or) {
+            self.pos += 1;
+            expression = TagExpression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<TagExpression, String> {
+        let mut expression = self.unary()?;
+        while self.next_is(&Token::And) {
+            self.pos += 1;
+            expression = TagExpression::And(Box::new(expression), Box::new(self.unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<TagExpression, String> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        match token {
+            Some(Token::Not) => Ok(TagExpression::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expression = self.or()?;
+                if !self.next_is(&Token::Close) {
+                    return Err("A parenthesis in the tag expression is never closed.".into());
+                }
+                self.pos += 1;
+                Ok(expression)
+            }
+            Some(Token::Tag(tag)) => Ok(TagExpression::Tag(tag.clone())),
+            Some(_) => Err("Expected a tag, `not` or `(` in the tag expression.".into()),
+            None => Err("The tag expression ends unexpectedly.".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TagExpression::{self, *};
+    use claims::{assert_err, assert_ok_eq};
+
+    fn tag(t: &str) -> Box<TagExpression> {
+        Box::new(Tag(t.into()))
+    }
+
+    #[test]
+    fn a_single_tag_is_an_expression() {
+        assert_ok_eq!(TagExpression::parse(" Rust "), Tag("rust".into()));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_ok_eq!(
+            TagExpression::parse("a or b and not c"),
+            Or(tag("a"), Box::new(And(tag("b"), Box::new(Not(tag("c"))))))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_sub_expressions() {
+        assert_ok_eq!(
+            TagExpression::parse("(a OR b) AND c"),
+            And(Box::new(Or(tag("a"), tag("b"))), tag("c"))
+        );
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        for expression in ["", "a and", "(a or b", "a b", "a or )", "not", "a & b"] {
+            assert_err!(TagExpression::parse(expression));
+        }
+    }
+
+    #[test]
+    fn expressions_compile_to_bound_sql() {
+        let expression = TagExpression::parse("a and not b").unwrap();
+        let mut builder = sqlx::QueryBuilder::new("");
+        expression.push_sql(&mut builder);
+        assert_eq!(
+            builder.sql(),
+            "(EXISTS (SELECT 1 FROM subscriber_tags st WHERE st.subscriber_id = s.id AND st.tag = $1) \
+            AND NOT (EXISTS (SELECT 1 FROM subscriber_tags st WHERE st.subscriber_id = s.id AND st.tag = $2)))"
+        );
+    }
+}
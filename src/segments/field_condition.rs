@@ -0,0 +1,152 @@
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::custom_fields::{CustomField, FieldType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Equals,
+    NotEquals,
+    LessThan,
+    GreaterThan,
+}
+
+/// A filter on a custom field, written as `<key> <operator> <value>`, e.g.
+/// `plan = pro` or `signed_up_on < 2024-01-01`. `<` and `>` only apply to
+/// number and date fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldCondition {
+    field_id: Uuid,
+    field_type: FieldType,
+    operator: Operator,
+    value: String,
+}
+
+impl FieldCondition {
+    pub fn parse(s: &str, fields: &[CustomField]) -> Result<Self, String> {
+        let mut parts = s.trim().splitn(3, char::is_whitespace);
+        let (Some(key), Some(operator), Some(value)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!(
+                "`{}` should look like `<field> <operator> <value>`.",
+                s.trim()
+            ));
+        };
+        let field = fields
+            .iter()
+            .find(|f| f.key == key)
+            .ok_or_else(|| format!("{} is not a custom field.", key))?;
+        let operator = match operator {
+            "=" => Operator::Equals,
+            "!=" => Operator::NotEquals,
+            "<" => Operator::LessThan,
+            ">" => Operator::GreaterThan,
+            other => {
+                return Err(format!(
+                    "{} is not a supported operator. Use =, !=, < or >.",
+                    other
+                ))
+            }
+        };
+        let is_ordered = matches!(field.field_type, FieldType::Number | FieldType::Date);
+        if matches!(operator, Operator::LessThan | Operator::GreaterThan) && !is_ordered {
+            return Err(format!(
+                "{} can only be compared with = or !=.",
+                field.label
+            ));
+        }
+        let value = field
+            .parse_value(&serde_json::Value::String(value.into()))?
+            .ok_or_else(|| format!("The condition on {} needs a value.", field.label))?;
+        Ok(Self {
+            field_id: field.field_id,
+            field_type: field.field_type,
+            operator,
+            value,
+        })
+    }
+
+    /// Pushes a SQL condition on subscribers aliased as `s`. Subscribers
+    /// without a value only match `!=`.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        let cast = match self.field_type {
+            FieldType::Number => "::numeric",
+            FieldType::Date => "::date",
+            FieldType::Text | FieldType::Choice => "",
+        };
+        let (negated, operator) = match self.operator {
+            Operator::Equals => (false, "="),
+            Operator::NotEquals => (true, "="),
+            Operator::LessThan => (false, "<"),
+            Operator::GreaterThan => (false, ">"),
+        };
+        if negated {
+            builder.push("NOT ");
+        }
+        builder.push(
+            "EXISTS (SELECT 1 FROM subscriber_field_values v \
+            WHERE v.subscriber_id = s.id AND v.field_id = ",
+        );
+        builder.push_bind(self.field_id);
+        builder.push(format!(" AND v.value{cast} {operator} "));
+        builder.push_bind(self.value.clone());
+        builder.push(format!("{cast})"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+
+    fn fields() -> Vec<CustomField> {
+        let field = |key: &str, field_type| CustomField {
+            field_id: Uuid::new_v4(),
+            key: key.into(),
+            label: key.into(),
+            field_type,
+            options: vec!["free".into(), "pro".into()],
+            required: false,
+        };
+        vec![
+            field("plan", FieldType::Choice),
+            field("age", FieldType::Number),
+            field("joined", FieldType::Date),
+        ]
+    }
+
+    #[test]
+    fn well_formed_conditions_are_accepted() {
+        for condition in [
+            "plan = pro",
+            "age > 30",
+            "joined < 2024-01-01",
+            "plan != free",
+        ] {
+            assert_ok!(FieldCondition::parse(condition, &fields()));
+        }
+    }
+
+    #[test]
+    fn conditions_are_checked_against_the_field_types() {
+        for condition in [
+            "plan > pro",
+            "age = old",
+            "joined < yesterday",
+            "plan = enterprise",
+            "city = paris",
+            "age >= 30",
+            "age",
+        ] {
+            assert_err!(FieldCondition::parse(condition, &fields()));
+        }
+    }
+
+    #[test]
+    fn ordered_comparisons_cast_both_sides() {
+        let condition = FieldCondition::parse("age > 30", &fields()).unwrap();
+        let mut builder = QueryBuilder::new("");
+        condition.push_sql(&mut builder);
+        assert!(builder.sql().ends_with("v.value::numeric > $2::numeric)"));
+    }
+}
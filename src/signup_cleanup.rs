@@ -0,0 +1,141 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{Settings, SubscriptionSettings},
+    startup::get_connection_pool,
+};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CleanupReport {
+    pub deleted_tokens: u64,
+    pub deleted_subscribers: u64,
+    /// Returning subscribers whose new signup went unconfirmed, and who are
+    /// back to being unsubscribed.
+    pub expired_resubscriptions: u64,
+}
+
+/// Deletes confirmation tokens past their retention period, then expires
+/// signups left unconfirmed for longer than the pending retention, judged
+/// by when the membership started waiting and by the latest link sent.
+/// Subscribers who never confirmed anything are deleted; those who once did
+/// keep their history and go back to being unsubscribed.
+#[tracing::instrument(skip_all)]
+pub async fn cleanup_stale_signups(
+    pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<CleanupReport, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let deleted_tokens = sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE created_at < $1"#,
+        Utc::now() - settings.token_retention()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete stale confirmation tokens")?
+    .rows_affected();
+    sqlx::query!(
+        r#"DELETE FROM confirmation_sends WHERE sent_at < $1"#,
+        Utc::now() - settings.token_retention()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete old confirmation sends")?;
+    sqlx::query!(r#"DELETE FROM used_form_stamps WHERE expires_at < now()"#)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete expired form stamps")?;
+    sqlx::query!(r#"DELETE FROM typo_suggestions WHERE expires_at < now()"#)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete expired typo suggestions")?;
+
+    let stale = sqlx::query!(
+        r#"
+        SELECT id, confirmed_at IS NOT NULL as "confirmed_before!"
+        FROM subscriptions s
+        WHERE
+            status = 'pending_confirmation' AND
+            subscribed_at < $1 AND
+            NOT EXISTS (
+                SELECT 1 FROM list_subscriptions ls
+                WHERE
+                    ls.subscriber_id = s.id AND
+                    ls.status = 'pending_confirmation' AND
+                    ls.pending_since >= $1
+            ) AND
+            NOT EXISTS (
+                SELECT 1 FROM subscription_tokens t
+                WHERE t.subscriber_id = s.id AND t.created_at >= $1
+            )
+        FOR UPDATE
+        "#,
+        Utc::now() - settings.pending_retention()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look for stale pending subscribers")?;
+    let (returning, never_confirmed): (Vec<_>, Vec<_>) =
+        stale.into_iter().partition(|r| r.confirmed_before);
+    let returning: Vec<Uuid> = returning.into_iter().map(|r| r.id).collect();
+    let never_confirmed: Vec<Uuid> = never_confirmed.into_iter().map(|r| r.id).collect();
+
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'unsubscribed', pending_since = NULL
+        WHERE subscriber_id = ANY($1) AND status = 'pending_confirmation'
+        "#,
+        &returning
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to expire stale memberships")?;
+    let expired_resubscriptions = sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = ANY($1)",
+        &returning
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to expire stale resubscriptions")?
+    .rows_affected();
+    let deleted_subscribers = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = ANY($1)",
+        &never_confirmed
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete stale pending subscribers")?
+    .rows_affected();
+    transaction.commit().await?;
+    Ok(CleanupReport {
+        deleted_tokens,
+        deleted_subscribers,
+        expired_resubscriptions,
+    })
+}
+
+async fn cleanup_loop(pool: PgPool, settings: SubscriptionSettings) -> Result<(), anyhow::Error> {
+    loop {
+        match cleanup_stale_signups(&pool, &settings).await {
+            Ok(report) => tracing::info!(
+                deleted_tokens = report.deleted_tokens,
+                deleted_subscribers = report.deleted_subscribers,
+                expired_resubscriptions = report.expired_resubscriptions,
+                "Cleaned up stale signups"
+            ),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to clean up stale signups"
+            ),
+        }
+        tokio::time::sleep(settings.cleanup_interval()).await;
+    }
+}
+
+pub async fn run_cleanup_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    cleanup_loop(connection_pool, configuration.subscriptions).await
+}
@@ -4,18 +4,33 @@ use actix_session::{storage::RedisSessionStore, SessionMiddleware};
 use actix_web::{cookie::Key, dev::Server, web, App, HttpServer};
 use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
 use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    authentication::reject_annonymousr_user,
-    configuration::{DatabaseSettings, Settings},
+    authentication::{reject_annonymousr_user, LoginThrottle},
+    bot_protection::BotProtection,
+    client_ip::TrustedProxies,
+    configuration::{DatabaseSettings, Settings, SubscriptionSettings},
+    deliverability::DeliverabilityChecker,
     email_client::EmailClient,
+    rate_limit::{RateLimit, RateLimiter, RouteGroup},
     routes::{
-        admin_dashboard, change_password, change_password_form, confirm, health_check, home, login,
-        login_form, logout, send_newsletter, send_newsletter_form, subscribe,
+        admin_dashboard, cancel_issue, change_password, change_password_form, confirm,
+        confirm_subscriber_manually, consent_events, create_field, create_list, create_segment,
+        create_welcome_step, delete_subscriber, erase_data, export_consent_events, export_data,
+        export_subscribers, health_check, home, import_details, import_form, import_report,
+        issue_details, list_fields, list_issues, list_lists, list_segments, list_subscribers,
+        list_tags, list_welcome_steps, login, login_form, logout, manage_data_form, pause_issue,
+        preferences_form, privacy_request_form, rename_subscriber, request_privacy_link,
+        resend_confirmation, resend_subscriber_confirmation, resume_issue, send_newsletter,
+        send_newsletter_form, set_field_value, subscribe, subscribe_form, subscriber_details,
+        track_click, track_open, unsubscribe, unsubscribe_subscriber, update_preferences,
+        update_subscriber_tags, update_tags, upload_import,
     },
+    subscriber_keys::ensure_subscribers_keyed,
 };
 
 #[derive(Clone)]
@@ -28,7 +43,30 @@ pub struct Application {
 impl Application {
     pub async fn build(config: Settings) -> Result<Self, anyhow::Error> {
         let db_pool = get_connection_pool(&config.database);
+        // Signups and imports look subscribers up by key, so keys computed
+        // with other alias folding rules are brought up to date first.
+        if let Some(summary) =
+            ensure_subscribers_keyed(&db_pool, &config.subscriptions.alias_folding_domains)
+                .await
+                .context("Failed to rekey the subscribers")?
+        {
+            tracing::info!(
+                rekeyed = summary.rekeyed,
+                merged = summary.merged,
+                unparseable = summary.unparseable.len(),
+                "Rekeyed the subscribers"
+            );
+        }
         let email_client = config.email_client.client();
+        let bot_protection = config
+            .bot_protection
+            .protection(config.application.hmac_secret.clone());
+        let login_throttle = config
+            .login_protection
+            .throttle()
+            .map_err(|e| anyhow::anyhow!("Invalid login protection settings: {e}"))?;
+        let rate_limiter =
+            RateLimiter::connect(config.redis_url.expose_secret(), config.rate_limits).await?;
         let listener = TcpListener::bind(format!(
             "{}:{}",
             config.application.host, config.application.port
@@ -40,6 +78,12 @@ impl Application {
             email_client,
             config.application.base_url,
             config.application.hmac_secret,
+            TrustedProxies::new(config.application.trusted_proxies),
+            config.subscriptions,
+            config.deliverability.checker()?,
+            bot_protection,
+            rate_limiter,
+            login_throttle,
             config.redis_url,
         )
         .await?;
@@ -55,17 +99,30 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
     base_url: String,
     hmac_secret: Secret<String>,
+    trusted_proxies: TrustedProxies,
+    subscription_settings: SubscriptionSettings,
+    deliverability: DeliverabilityChecker,
+    bot_protection: BotProtection,
+    rate_limiter: RateLimiter,
+    login_throttle: LoginThrottle,
     redis_url: Secret<String>,
 ) -> Result<Server, anyhow::Error> {
     let email_client = web::Data::new(email_client);
     let connection = web::Data::new(db_pool);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let subscription_settings = web::Data::new(subscription_settings);
+    let deliverability = web::Data::new(deliverability);
+    let bot_protection = web::Data::new(bot_protection);
+    let trusted_proxies = web::Data::new(trusted_proxies);
+    let rate_limiter = web::Data::new(rate_limiter);
+    let login_throttle = web::Data::new(login_throttle);
     // Flash Message Middleware
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_storage = CookieMessageStore::builder(secret_key.clone()).build();
@@ -83,16 +140,109 @@ pub async fn run(
             .wrap(TracingLogger::default())
             .route("/", web::get().to(home))
             .route("/login", web::get().to(login_form))
-            .route("/login", web::post().to(login))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/login",
+                web::post().to(login).wrap(RateLimit(RouteGroup::Login)),
+            )
+            .route("/subscriptions", web::get().to(subscribe_form))
+            .route(
+                "/subscriptions",
+                web::post()
+                    .to(subscribe)
+                    .wrap(RateLimit(RouteGroup::Subscribe)),
+            )
+            .route(
+                "/subscriptions/confirm",
+                web::get().to(confirm).wrap(RateLimit(RouteGroup::Confirm)),
+            )
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post()
+                    .to(resend_confirmation)
+                    .wrap(RateLimit(RouteGroup::Confirm)),
+            )
+            .route("/preferences", web::get().to(preferences_form))
+            .route("/preferences", web::post().to(update_preferences))
+            .route("/preferences/unsubscribe", web::post().to(unsubscribe))
+            .route("/privacy", web::get().to(privacy_request_form))
+            .route(
+                "/privacy",
+                web::post()
+                    .to(request_privacy_link)
+                    .wrap(RateLimit(RouteGroup::Subscribe)),
+            )
+            .route("/privacy/manage", web::get().to(manage_data_form))
+            .route("/privacy/export", web::get().to(export_data))
+            .route("/privacy/erase", web::post().to(erase_data))
             .route("/health_check", web::get().to(health_check))
+            .route("/t/o/{token}.gif", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_annonymousr_user))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/newsletters", web::get().to(send_newsletter_form))
                     .route("/newsletters", web::post().to(send_newsletter))
+                    .route("/issues", web::get().to(list_issues))
+                    .route("/issues/{issue_id}", web::get().to(issue_details))
+                    .route("/issues/{issue_id}/pause", web::post().to(pause_issue))
+                    .route("/issues/{issue_id}/resume", web::post().to(resume_issue))
+                    .route("/issues/{issue_id}/cancel", web::post().to(cancel_issue))
+                    .route("/lists", web::get().to(list_lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/fields", web::get().to(list_fields))
+                    .route("/fields", web::post().to(create_field))
+                    .route("/fields/values", web::post().to(set_field_value))
+                    .route("/segments", web::get().to(list_segments))
+                    .route("/segments", web::post().to(create_segment))
+                    .route("/tags", web::get().to(list_tags))
+                    .route("/tags", web::post().to(update_tags))
+                    .route(
+                        "/subscribers/tags",
+                        web::post()
+                            .to(update_subscriber_tags)
+                            .wrap(RateLimit(RouteGroup::Api)),
+                    )
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/subscribers/import", web::get().to(import_form))
+                    .route("/subscribers/import", web::post().to(upload_import))
+                    .route(
+                        "/subscribers/imports/{import_id}",
+                        web::get().to(import_details),
+                    )
+                    .route(
+                        "/subscribers/imports/{import_id}/report",
+                        web::get().to(import_report),
+                    )
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_details),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(confirm_subscriber_manually),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/resend",
+                        web::post().to(resend_subscriber_confirmation),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post().to(unsubscribe_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/name",
+                        web::post().to(rename_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/delete",
+                        web::post().to(delete_subscriber),
+                    )
+                    .route("/welcome", web::get().to(list_welcome_steps))
+                    .route("/welcome", web::post().to(create_welcome_step))
+                    .route("/consent", web::get().to(consent_events))
+                    .route("/consent/export", web::get().to(export_consent_events))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(logout)),
@@ -100,6 +250,12 @@ pub async fn run(
             .app_data(connection.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(deliverability.clone())
+            .app_data(bot_protection.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(login_throttle.clone())
             .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
@@ -0,0 +1,449 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use tokio::sync::mpsc::Sender;
+use uuid::Uuid;
+
+use crate::{
+    configuration::SubscriptionSettings,
+    consent::record_consent_note,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    lists::{get_list_by_slug, OptIn},
+    privacy::is_suppressed,
+    routes::{
+        add_to_list, confirm_membership, error_chain_fmt, generate_subscription_token,
+        insert_subscriber, queue_confirmation_email, store_token,
+    },
+    telemetry::spawn_blocking_with_tracing,
+};
+
+#[derive(Debug)]
+pub struct ImportOptions {
+    pub list_slug: String,
+    /// Imports everyone as confirmed instead of sending confirmation emails.
+    pub mark_confirmed: bool,
+    /// Where the imported subscribers gave their consent. Required when
+    /// importing them as confirmed.
+    pub consent_note: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub import_id: Uuid,
+    pub imported: i32,
+    pub skipped_duplicates: i32,
+    pub skipped_suppressed: i32,
+    pub rejected_invalid: i32,
+    /// Rows that could not be written to the database.
+    pub failed: i32,
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+enum RowOutcome {
+    Imported,
+    Duplicate(&'static str),
+    Suppressed,
+    Invalid(String),
+    /// Details are in the logs rather than in the report.
+    Failed(&'static str),
+}
+
+/// A row read from the CSV, or why it could not be read.
+enum CsvRow {
+    Row {
+        line: u64,
+        email: String,
+        name: String,
+    },
+    Unreadable {
+        line: u64,
+        reason: String,
+    },
+}
+
+/// How many rows are imported between updates of the counts.
+const PROGRESS_INTERVAL: i32 = 100;
+
+/// An import that has been recorded, and checked as far as possible without
+/// reading the file.
+#[derive(Debug)]
+pub struct ImportJob {
+    pub import_id: Uuid,
+    list_id: Uuid,
+    /// Set when the subscribers are imported as confirmed.
+    consent_note: Option<String>,
+}
+
+/// Imports subscribers from a CSV with `email` and `name` columns, one row
+/// at a time. Rows that are not imported end up in `import_rejections`
+/// with the reason, including those that failed to be written: the import
+/// carries on with the next row.
+#[tracing::instrument(skip(pool, base_url, settings, hmac_secret, csv))]
+pub async fn import_subscribers(
+    pool: &PgPool,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+    hmac_secret: &Secret<String>,
+    csv: impl Read + Send + 'static,
+    options: &ImportOptions,
+    source: &str,
+) -> Result<ImportSummary, ImportError> {
+    let job = create_import(pool, options, source).await?;
+    run_import(pool, base_url, settings, hmac_secret, job, csv).await
+}
+
+/// Checks the options and records the import as running.
+#[tracing::instrument(skip(pool))]
+pub async fn create_import(
+    pool: &PgPool,
+    options: &ImportOptions,
+    source: &str,
+) -> Result<ImportJob, ImportError> {
+    let consent_note = options
+        .consent_note
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if options.mark_confirmed && consent_note.is_none() {
+        return Err(ImportError::ValidationError(
+            "A consent note is required to import subscribers as confirmed.".into(),
+        ));
+    }
+    let list = get_list_by_slug(pool, &options.list_slug)
+        .await
+        .context("Failed to look up the mailing list")?
+        .ok_or_else(|| {
+            ImportError::ValidationError(format!(
+                "{} is not a known mailing list.",
+                options.list_slug
+            ))
+        })?;
+    let job = ImportJob {
+        import_id: Uuid::new_v4(),
+        list_id: list.list_id,
+        consent_note: consent_note
+            .filter(|_| options.mark_confirmed)
+            .map(str::to_string),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id, source, list_id, mark_confirmed, consent_note, status
+        )
+        VALUES ($1, $2, $3, $4, $5, 'running')
+        "#,
+        job.import_id,
+        source,
+        job.list_id,
+        options.mark_confirmed,
+        consent_note
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the import")?;
+    Ok(job)
+}
+
+/// Imports the rows of a recorded import, then marks it as completed, or
+/// as failed with the reason.
+#[tracing::instrument(skip(pool, base_url, settings, hmac_secret, csv))]
+pub async fn run_import(
+    pool: &PgPool,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+    hmac_secret: &Secret<String>,
+    job: ImportJob,
+    csv: impl Read + Send + 'static,
+) -> Result<ImportSummary, ImportError> {
+    let outcome = import_rows(pool, base_url, settings, hmac_secret, &job, csv).await;
+    let error = match &outcome {
+        Ok(_) => None,
+        Err(ImportError::ValidationError(e)) => Some(e.clone()),
+        Err(_) => Some("The import stopped unexpectedly.".to_string()),
+    };
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET status = CASE WHEN $2::text IS NULL THEN 'completed' ELSE 'failed' END, error = $2
+        WHERE import_id = $1
+        "#,
+        job.import_id,
+        error
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the end of the import")?;
+    outcome
+}
+
+/// The CSV is parsed on a blocking thread, which hands the rows over as
+/// they are read.
+async fn import_rows(
+    pool: &PgPool,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+    hmac_secret: &Secret<String>,
+    job: &ImportJob,
+    csv: impl Read + Send + 'static,
+) -> Result<ImportSummary, ImportError> {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(PROGRESS_INTERVAL as usize);
+    let reader = spawn_blocking_with_tracing(move || read_rows(csv, sender));
+
+    let mut summary = ImportSummary {
+        import_id: job.import_id,
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    let mut rows = 0;
+    while let Some(row) = receiver.recv().await {
+        if rows > 0 && rows % PROGRESS_INTERVAL == 0 {
+            record_progress(pool, &summary)
+                .await
+                .context("Failed to record the import progress")?;
+        }
+        rows += 1;
+        let (line, email, outcome) = match row {
+            CsvRow::Row { line, email, name } => {
+                let outcome = match parse_row(email.clone(), name) {
+                    Ok(new_subscriber)
+                        if !seen
+                            .insert(new_subscriber.email.key(&settings.alias_folding_domains)) =>
+                    {
+                        RowOutcome::Duplicate("duplicate row")
+                    }
+                    Ok(new_subscriber) => match import_row(
+                        pool,
+                        base_url,
+                        hmac_secret,
+                        &new_subscriber,
+                        &new_subscriber.email.key(&settings.alias_folding_domains),
+                        job.list_id,
+                        job.consent_note.as_deref(),
+                    )
+                    .await
+                    {
+                        Ok(outcome) => outcome,
+                        Err(e) => {
+                            tracing::error!(
+                                error.cause_chain = ?e,
+                                line,
+                                "Failed to import a subscriber"
+                            );
+                            RowOutcome::Failed("could not be imported")
+                        }
+                    },
+                    Err(e) => RowOutcome::Invalid(e),
+                };
+                (line, email, outcome)
+            }
+            CsvRow::Unreadable { line, reason } => {
+                (line, String::new(), RowOutcome::Invalid(reason))
+            }
+        };
+        let reason = match outcome {
+            RowOutcome::Imported => {
+                summary.imported += 1;
+                continue;
+            }
+            RowOutcome::Duplicate(reason) => {
+                summary.skipped_duplicates += 1;
+                reason.to_string()
+            }
+            RowOutcome::Suppressed => {
+                summary.skipped_suppressed += 1;
+                "suppressed".to_string()
+            }
+            RowOutcome::Invalid(reason) => {
+                summary.rejected_invalid += 1;
+                reason
+            }
+            RowOutcome::Failed(reason) => {
+                summary.failed += 1;
+                reason.to_string()
+            }
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO import_rejections (import_id, line, email, reason)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            summary.import_id,
+            line as i64,
+            email,
+            reason
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record a rejected row")?;
+    }
+    reader
+        .await
+        .context("Failed to read the CSV")?
+        .map_err(ImportError::ValidationError)?;
+    record_progress(pool, &summary)
+        .await
+        .context("Failed to record the import results")?;
+    Ok(summary)
+}
+
+/// Sends the rows of the CSV down the channel, until the receiving end
+/// goes away. Fails when the file has no header with the expected columns.
+fn read_rows(csv: impl Read, sender: Sender<CsvRow>) -> Result<(), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("The file is not a valid CSV: {}", e))?
+        .clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("The CSV has no `{}` column.", name))
+    };
+    let email_column = column("email")?;
+    let name_column = column("name")?;
+    for record in reader.records() {
+        let row = match record {
+            Ok(record) => CsvRow::Row {
+                line: record.position().map(|p| p.line()).unwrap_or_default(),
+                email: record.get(email_column).unwrap_or_default().to_string(),
+                name: record.get(name_column).unwrap_or_default().to_string(),
+            },
+            Err(e) => CsvRow::Unreadable {
+                line: e.position().map(|p| p.line()).unwrap_or_default(),
+                reason: e.to_string(),
+            },
+        };
+        if sender.blocking_send(row).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn record_progress(pool: &PgPool, summary: &ImportSummary) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET imported = $2, skipped_duplicates = $3, skipped_suppressed = $4,
+            rejected_invalid = $5, failed = $6
+        WHERE import_id = $1
+        "#,
+        summary.import_id,
+        summary.imported,
+        summary.skipped_duplicates,
+        summary.skipped_suppressed,
+        summary.rejected_invalid,
+        summary.failed
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Writes the rows of an import that were not imported as CSV.
+#[tracing::instrument(skip(pool, writer))]
+pub async fn write_rejection_report(
+    pool: &PgPool,
+    import_id: Uuid,
+    writer: impl Write,
+) -> Result<(), anyhow::Error> {
+    let rejections = sqlx::query!(
+        r#"
+        SELECT line, email, reason FROM import_rejections
+        WHERE import_id = $1
+        ORDER BY line
+        "#,
+        import_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the rejected rows")?;
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["line", "email", "reason"])?;
+    for rejection in rejections {
+        writer.write_record([
+            &rejection.line.to_string(),
+            &rejection.email,
+            &rejection.reason,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn parse_row(email: String, name: String) -> Result<NewSubscriber, String> {
+    Ok(NewSubscriber {
+        email: SubscriberEmail::parse(email)?,
+        name: SubscriberName::parse(name)?,
+    })
+}
+
+/// Imported subscribers either go through double opt-in like everyone else
+/// or, when a consent note is given, are confirmed straight away.
+async fn import_row(
+    pool: &PgPool,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    new_subscriber: &NewSubscriber,
+    email_key: &str,
+    list_id: Uuid,
+    consent_note: Option<&str>,
+) -> Result<RowOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    if is_suppressed(&mut *transaction, hmac_secret, email_key).await? {
+        return Ok(RowOutcome::Suppressed);
+    }
+    let Some(subscriber_id) =
+        insert_subscriber(&mut transaction, new_subscriber, email_key).await?
+    else {
+        return Ok(RowOutcome::Duplicate("already subscribed"));
+    };
+    add_to_list(&mut transaction, list_id, subscriber_id).await?;
+    match consent_note {
+        Some(note) => {
+            confirm_membership(&mut transaction, subscriber_id, list_id, OptIn::Manual).await?;
+            record_consent_note(&mut *transaction, subscriber_id, list_id, "import", note).await?;
+        }
+        None => {
+            let token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, list_id, &token).await?;
+            queue_confirmation_email(&mut transaction, &new_subscriber.email, base_url, &token)
+                .await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(RowOutcome::Imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_row;
+    use claims::assert_ok;
+
+    #[test]
+    fn rows_need_a_valid_email_and_name() {
+        assert_ok!(parse_row("monkey@gmail.com".into(), "monkey".into()));
+        assert!(parse_row("monkey.gmail.com".into(), "monkey".into()).is_err());
+        assert!(parse_row("monkey@gmail.com".into(), "".into()).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+#[derive(Debug, Default)]
+pub struct RekeySummary {
+    pub rekeyed: usize,
+    pub merged: usize,
+    /// Stored addresses that no longer parse, left as they are.
+    pub unparseable: Vec<String>,
+}
+
+struct StoredSubscriber {
+    id: Uuid,
+    email: String,
+    email_key: String,
+    confirmed: bool,
+    subscribed_at: DateTime<Utc>,
+}
+
+/// The domains in a form that does not depend on how they were listed.
+fn normalise_domains(alias_folding_domains: &[String]) -> Vec<String> {
+    let mut domains: Vec<_> = alias_folding_domains
+        .iter()
+        .map(|d| d.trim().to_lowercase())
+        .collect();
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+async fn keys_are_current(
+    executor: impl PgExecutor<'_>,
+    alias_folding_domains: &[String],
+) -> Result<bool, sqlx::Error> {
+    let keyed_with =
+        sqlx::query_scalar!("SELECT alias_folding_domains FROM subscriber_key_settings")
+            .fetch_optional(executor)
+            .await?;
+    Ok(keyed_with.as_deref() == Some(&normalise_domains(alias_folding_domains)[..]))
+}
+
+/// Keeps signups from adding keys while they are being moved around, and
+/// other instances from rekeying at the same time.
+async fn lock_subscribers(transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query!("LOCK TABLE subscriptions IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut **transaction)
+        .await?;
+    Ok(())
+}
+
+/// Recomputes `email_key`, and the normalised address, of every subscriber
+/// with `SubscriberEmail::key`. Needed after `alias_folding_domains`
+/// changes, and for keys backfilled by the database before the application
+/// computed them.
+///
+/// Subscribers whose keys now match are merged into one, like the migration
+/// that introduced the keys did: the confirmed one if any, else the oldest.
+#[tracing::instrument(skip(pool))]
+pub async fn rekey_subscribers(
+    pool: &PgPool,
+    alias_folding_domains: &[String],
+) -> Result<RekeySummary, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    lock_subscribers(&mut transaction)
+        .await
+        .context("Failed to lock the subscribers")?;
+    let summary = rekey(&mut transaction, alias_folding_domains).await?;
+    transaction.commit().await?;
+    Ok(summary)
+}
+
+/// Rekeys the subscribers unless their keys were computed with the same
+/// `alias_folding_domains`. Run on startup, so the application never serves
+/// with keys that disagree with its configuration. Returns `None` when the
+/// keys were up to date.
+#[tracing::instrument(skip(pool))]
+pub async fn ensure_subscribers_keyed(
+    pool: &PgPool,
+    alias_folding_domains: &[String],
+) -> Result<Option<RekeySummary>, anyhow::Error> {
+    if keys_are_current(pool, alias_folding_domains)
+        .await
+        .context("Failed to look up how the subscribers were keyed")?
+    {
+        return Ok(None);
+    }
+    let mut transaction = pool.begin().await?;
+    lock_subscribers(&mut transaction)
+        .await
+        .context("Failed to lock the subscribers")?;
+    // Another instance may have rekeyed while this one waited for the lock.
+    if keys_are_current(&mut *transaction, alias_folding_domains)
+        .await
+        .context("Failed to look up how the subscribers were keyed")?
+    {
+        return Ok(None);
+    }
+    let summary = rekey(&mut transaction, alias_folding_domains).await?;
+    transaction.commit().await?;
+    Ok(Some(summary))
+}
+
+/// Expects the subscribers to be locked.
+async fn rekey(
+    transaction: &mut Transaction<'_, Postgres>,
+    alias_folding_domains: &[String],
+) -> Result<RekeySummary, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        StoredSubscriber,
+        r#"
+        SELECT id, email, email_key, status = 'confirmed' as "confirmed!", subscribed_at
+        FROM subscriptions
+        "#
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to retrieve the subscribers")?;
+
+    let mut summary = RekeySummary::default();
+    let mut by_key: HashMap<String, Vec<(StoredSubscriber, String)>> = HashMap::new();
+    for subscriber in subscribers {
+        match SubscriberEmail::parse(subscriber.email.clone()) {
+            Ok(email) => by_key
+                .entry(email.key(alias_folding_domains))
+                .or_default()
+                .push((subscriber, email.as_ref().to_string())),
+            Err(_) => summary.unparseable.push(subscriber.email),
+        }
+    }
+
+    let mut updates = Vec::new();
+    for (key, mut group) in by_key {
+        group.sort_by_key(|(s, _)| (!s.confirmed, s.subscribed_at, s.id));
+        let mut group = group.into_iter();
+        let Some((keeper, email)) = group.next() else {
+            continue;
+        };
+        for (duplicate, _) in group {
+            merge_subscriber(transaction, duplicate.id, keeper.id)
+                .await
+                .context("Failed to merge a duplicate subscriber")?;
+            summary.merged += 1;
+        }
+        if keeper.email_key != key || keeper.email != email {
+            updates.push((keeper.id, email, key));
+        }
+    }
+    // Moved out of the way first, as keys can be swapped between rows.
+    for (id, _, _) in &updates {
+        sqlx::query!(
+            "UPDATE subscriptions SET email_key = 'rekeying:' || id WHERE id = $1",
+            id
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to clear a subscriber key")?;
+    }
+    for (id, email, key) in &updates {
+        sqlx::query!(
+            "UPDATE subscriptions SET email = $2, email_key = $3 WHERE id = $1",
+            id,
+            email,
+            key
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to store a subscriber key")?;
+    }
+    summary.rekeyed = updates.len();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_key_settings (alias_folding_domains, rekeyed_at)
+        VALUES ($1, now())
+        ON CONFLICT (id) DO UPDATE
+        SET alias_folding_domains = EXCLUDED.alias_folding_domains,
+            rekeyed_at = EXCLUDED.rekeyed_at
+        "#,
+        &normalise_domains(alias_folding_domains)
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record how the subscribers were keyed")?;
+    Ok(summary)
+}
+
+/// Moves the memberships, tags, field values and history of a duplicate to
+/// the subscriber it is merged into, then deletes it.
+async fn merge_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    duplicate_id: Uuid,
+    keeper_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    for query in [
+        sqlx::query!(
+            r#"
+            INSERT INTO list_subscriptions (
+                list_id, subscriber_id, status, subscribed_at, reminders_sent,
+                last_reminder_at, opt_in, consented_at, pending_since
+            )
+            SELECT
+                list_id, $2, status, subscribed_at, reminders_sent, last_reminder_at,
+                opt_in, consented_at, pending_since
+            FROM list_subscriptions WHERE subscriber_id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+            duplicate_id,
+            keeper_id
+        ),
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+            SELECT $2, tag, tagged_at FROM subscriber_tags WHERE subscriber_id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+            duplicate_id,
+            keeper_id
+        ),
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_field_values (subscriber_id, field_id, value, updated_at)
+            SELECT $2, field_id, value, updated_at
+            FROM subscriber_field_values WHERE subscriber_id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+            duplicate_id,
+            keeper_id
+        ),
+        sqlx::query!(
+            "UPDATE subscription_tokens SET subscriber_id = $2 WHERE subscriber_id = $1",
+            duplicate_id,
+            keeper_id
+        ),
+        sqlx::query!(
+            "UPDATE consent_events SET subscriber_id = $2 WHERE subscriber_id = $1",
+            duplicate_id,
+            keeper_id
+        ),
+        sqlx::query!(
+            "UPDATE preference_changes SET subscriber_id = $2 WHERE subscriber_id = $1",
+            duplicate_id,
+            keeper_id
+        ),
+    ] {
+        query.execute(&mut **transaction).await?;
+    }
+    // Whatever was not moved goes with the duplicate.
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", duplicate_id)
+        .execute(&mut **transaction)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Tags are case-insensitive: they are stored lowercased and may only contain
+/// letters, digits, `-`, `_` and `:`.
+pub fn parse_tag(s: &str) -> Result<String, String> {
+    let tag = s.trim().to_lowercase();
+    let is_valid = !tag.is_empty()
+        && tag.chars().count() <= 64
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':'));
+    if is_valid {
+        Ok(tag)
+    } else {
+        Err(format!("{} is not a valid tag.", s.trim()))
+    }
+}
+
+/// Parses a comma separated list of tags, ignoring empty entries.
+pub fn parse_tag_list(s: &str) -> Result<Vec<String>, String> {
+    let mut tags = Vec::new();
+    for tag in s.split(',').filter(|t| !t.trim().is_empty()) {
+        let tag = parse_tag(tag)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn add_tags(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    tags: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT $1, * FROM UNNEST($2::text[])
+        ON CONFLICT (subscriber_id, tag) DO NOTHING
+        "#,
+        subscriber_id,
+        tags
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn remove_tags(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    tags: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = ANY($2)"#,
+        subscriber_id,
+        tags
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_tags(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag"#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.tag).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tag, parse_tag_list};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn tags_are_trimmed_and_lowercased() {
+        assert_ok_eq!(parse_tag("  Beta-Tester "), "beta-tester".to_string());
+    }
+
+    #[test]
+    fn tags_with_spaces_or_punctuation_are_rejected() {
+        for tag in ["", "two words", "semi;colon", "a/b"] {
+            assert_err!(parse_tag(tag));
+        }
+    }
+
+    #[test]
+    fn tag_lists_skip_empty_entries_and_duplicates() {
+        assert_ok_eq!(
+            parse_tag_list("rust, ,Rust,plan:pro,"),
+            vec!["rust".to_string(), "plan:pro".to_string()]
+        );
+    }
+}
@@ -0,0 +1,11 @@
+mod click;
+mod links;
+mod open;
+mod token;
+mod utm;
+
+pub use click::*;
+pub use links::is_absolute_http_url;
+pub use open::*;
+pub use token::*;
+pub use utm::*;
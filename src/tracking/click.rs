@@ -0,0 +1,161 @@
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use secrecy::{ExposeSecret, Secret};
+use sha3::Sha3_256;
+
+use super::links::replace_hrefs;
+
+/// Links carrying this attribute are left untouched by click tracking.
+pub const NO_TRACK_ATTRIBUTE: &str = "data-no-track";
+
+fn no_track_regex() -> &'static Regex {
+    static NO_TRACK: OnceLock<Regex> = OnceLock::new();
+    NO_TRACK.get_or_init(|| Regex::new(&format!(r#"(?i)\s{}\b"#, NO_TRACK_ATTRIBUTE)).unwrap())
+}
+
+fn mac(hmac_secret: &Secret<String>, tracking_token: &str, url: &str) -> Hmac<Sha3_256> {
+    let mut mac = Hmac::<Sha3_256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(tracking_token.as_bytes());
+    mac.update(b":");
+    mac.update(url.as_bytes());
+    mac
+}
+
+pub fn sign_click(hmac_secret: &Secret<String>, tracking_token: &str, url: &str) -> String {
+    hex::encode(
+        mac(hmac_secret, tracking_token, url)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+pub fn verify_click(
+    hmac_secret: &Secret<String>,
+    tracking_token: &str,
+    url: &str,
+    signature: &str,
+) -> Result<(), anyhow::Error> {
+    let signature = hex::decode(signature).context("The click signature is not valid hex")?;
+    mac(hmac_secret, tracking_token, url)
+        .verify_slice(&signature)
+        .context("The click signature does not match the link")
+}
+
+pub fn click_tracking_url(
+    base_url: &str,
+    tracking_token: &str,
+    url: &str,
+    hmac_secret: &Secret<String>,
+) -> String {
+    format!(
+        "{}/t/c/{}?url={}&sig={}",
+        base_url,
+        tracking_token,
+        urlencoding::encode(url),
+        sign_click(hmac_secret, tracking_token, url)
+    )
+}
+
+/// Points every absolute http(s) link of the issue at the click tracking
+/// endpoint, unless the anchor opted out with `data-no-track`.
+pub fn rewrite_links(
+    html_content: &str,
+    base_url: &str,
+    tracking_token: &str,
+    hmac_secret: &Secret<String>,
+) -> String {
+    replace_hrefs(html_content, |anchor, url| {
+        if no_track_regex().is_match(anchor) {
+            return None;
+        }
+        Some(click_tracking_url(
+            base_url,
+            tracking_token,
+            url,
+            hmac_secret,
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn absolute_links_are_rewritten() {
+        let html = r#"<p><a class="cta" href="https://example.com/?a=1&amp;b=2">Read</a></p>"#;
+        let rewritten = rewrite_links(html, "http://localhost", "token", &secret());
+        let expected_url = click_tracking_url(
+            "http://localhost",
+            "token",
+            "https://example.com/?a=1&b=2",
+            &secret(),
+        );
+        assert_eq!(
+            rewritten,
+            format!(
+                r#"<p><a class="cta" href="{}">Read</a></p>"#,
+                expected_url.replace('&', "&amp;")
+            )
+        );
+    }
+
+    #[test]
+    fn single_quoted_hrefs_are_rewritten() {
+        let html = "<a href='http://example.com'>Read</a>";
+        let rewritten = rewrite_links(html, "http://localhost", "token", &secret());
+        assert!(rewritten.starts_with(r#"<a href="http://localhost/t/c/token?url="#));
+    }
+
+    #[test]
+    fn relative_and_mailto_links_are_left_alone() {
+        let html = r##"<a href="#top">Top</a><a href="mailto:me@example.com">Mail</a>"##;
+        let rewritten = rewrite_links(html, "http://localhost", "token", &secret());
+        assert_eq!(rewritten, html);
+    }
+
+    #[test]
+    fn opted_out_links_are_left_alone() {
+        let html = r#"<a data-no-track href="https://example.com">Read</a>"#;
+        let rewritten = rewrite_links(html, "http://localhost", "token", &secret());
+        assert_eq!(rewritten, html);
+    }
+
+    #[test]
+    fn signatures_are_bound_to_token_and_url() {
+        let signature = sign_click(&secret(), "token", "https://example.com");
+        assert_ok!(verify_click(
+            &secret(),
+            "token",
+            "https://example.com",
+            &signature
+        ));
+        assert_err!(verify_click(
+            &secret(),
+            "token",
+            "https://evil.com",
+            &signature
+        ));
+        assert_err!(verify_click(
+            &secret(),
+            "another-token",
+            "https://example.com",
+            &signature
+        ));
+        assert_err!(verify_click(
+            &secret(),
+            "token",
+            "https://example.com",
+            "not-hex"
+        ));
+    }
+}
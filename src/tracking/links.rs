@@ -0,0 +1,67 @@
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+fn anchor_regex() -> &'static Regex {
+    static ANCHOR: OnceLock<Regex> = OnceLock::new();
+    ANCHOR.get_or_init(|| Regex::new(r#"(?is)<a\s[^>]*>"#).unwrap())
+}
+
+fn href_regex() -> &'static Regex {
+    static HREF: OnceLock<Regex> = OnceLock::new();
+    HREF.get_or_init(|| Regex::new(r#"(?is)(\shref\s*=\s*)(?:"([^"]*)"|'([^']*)')"#).unwrap())
+}
+
+fn text_url_regex() -> &'static Regex {
+    static TEXT_URL: OnceLock<Regex> = OnceLock::new();
+    TEXT_URL.get_or_init(|| Regex::new(r#"(?i)https?://[^\s<>"']+"#).unwrap())
+}
+
+/// Calls `f` with every anchor tag of `html_content` and the (entity decoded)
+/// absolute http(s) url it links to. The href is replaced by whatever `f`
+/// returns, or left untouched on `None`.
+pub(crate) fn replace_hrefs<F>(html_content: &str, f: F) -> String
+where
+    F: Fn(&str, &str) -> Option<String>,
+{
+    anchor_regex()
+        .replace_all(html_content, |anchor: &Captures| {
+            let anchor = &anchor[0];
+            href_regex()
+                .replace(anchor, |href: &Captures| {
+                    let raw = href.get(2).or_else(|| href.get(3)).unwrap().as_str();
+                    let url = htmlescape::decode_html(raw).unwrap_or_else(|_| raw.to_string());
+                    if !is_absolute_http_url(&url) {
+                        return href[0].to_string();
+                    }
+                    match f(anchor, &url) {
+                        Some(new_url) => {
+                            format!(r#"{}"{}""#, &href[1], new_url.replace('&', "&amp;"))
+                        }
+                        None => href[0].to_string(),
+                    }
+                })
+                .into_owned()
+        })
+        .into_owned()
+}
+
+/// Same as [`replace_hrefs`] for urls written out in plain text. Trailing
+/// punctuation is considered part of the sentence, not of the url.
+pub(crate) fn replace_text_urls<F>(text_content: &str, f: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    text_url_regex()
+        .replace_all(text_content, |m: &Captures| {
+            let matched = &m[0];
+            let url = matched.trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+            format!("{}{}", f(url), &matched[url.len()..])
+        })
+        .into_owned()
+}
+
+pub fn is_absolute_http_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
@@ -0,0 +1,32 @@
+use crate::utils::insert_before_body_end;
+
+/// Adds a 1x1 tracking image pointing at the open tracking endpoint, right
+/// before `</body>` when the issue has one, at the very end otherwise.
+pub fn inject_open_pixel(html_content: &str, base_url: &str, tracking_token: &str) -> String {
+    let pixel = format!(
+        r#"<img src="{}/t/o/{}.gif" width="1" height="1" alt="" style="display:none">"#,
+        base_url, tracking_token
+    );
+    insert_before_body_end(html_content, &pixel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_is_injected_before_closing_body_tag() {
+        let html = "<html><body><h1>Issue</h1></BODY></html>";
+        let tracked = inject_open_pixel(html, "http://localhost", "token");
+        assert_eq!(
+            tracked,
+            r#"<html><body><h1>Issue</h1><img src="http://localhost/t/o/token.gif" width="1" height="1" alt="" style="display:none"></BODY></html>"#
+        );
+    }
+
+    #[test]
+    fn pixel_is_appended_to_fragments() {
+        let tracked = inject_open_pixel("<h1>Issue</h1>", "http://localhost", "token");
+        assert!(tracked.starts_with("<h1>Issue</h1><img "));
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+pub fn generate_tracking_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+#[tracing::instrument(skip(transaction, tracking_token))]
+pub async fn store_tracking_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    tracking_token: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+    subject_variant: Option<i16>,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO tracking_tokens (
+            tracking_token,
+            newsletter_issue_id,
+            subscriber_email,
+            created_at,
+            subject_variant
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        tracking_token,
+        newsletter_issue_id,
+        subscriber_email,
+        Utc::now(),
+        subject_variant
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_tracking_token;
+
+    #[test]
+    fn tracking_tokens_are_unique() {
+        assert_ne!(generate_tracking_token(), generate_tracking_token());
+    }
+}
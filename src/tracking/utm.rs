@@ -0,0 +1,118 @@
+use reqwest::Url;
+
+use super::links::{replace_hrefs, replace_text_urls};
+
+#[derive(Debug, Clone)]
+pub struct UtmParameters {
+    pub source: String,
+    pub medium: String,
+    pub campaign: String,
+}
+
+impl UtmParameters {
+    /// Appends the UTM parameters to `url`, keeping its existing query
+    /// string. Links that already carry UTM parameters are left as they are.
+    pub fn tag_url(&self, url: &str) -> String {
+        let Ok(mut parsed) = Url::parse(url) else {
+            return url.to_string();
+        };
+        if !matches!(parsed.scheme(), "http" | "https")
+            || parsed
+                .query_pairs()
+                .any(|(key, _)| key.to_ascii_lowercase().starts_with("utm_"))
+        {
+            return url.to_string();
+        }
+        parsed
+            .query_pairs_mut()
+            .append_pair("utm_source", &self.source)
+            .append_pair("utm_medium", &self.medium)
+            .append_pair("utm_campaign", &self.campaign);
+        parsed.into()
+    }
+
+    pub fn tag_html_links(&self, html_content: &str) -> String {
+        replace_hrefs(html_content, |_, url| Some(self.tag_url(url)))
+    }
+
+    pub fn tag_text_links(&self, text_content: &str) -> String {
+        replace_text_urls(text_content, |url| self.tag_url(url))
+    }
+}
+
+/// Turns an issue title into something readable in an analytics dashboard,
+/// e.g. "July Update: What's new?" becomes "july-update-what-s-new".
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utm() -> UtmParameters {
+        UtmParameters {
+            source: "newsletter".into(),
+            medium: "email".into(),
+            campaign: "july-update".into(),
+        }
+    }
+
+    #[test]
+    fn parameters_are_appended_to_existing_query_strings() {
+        assert_eq!(
+            utm().tag_url("https://example.com/page?ref=1#section"),
+            "https://example.com/page?ref=1&utm_source=newsletter&utm_medium=email&utm_campaign=july-update#section"
+        );
+    }
+
+    #[test]
+    fn links_with_utm_parameters_are_skipped() {
+        let url = "https://example.com/?utm_source=twitter";
+        assert_eq!(utm().tag_url(url), url);
+    }
+
+    #[test]
+    fn non_http_links_are_skipped() {
+        assert_eq!(
+            utm().tag_url("mailto:me@example.com"),
+            "mailto:me@example.com"
+        );
+        assert_eq!(utm().tag_url("/relative"), "/relative");
+    }
+
+    #[test]
+    fn html_links_are_tagged() {
+        let html =
+            r#"<a href="https://example.com/?a=1&amp;b=2">Read</a><a href="/about">About</a>"#;
+        assert_eq!(
+            utm().tag_html_links(html),
+            r#"<a href="https://example.com/?a=1&amp;b=2&amp;utm_source=newsletter&amp;utm_medium=email&amp;utm_campaign=july-update">Read</a><a href="/about">About</a>"#
+        );
+    }
+
+    #[test]
+    fn text_links_are_tagged_without_trailing_punctuation() {
+        assert_eq!(
+            utm().tag_text_links("Read it at https://example.com/page."),
+            "Read it at https://example.com/page?utm_source=newsletter&utm_medium=email&utm_campaign=july-update."
+        );
+    }
+
+    #[test]
+    fn titles_are_slugified() {
+        assert_eq!(
+            slugify("July Update: What's new?"),
+            "july-update-what-s-new"
+        );
+        assert_eq!(slugify("  Ünïcode   title "), "ünïcode-title");
+    }
+}
@@ -19,3 +19,17 @@ pub fn see_other(location: &str) -> HttpResponse {
         .insert_header((header::LOCATION, location))
         .finish()
 }
+
+/// Inserts `snippet` right before `</body>` when the document has one, at the
+/// very end otherwise.
+pub fn insert_before_body_end(html_content: &str, snippet: &str) -> String {
+    match html_content.to_ascii_lowercase().rfind("</body>") {
+        Some(idx) => format!(
+            "{}{}{}",
+            &html_content[..idx],
+            snippet,
+            &html_content[idx..]
+        ),
+        None => format!("{}{}", html_content, snippet),
+    }
+}
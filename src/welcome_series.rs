@@ -0,0 +1,150 @@
+use anyhow::Context;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    custom_fields::get_merge_values,
+    domain::SubscriberEmail,
+    merge_tags::{render_html_merge_tags, render_merge_tags},
+    outbox::{enqueue_email, OutboxMessage},
+    preferences::{add_preferences_footer, get_preferences_token, preferences_url},
+};
+
+/// Schedules every step of the series the subscriber has not received yet,
+/// relative to now. Returns whether any step was scheduled.
+#[tracing::instrument(skip(connection))]
+pub async fn schedule_welcome_series(
+    connection: &mut PgConnection,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let scheduled = sqlx::query!(
+        r#"
+        INSERT INTO welcome_queue (subscriber_id, step_id, send_at)
+        SELECT $1, w.step_id, now() + make_interval(hours => w.delay_hours)
+        FROM welcome_steps w
+        WHERE NOT EXISTS (
+            SELECT 1 FROM welcome_deliveries d
+            WHERE d.subscriber_id = $1 AND d.step_id = w.step_id
+        )
+        ON CONFLICT (subscriber_id, step_id) DO NOTHING
+        "#,
+        subscriber_id
+    )
+    .execute(connection)
+    .await?
+    .rows_affected();
+    Ok(scheduled > 0)
+}
+
+#[tracing::instrument(skip(connection))]
+pub async fn cancel_welcome_series(
+    connection: &mut PgConnection,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM welcome_queue WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+/// Moves the due steps to the outbox, which retries failed deliveries
+/// without holding up the rest of the queue.
+#[tracing::instrument(skip_all)]
+pub async fn send_due_welcome_emails(pool: &PgPool, base_url: &str) -> Result<(), anyhow::Error> {
+    while try_send_next_welcome_email(pool, base_url).await? {}
+    Ok(())
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty, step_id = tracing::field::Empty)
+)]
+async fn try_send_next_welcome_email(pool: &PgPool, base_url: &str) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let due = sqlx::query!(
+        r#"
+        SELECT q.subscriber_id, q.step_id, s.email, s.status,
+            w.subject, w.html_content, w.text_content
+        FROM welcome_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        JOIN welcome_steps w ON w.step_id = q.step_id
+        WHERE q.send_at <= now()
+        ORDER BY q.send_at
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for due welcome emails")?;
+    let Some(due) = due else {
+        return Ok(false);
+    };
+    tracing::Span::current()
+        .record("subscriber_id", &tracing::field::display(due.subscriber_id))
+        .record("step_id", &tracing::field::display(due.step_id));
+    if due.status != "confirmed" {
+        cancel_welcome_series(&mut transaction, due.subscriber_id).await?;
+        transaction.commit().await?;
+        return Ok(true);
+    }
+    let merge_values = get_merge_values(&mut *transaction, &due.email).await?;
+    let subject = render_merge_tags(&due.subject, &merge_values);
+    let html_content = render_html_merge_tags(&due.html_content, &merge_values);
+    let text_content = render_merge_tags(&due.text_content, &merge_values);
+    let (html_content, text_content) =
+        match get_preferences_token(&mut transaction, &due.email).await? {
+            Some(token) => add_preferences_footer(
+                &html_content,
+                &text_content,
+                &preferences_url(base_url, &token),
+            ),
+            None => (html_content, text_content),
+        };
+    match SubscriberEmail::parse(due.email) {
+        Ok(email) => {
+            enqueue_email(
+                &mut *transaction,
+                OutboxMessage {
+                    kind: "welcome_series",
+                    recipient: &email,
+                    subject: &subject,
+                    html_content: &html_content,
+                    text_content: &text_content,
+                },
+            )
+            .await
+            .context("Failed to queue a welcome email")?;
+        }
+        Err(e) => tracing::error!(
+            error.message = %e,
+            "Skipping a confirmed subscriber. Their stored contact details are invalid",
+        ),
+    }
+    sqlx::query!(
+        r#"
+        DELETE FROM welcome_queue WHERE subscriber_id = $1 AND step_id = $2
+        "#,
+        due.subscriber_id,
+        due.step_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO welcome_deliveries (subscriber_id, step_id)
+        VALUES ($1, $2)
+        ON CONFLICT (subscriber_id, step_id) DO NOTHING
+        "#,
+        due.subscriber_id,
+        due.step_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,286 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> Option<(String, String)> {
+    sqlx::query!(
+        "SELECT name, status FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| (r.name, r.status))
+}
+
+async fn sign_up_pending(app: &TestApp, name: &str, email: &str) -> Uuid {
+    app.post_subscriptions(format!(
+        "name={}&email={}",
+        name,
+        urlencoding::encode(email)
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    subscriber_id(app, email).await
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_by_email_name_and_status() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    sign_up_pending(&app, "gorilla", "gorilla@yahoo.com").await;
+
+    let html = app.get_admin_html("/admin/subscribers").await;
+    assert!(html.contains("monkey@gmail.com"));
+    assert!(html.contains("gorilla@yahoo.com"));
+    assert!(html.contains("(2 subscribers)"));
+
+    let html = app.get_admin_html("/admin/subscribers?q=YAHOO").await;
+    assert!(!html.contains("monkey@gmail.com"));
+    assert!(html.contains("gorilla@yahoo.com"));
+
+    let html = app.get_admin_html("/admin/subscribers?q=monk").await;
+    assert!(html.contains("monkey@gmail.com"));
+    assert!(!html.contains("gorilla@yahoo.com"));
+
+    let html = app
+        .get_admin_html("/admin/subscribers?status=pending_confirmation")
+        .await;
+    assert!(!html.contains("monkey@gmail.com"));
+    assert!(html.contains("gorilla@yahoo.com"));
+
+    let html = app.get_admin_html("/admin/subscribers?to=2020-01-01").await;
+    assert!(html.contains("(0 subscribers)"));
+}
+
+#[tokio::test]
+async fn the_subscriber_list_is_paginated() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'subscriber' || n || '@gmail.com',
+            'subscriber' || n || '@gmail.com', 'subscriber',
+            now() - n * interval '1 minute', 'confirmed'
+        FROM generate_series(1, 60) n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let first = app.get_admin_html("/admin/subscribers").await;
+    assert!(first.contains("Page 1 of 2 (60 subscribers)"));
+    assert!(first.contains("subscriber1@gmail.com"));
+    assert!(!first.contains("subscriber60@gmail.com"));
+    assert!(first.contains(">Next</a>"));
+
+    let second = app.get_admin_html("/admin/subscribers?page=2").await;
+    assert!(second.contains("Page 2 of 2"));
+    assert!(second.contains("subscriber60@gmail.com"));
+    assert!(!second.contains("subscriber1@gmail.com<"));
+    assert!(second.contains(">Previous</a>"));
+
+    let response = app
+        .get_admin_path(&format!("/admin/subscribers?page={}", i64::MAX))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("of 2 (60 subscribers)"));
+}
+
+#[tokio::test]
+async fn the_detail_page_shows_the_subscriber_history() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    let id = subscriber_id(&app, "monkey@gmail.com").await;
+
+    let html = app
+        .get_admin_html(&format!("/admin/subscribers/{}", id))
+        .await;
+
+    assert!(html.contains("<li>Status: confirmed</li>"));
+    assert!(html.contains("<td>consent: signup</td>"));
+    assert!(html.contains("<td>consent: confirmation</td>"));
+    assert!(html.contains("<td>email: confirmation</td>"));
+    assert!(html.contains("<td>Newsletter</td><td>confirmed</td>"));
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .get_admin_path(&format!("/admin/subscribers/{}", Uuid::new_v4()))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn deleting_an_unknown_subscriber_is_not_found() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_action(Uuid::new_v4(), "delete", &[])
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_subscriber_manually() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let id = sign_up_pending(&app, "monkey", "monkey@gmail.com").await;
+
+    let response = app.post_subscriber_action(id, "confirm", &[]).await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    let html = app
+        .get_admin_html(&format!("/admin/subscribers/{}", id))
+        .await;
+    assert!(html.contains("The subscriber has been confirmed."));
+    assert_eq!(
+        subscriber_status(&app, "monkey@gmail.com").await,
+        Some(("monkey".into(), "confirmed".into()))
+    );
+    let consent =
+        sqlx::query!("SELECT note FROM consent_events WHERE event_type = 'admin_confirmation'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        consent.note.as_deref(),
+        Some("Confirmed by an administrator")
+    );
+    let membership = sqlx::query!("SELECT opt_in FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.opt_in, "manual");
+}
+
+#[tokio::test]
+async fn admins_can_resend_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let id = sign_up_pending(&app, "monkey", "monkey@gmail.com").await;
+    app.deliver_outbox().await;
+
+    let response = app.post_subscriber_action(id, "resend", &[]).await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", id));
+    app.deliver_outbox().await;
+
+    let tokens = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!" FROM subscription_tokens WHERE subscriber_id = $1"#,
+        id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens.count, 2);
+}
+
+#[tokio::test]
+async fn admins_can_unsubscribe_and_rename_a_subscriber() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    let id = subscriber_id(&app, "monkey@gmail.com").await;
+
+    app.post_subscriber_action(id, "name", &[("name", "Monkey D. Luffy")])
+        .await;
+    app.post_subscriber_action(id, "unsubscribe", &[]).await;
+
+    assert_eq!(
+        subscriber_status(&app, "monkey@gmail.com").await,
+        Some(("Monkey D. Luffy".into(), "unsubscribed".into()))
+    );
+    let html = app
+        .get_admin_html(&format!("/admin/subscribers/{}", id))
+        .await;
+    assert!(html.contains("name: monkey -&gt; Monkey D. Luffy"));
+    assert!(html.contains("status: confirmed -&gt; unsubscribed"));
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let id = sign_up_pending(&app, "monkey", "monkey@gmail.com").await;
+
+    app.post_subscriber_action(id, "name", &[("name", "  ")])
+        .await;
+
+    let html = app
+        .get_admin_html(&format!("/admin/subscribers/{}", id))
+        .await;
+    assert!(html.contains("not a valid subscriber name"));
+    assert_eq!(
+        subscriber_status(&app, "monkey@gmail.com").await,
+        Some(("monkey".into(), "pending_confirmation".into()))
+    );
+}
+
+#[tokio::test]
+async fn deleted_subscribers_can_sign_up_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    let id = subscriber_id(&app, "monkey@gmail.com").await;
+
+    let response = app.post_subscriber_action(id, "delete", &[]).await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    assert_eq!(subscriber_status(&app, "monkey@gmail.com").await, None);
+    let suppressions = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM suppressions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressions.count, 0);
+    sign_up_pending(&app, "monkey", "monkey@gmail.com").await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_path("/admin/subscribers").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_subscriber_action(Uuid::new_v4(), "delete", &[])
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,332 @@
+use chrono::{Duration, Utc};
+use monkey_letter::{
+    bot_protection::{form_stamp, CaptchaProvider},
+    configuration::CaptchaSettings,
+};
+use secrecy::Secret;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{spawn_app, spawn_app_with, TestApp};
+
+async fn subscriber_count(app: &TestApp) -> Option<i64> {
+    sqlx::query!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+async fn confirmation_count(app: &TestApp) -> Option<i64> {
+    sqlx::query!("SELECT count(*) FROM outbox WHERE kind = 'confirmation'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+fn stamp_from(html: &str) -> String {
+    let start = html.find(r#"name="form_stamp" value=""#).unwrap() + 25;
+    html[start..][..html[start..].find('"').unwrap()].to_string()
+}
+
+#[tokio::test]
+async fn the_signup_form_carries_a_honeypot_and_a_form_stamp() {
+    let app = spawn_app().await;
+
+    let html = app.get_subscribe_form("?list=weekly&form_id=footer").await;
+
+    assert!(html.contains(r#"name="website""#));
+    assert!(html.contains(r#"name="list" value="weekly""#));
+    assert!(html.contains(r#"name="form_id" value="footer""#));
+    assert!(!stamp_from(&html).is_empty());
+}
+
+#[tokio::test]
+async fn signups_filling_in_the_honeypot_are_silently_dropped() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com&website=spam.example".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(0));
+    assert_eq!(confirmation_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn forms_submitted_too_quickly_are_silently_dropped() {
+    let app = spawn_app().await;
+    let stamp = stamp_from(&app.get_subscribe_form("").await);
+
+    let response = app
+        .post_subscriptions(format!(
+            "name=monkey&email=monkey%40gmail.com&form_stamp={}",
+            stamp
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn forged_form_stamps_are_silently_dropped() {
+    let app = spawn_app().await;
+    let forged = form_stamp(
+        &Secret::new("not-the-secret".into()),
+        Utc::now() - Duration::seconds(30),
+    );
+
+    app.post_subscriptions(format!(
+        "name=monkey&email=monkey%40gmail.com&form_stamp={}",
+        forged
+    ))
+    .await;
+
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn forms_filled_in_at_human_speed_go_through() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions(format!(
+            "name=monkey&email=monkey%40gmail.com&website=&form_stamp={}",
+            app.old_form_stamp()
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(1));
+    assert_eq!(confirmation_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn form_posts_without_a_form_stamp_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_unstamped("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn expired_form_stamps_are_rejected() {
+    let app = spawn_app().await;
+    let stamp = form_stamp(&app.hmac_secret, Utc::now() - Duration::days(2));
+
+    let response = app
+        .post_subscriptions(format!(
+            "name=monkey&email=monkey%40gmail.com&form_stamp={}",
+            stamp
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("expired"));
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn json_calls_need_a_form_stamp_too() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json_unstamped(&serde_json::json!({
+            "name": "monkey",
+            "email": "monkey@gmail.com"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn json_calls_can_be_let_through_without_a_form_stamp() {
+    let app = spawn_app_with(|c| c.bot_protection.require_form_stamp = false).await;
+
+    let response = app
+        .post_subscriptions_json_unstamped(&serde_json::json!({
+            "name": "monkey",
+            "email": "monkey@gmail.com"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn form_stamps_cannot_be_replayed() {
+    let app = spawn_app().await;
+    let stamp = app.old_form_stamp();
+
+    let first = app
+        .post_subscriptions(format!(
+            "name=monkey&email=monkey%40gmail.com&form_stamp={}",
+            stamp
+        ))
+        .await;
+    let replayed = app
+        .post_subscriptions(format!(
+            "name=gorilla&email=gorilla%40gmail.com&form_stamp={}",
+            stamp
+        ))
+        .await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(replayed.status().as_u16(), 400);
+    assert!(replayed
+        .text()
+        .await
+        .unwrap()
+        .contains("This form has already been submitted."));
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn a_rejected_signup_can_be_corrected_with_the_same_form() {
+    let app = spawn_app().await;
+    let stamp = app.old_form_stamp();
+
+    let rejected = app
+        .post_subscriptions(format!(
+            "name=monkey&email=not-an-email&form_stamp={}",
+            stamp
+        ))
+        .await;
+    let corrected = app
+        .post_subscriptions(format!(
+            "name=monkey&email=monkey%40gmail.com&form_stamp={}",
+            stamp
+        ))
+        .await;
+
+    assert_eq!(rejected.status().as_u16(), 400);
+    assert_eq!(corrected.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn captcha_responses_are_checked_when_enabled() {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.captcha = CaptchaSettings::Stub {
+            valid_response: "human".into(),
+        }
+    })
+    .await;
+
+    for (body, status) in [
+        ("name=monkey&email=monkey%40gmail.com", 400),
+        (
+            "name=monkey&email=monkey%40gmail.com&captcha_response=robot",
+            400,
+        ),
+        (
+            "name=monkey&email=monkey%40gmail.com&captcha_response=human",
+            200,
+        ),
+    ] {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), status, "{}", body);
+    }
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn captcha_widgets_are_verified_with_the_provider() {
+    let app = spawn_app_with(|c| {
+        // The email mock server stands in for the provider.
+        let verify_url = format!("{}/siteverify", c.email_client.base_url);
+        c.bot_protection.captcha = CaptchaSettings::SiteVerify {
+            provider: CaptchaProvider::Hcaptcha,
+            site_key: "sitekey123".into(),
+            secret: Secret::new("captcha-secret".into()),
+            verify_url: Some(verify_url),
+            timeout_milliseconds: 1000,
+        }
+    })
+    .await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=captcha-secret"))
+        .and(body_string_contains("response=widget-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let html = app.get_subscribe_form("").await;
+    assert!(html.contains(r#"class="h-captcha" data-sitekey="sitekey123""#));
+    let response = app
+        .post_subscriptions(
+            "name=monkey&email=monkey%40gmail.com&h-captcha-response=widget-token".into(),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn confirmation_emails_to_an_address_are_rate_limited() {
+    let app = spawn_app_with(|c| c.bot_protection.confirmations_per_email = 2).await;
+
+    for _ in 0..3 {
+        let response = app
+            .post_subscriptions("name=monkey&email=monkey%40gmail.com".into())
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // Case and spacing variations count towards the same address.
+    app.post_subscriptions("name=monkey&email=MONKEY%40gmail.com".into())
+        .await;
+
+    assert_eq!(confirmation_count(&app).await, Some(2));
+}
+
+#[tokio::test]
+async fn confirmation_emails_from_a_client_are_rate_limited() {
+    let app = spawn_app_with(|c| c.bot_protection.confirmations_per_ip = 2).await;
+
+    for (n, status) in [(1, 200), (2, 200), (3, 429)] {
+        let response = app
+            .post_subscriptions(format!("name=monkey&email=monkey{}%40gmail.com", n))
+            .await;
+        assert_eq!(response.status().as_u16(), status);
+    }
+
+    assert_eq!(subscriber_count(&app).await, Some(2));
+    assert_eq!(confirmation_count(&app).await, Some(2));
+}
+
+#[tokio::test]
+async fn spoofed_forwarding_headers_do_not_escape_the_client_limit() {
+    let app = spawn_app_with(|c| c.bot_protection.confirmations_per_ip = 2).await;
+
+    for (n, status) in [(1, 200), (2, 200), (3, 429)] {
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("198.51.100.{}", n))
+            .body(format!(
+                "name=monkey&email=monkey{}%40gmail.com&form_stamp={}",
+                n,
+                app.old_form_stamp()
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), status);
+    }
+}
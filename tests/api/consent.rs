@@ -0,0 +1,165 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+
+const USER_AGENT: &str = "Mozilla/5.0 (Monkey)";
+
+async fn sign_up(app: &TestApp, body: &str) {
+    app.api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("User-Agent", USER_AGENT)
+        .body(format!("{}&form_stamp={}", body, app.old_form_stamp()))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .error_for_status()
+        .unwrap();
+}
+
+async fn confirm_latest_signup(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.deliver_outbox().await;
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_confirmation_link(&email_req);
+    app.api_client
+        .get(link.html)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn signing_up_records_where_consent_was_given() {
+    let app = spawn_app().await;
+
+    sign_up(
+        &app,
+        "name=monkey&email=monkey%40gmail.com&form_id=footer&consent_version=v2",
+    )
+    .await;
+
+    let event = sqlx::query!(
+        "SELECT event_type, ip_address, user_agent, form_id, consent_text_version FROM consent_events"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.event_type, "signup");
+    assert_eq!(event.ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(event.user_agent.as_deref(), Some(USER_AGENT));
+    assert_eq!(event.form_id.as_deref(), Some("footer"));
+    assert_eq!(event.consent_text_version.as_deref(), Some("v2"));
+}
+
+#[tokio::test]
+async fn signups_without_a_consent_version_use_the_configured_one() {
+    let app = spawn_app().await;
+
+    sign_up(&app, "name=monkey&email=monkey%40gmail.com").await;
+
+    let event = sqlx::query!("SELECT form_id, consent_text_version FROM consent_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.form_id, None);
+    assert_eq!(
+        event.consent_text_version,
+        Some(app.subscription_settings.consent_text_version.clone())
+    );
+}
+
+#[tokio::test]
+async fn confirming_records_a_confirmation_event_for_the_same_form() {
+    let app = spawn_app().await;
+    sign_up(
+        &app,
+        "name=monkey&email=monkey%40gmail.com&form_id=footer&consent_version=v2",
+    )
+    .await;
+
+    confirm_latest_signup(&app).await;
+
+    let events = sqlx::query!(
+        "SELECT event_type, user_agent, form_id, consent_text_version
+        FROM consent_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].event_type, "confirmation");
+    assert_eq!(events[1].user_agent.as_deref(), Some(USER_AGENT));
+    assert_eq!(events[1].form_id.as_deref(), Some("footer"));
+    assert_eq!(events[1].consent_text_version.as_deref(), Some("v2"));
+}
+
+#[tokio::test]
+async fn admins_can_see_the_consent_history_of_a_subscriber() {
+    let app = spawn_app().await;
+    sign_up(&app, "name=monkey&email=monkey%40gmail.com&form_id=footer").await;
+    confirm_latest_signup(&app).await;
+    sign_up(&app, "name=other&email=other%40gmail.com&form_id=sidebar").await;
+    app.test_user.login(&app).await;
+
+    let html = app.get_consent_html("monkey@gmail.com").await;
+
+    assert!(html.contains("<td>signup</td>"));
+    assert!(html.contains("<td>confirmation</td>"));
+    assert!(html.contains("<td>footer</td>"));
+    assert!(html.contains(USER_AGENT));
+    assert!(!html.contains("sidebar"));
+}
+
+#[tokio::test]
+async fn consent_records_can_be_exported_as_csv() {
+    let app = spawn_app().await;
+    sign_up(&app, "name=monkey&email=monkey%40gmail.com&form_id=footer").await;
+    sign_up(&app, "name=other&email=other%40gmail.com&form_id=sidebar").await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_consent_export(Some("monkey@gmail.com")).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .contains("attachment"));
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("email,list,event,ip_address"));
+    assert!(lines[1].starts_with("monkey@gmail.com,default,signup,127.0.0.1"));
+
+    let everything = app.get_consent_export(None).await.text().await.unwrap();
+    assert_eq!(everything.lines().count(), 3);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_consent_records() {
+    let app = spawn_app().await;
+
+    let response = app.get_consent_export(None).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
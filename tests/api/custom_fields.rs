@@ -0,0 +1,218 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn create_fields(app: &TestApp) {
+    for field in [
+        serde_json::json!({"key": "age", "label": "Age", "field_type": "number"}),
+        serde_json::json!({
+            "key": "plan",
+            "label": "Plan",
+            "field_type": "choice",
+            "options": "free, pro"
+        }),
+    ] {
+        let response = app.post_field(&field).await;
+        assert_is_redirect_to(&response, "/admin/fields");
+    }
+}
+
+async fn get_field_value(app: &TestApp, key: &str) -> Option<String> {
+    sqlx::query!(
+        r#"
+        SELECT v.value
+        FROM subscriber_field_values v JOIN custom_fields f ON f.field_id = v.field_id
+        WHERE f.key = $1
+        "#,
+        key
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| r.value)
+}
+
+#[tokio::test]
+async fn an_admin_can_define_custom_fields() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    create_fields(&app).await;
+
+    let html_page = app.get_fields_html().await;
+    assert!(html_page.contains(
+        "<tr><td>plan</td><td>Plan</td><td>choice</td><td>free, pro</td><td>no</td></tr>"
+    ));
+}
+
+#[tokio::test]
+async fn custom_field_keys_must_be_snake_case() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_field(
+        &serde_json::json!({"key": "Company Size", "label": "Size", "field_type": "text"}),
+    )
+    .await;
+
+    let html_page = app.get_fields_html().await;
+    assert!(html_page.contains("The key must be snake_case"));
+}
+
+#[tokio::test]
+async fn signup_form_values_are_validated_by_type() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_fields(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com&age=old".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com&age=42&plan=pro".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.deliver_outbox().await;
+    assert_eq!(get_field_value(&app, "age").await.as_deref(), Some("42"));
+    assert_eq!(get_field_value(&app, "plan").await.as_deref(), Some("pro"));
+}
+
+#[tokio::test]
+async fn the_json_api_validates_custom_fields() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_fields(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "monkey",
+            "email": "monkey@gmail.com",
+            "plan": "enterprise"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "monkey",
+            "email": "monkey@gmail.com",
+            "age": 42.5,
+            "plan": "free"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.deliver_outbox().await;
+    assert_eq!(get_field_value(&app, "age").await.as_deref(), Some("42.5"));
+}
+
+#[tokio::test]
+async fn required_fields_must_be_filled_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_field(&serde_json::json!({
+        "key": "company",
+        "label": "Company",
+        "field_type": "text",
+        "required": "true"
+    }))
+    .await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn an_admin_can_edit_a_subscribers_value() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_fields(&app).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com&plan=free".into())
+        .await;
+
+    let response = app
+        .post_field_value(&serde_json::json!({
+            "email": "monkey@gmail.com",
+            "key": "plan",
+            "value": "pro"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/fields");
+
+    assert_eq!(get_field_value(&app, "plan").await.as_deref(), Some("pro"));
+}
+
+#[tokio::test]
+async fn custom_fields_drive_merge_tags_and_segments() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_fields(&app).await;
+    app.subscribe_and_confirm("name=Ursula&email=pro%40example.com&plan=pro&age=40".into())
+        .await;
+    app.subscribe_and_confirm("name=Jorge&email=free%40example.com&plan=free&age=40".into())
+        .await;
+    app.post_segment(&serde_json::json!({
+        "name": "Seasoned pros",
+        "field_conditions": "plan = pro\nage > 35"
+    }))
+    .await;
+    let segment_id = sqlx::query!("SELECT segment_id FROM segments")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .segment_id;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Hello {{ name }}",
+        "html_content": "<p>You are on {{ plan }} in {{ city | your city }}</p>",
+        "text_content": "You are on {{ plan }}",
+        "segment": segment_id.to_string(),
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["To"], "pro@example.com");
+    assert_eq!(body["Subject"], "Hello Ursula");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>You are on pro in your&#x20;city</p>"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("You are on pro\n"));
+}
@@ -0,0 +1,139 @@
+use monkey_letter::configuration::ResolverSettings;
+
+use crate::helper::{spawn_app, spawn_app_with, TestApp};
+
+async fn subscriber_count(app: &TestApp) -> Option<i64> {
+    sqlx::query!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40eu.mailinator.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("disposable"));
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn typos_of_common_domains_are_rejected_with_a_suggestion() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmial.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Did you mean monkey@gmail.com?"));
+    assert_eq!(subscriber_count(&app).await, Some(0));
+}
+
+#[tokio::test]
+async fn an_address_submitted_again_after_a_suggestion_is_accepted() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40protonmail.co";
+
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(first.status().as_u16(), 400);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
+
+#[tokio::test]
+async fn a_suggestion_is_only_made_once_per_address() {
+    let app = spawn_app().await;
+
+    let first = app
+        .post_subscriptions("name=monkey&email=monkey%40gmial.com".into())
+        .await;
+    let other = app
+        .post_subscriptions("name=gorilla&email=gorilla%40gmial.com".into())
+        .await;
+    let again = app
+        .post_subscriptions("name=monkey&email=Monkey%40gmial.com".into())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 400);
+    assert_eq!(other.status().as_u16(), 400);
+    assert_eq!(again.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_suggestion_is_made_again_once_it_expires() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40gmial.com";
+
+    let first = app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE typo_suggestions SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(first.status().as_u16(), 400);
+    assert_eq!(second.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn real_providers_close_to_common_ones_are_accepted() {
+    let app = spawn_app().await;
+
+    for email in ["protonmail.ch", "yahoo.co.jp", "hotmail.co.jp", "email.com"] {
+        let response = app
+            .post_subscriptions(format!("name=monkey&email=monkey%40{}", email))
+            .await;
+        assert_eq!(response.status().as_u16(), 200, "{}", email);
+    }
+}
+
+#[tokio::test]
+async fn suggestions_can_be_turned_off() {
+    let app = spawn_app_with(|c| c.deliverability.suggest_corrections = false).await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmial.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn domains_without_mail_records_are_rejected_when_checked() {
+    let app = spawn_app_with(|c| {
+        c.deliverability.check_domains = true;
+        c.deliverability.resolver = ResolverSettings::Static {
+            domains: vec!["gmail.com".into()],
+        };
+    })
+    .await;
+
+    let rejected = app
+        .post_subscriptions("name=monkey&email=monkey%40nowhere.example".into())
+        .await;
+    let accepted = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+
+    assert_eq!(rejected.status().as_u16(), 400);
+    assert!(rejected
+        .text()
+        .await
+        .unwrap()
+        .contains("nowhere.example does not accept email."));
+    assert_eq!(accepted.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, Some(1));
+}
@@ -1,16 +1,27 @@
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::{Duration, Utc};
 use monkey_letter::{
-    configuration::{self, DatabaseSettings},
+    ab_test::decide_ab_tests,
+    bot_protection::form_stamp,
+    configuration::{self, DatabaseSettings, SubscriptionSettings},
+    confirmation_reminders::send_confirmation_reminders,
+    digest::send_due_digests,
     email_client::EmailClient,
     issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    outbox::process_outbox,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
+    welcome_series::send_due_welcome_emails,
 };
 use once_cell::sync::Lazy;
 use reqwest::Client;
+use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let (name, env_filter) = ("test", "debug");
@@ -34,21 +45,55 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: Client,
     pub email_client: EmailClient,
+    pub hmac_secret: Secret<String>,
+    pub subscription_settings: SubscriptionSettings,
 }
 
 impl TestApp {
+    pub async fn deliver_outbox(&self) {
+        process_outbox(&self.db_pool, &self.email_client)
+            .await
+            .unwrap();
+    }
     pub async fn dispatch_all_pending_emails(&self) {
+        self.deliver_outbox().await;
+        decide_ab_tests(&self.db_pool).await.unwrap();
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.address,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
+        send_due_digests(&self.db_pool, &self.email_client, &self.address)
+            .await
+            .unwrap();
+        send_due_welcome_emails(&self.db_pool, &self.address)
+            .await
+            .unwrap();
+        send_confirmation_reminders(&self.db_pool, &self.address, &self.subscription_settings)
+            .await
+            .unwrap();
+        // Reminders and welcome emails go out through the outbox.
+        self.deliver_outbox().await;
     }
+    /// Posts the signup form. Bodies without a form stamp get one from a
+    /// form rendered long enough ago.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let body = if body.contains("form_stamp=") {
+            body
+        } else {
+            format!("{}&form_stamp={}", body, self.old_form_stamp())
+        };
+        self.post_subscriptions_unstamped(body).await
+    }
+    pub async fn post_subscriptions_unstamped(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(&format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
@@ -57,6 +102,74 @@ impl TestApp {
             .await
             .expect("Failed to execute request")
     }
+    pub fn old_form_stamp(&self) -> String {
+        form_stamp(&self.hmac_secret, Utc::now() - Duration::seconds(30))
+    }
+    pub async fn get_subscribe_form(&self, query: &str) -> String {
+        self.api_client
+            .get(&format!("{}/subscriptions{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    /// Posts a JSON signup, stamped like `post_subscriptions` unless the
+    /// body has a form stamp already.
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        let mut body = body.clone();
+        if body.get("form_stamp").is_none() {
+            body["form_stamp"] = self.old_form_stamp().into();
+        }
+        self.post_subscriptions_json_unstamped(&body).await
+    }
+    pub async fn post_subscriptions_json_unstamped(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    /// Signs up with the given form body and follows the confirmation link.
+    pub async fn subscribe_and_confirm(&self, body: String) {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+        self.deliver_outbox().await;
+        let email_req = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let link = self.get_confirmation_link(&email_req);
+        reqwest::get(link.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    pub async fn post_resend_confirmation(&self, subscription_token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/confirm/resend", self.address))
+            .form(&[("subscription_token", subscription_token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
     pub fn get_confirmation_link(&self, email_req: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
 
@@ -94,6 +207,254 @@ impl TestApp {
             .await
             .expect("Failed to get text")
     }
+    pub async fn post_issue_action(&self, issue_id: Uuid, action: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/issues/{}/{}",
+                self.address, issue_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn post_list(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lists", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .expect("Failed to get text")
+    }
+    pub async fn post_field(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/fields", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn post_field_value(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/fields/values", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_fields_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/fields", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .expect("Failed to get text")
+    }
+    pub async fn post_segment(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/segments", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_segments_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/segments", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .expect("Failed to get text")
+    }
+    pub async fn post_subscriber_tags(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/tags", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn post_welcome_step(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/welcome", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_welcome_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/welcome", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn get_consent_html(&self, email: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/consent", self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn get_consent_export(&self, email: Option<&str>) -> reqwest::Response {
+        let mut request = self
+            .api_client
+            .get(format!("{}/admin/consent/export", self.address));
+        if let Some(email) = email {
+            request = request.query(&[("email", email)]);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+    pub async fn post_privacy_request(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/privacy", self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_privacy_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/privacy", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn get_privacy_export(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/privacy/export", self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn post_privacy_erase(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/privacy/erase", self.address))
+            .form(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    /// Uploads `csv` to the import form, along with the other form fields.
+    pub async fn post_import(&self, csv: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+        let boundary = "monkey-letter-boundary";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\nContent-Type: text/csv\r\n\r\n{csv}\r\n"
+        );
+        for (name, value) in fields {
+            body.push_str(&format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            ));
+        }
+        body.push_str(&format!("--{boundary}--\r\n"));
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", self.address))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_import_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn get_admin_path(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_admin_html(&self, path: &str) -> String {
+        self.get_admin_path(path).await.text().await.unwrap()
+    }
+    pub async fn post_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+        form: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                self.address, subscriber_id, action
+            ))
+            .form(form)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_preferences(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/preferences", self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_preferences_html(&self, token: &str) -> String {
+        self.get_preferences(token).await.text().await.unwrap()
+    }
+    pub async fn post_preferences(&self, body: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/preferences", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn post_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/preferences/unsubscribe", self.address))
+            .form(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_issue_html(&self, issue_id: Uuid) -> String {
+        self.api_client
+            .get(format!("{}/admin/issues/{}", self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .expect("Failed to get text")
+    }
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -160,6 +521,11 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, with a chance to tweak the configuration first.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut configuration::Settings)) -> TestApp {
     Lazy::force(&TRACING);
     let email_server = MockServer::start().await;
     let config = {
@@ -167,6 +533,9 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Test apps share one Redis, so each gets its own rate limit buckets.
+        c.rate_limits.key_prefix = format!("rate_limit:{}", Uuid::new_v4());
+        configure(&mut c);
         c
     };
     configure_database(&config.database).await;
@@ -193,6 +562,8 @@ pub async fn spawn_app() -> TestApp {
         test_user,
         api_client: client,
         email_client: config.email_client.client(),
+        hmac_secret: config.application.hmac_secret,
+        subscription_settings: config.subscriptions,
     }
 }
 //clean up is not implemented. probably better to do so.
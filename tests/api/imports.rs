@@ -0,0 +1,308 @@
+use monkey_letter::privacy::suppression_hash;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// Uploads the CSV and waits for the import to finish in the background.
+async fn import_location(app: &TestApp, csv: &str, fields: &[(&str, &str)]) -> String {
+    let response = app.post_import(csv, fields).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let import_id: Uuid = location
+        .trim_start_matches("/admin/subscribers/imports/")
+        .parse()
+        .unwrap();
+    for _ in 0..300 {
+        let status = sqlx::query_scalar!(
+            "SELECT status FROM subscriber_imports WHERE import_id = $1",
+            import_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        if status != "running" {
+            return location;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("The import did not finish");
+}
+
+async fn subscriber_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let location = import_location(
+        &app,
+        "email,name\nmonkey@gmail.com,monkey\ngorilla@gmail.com,gorilla\n",
+        &[("list", "default")],
+    )
+    .await;
+    assert!(location.starts_with("/admin/subscribers/imports/"));
+    app.deliver_outbox().await;
+
+    assert_eq!(
+        subscriber_statuses(&app).await,
+        vec![
+            ("gorilla@gmail.com".into(), "pending_confirmation".into()),
+            ("monkey@gmail.com".into(), "pending_confirmation".into()),
+        ]
+    );
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("The import has started."));
+    assert!(html.contains("<li>Status: Completed</li>"));
+    assert!(html.contains("<li>Imported: 2</li>"));
+}
+
+#[tokio::test]
+async fn invalid_duplicate_and_suppressed_rows_are_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.subscribe_and_confirm("name=existing&email=existing%40gmail.com".into())
+        .await;
+    sqlx::query!(
+        "INSERT INTO suppressions (email_hash, reason) VALUES ($1, 'erasure_request')",
+        suppression_hash(&app.hmac_secret, "erased@gmail.com")
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let csv = "name,email\n\
+        monkey,monkey@gmail.com\n\
+        no email,not-an-email\n\
+        ,nameless@gmail.com\n\
+        monkey again,monkey@gmail.com\n\
+        existing,existing@gmail.com\n\
+        erased,erased@gmail.com\n";
+    let location = import_location(&app, csv, &[("list", "default")]).await;
+
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("<li>Imported: 1</li>"));
+    assert!(html.contains("<li>Skipped duplicates: 2</li>"));
+    assert!(html.contains("<li>Skipped suppressed addresses: 1</li>"));
+    assert!(html.contains("<li>Rejected invalid rows: 2</li>"));
+
+    let report = app.get_admin_path(&format!("{}/report", location)).await;
+    assert!(report.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .contains("attachment"));
+    let report = report.text().await.unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines[0], "line,email,reason");
+    assert!(lines[1].starts_with("3,not-an-email,"));
+    assert!(lines[2].starts_with("4,nameless@gmail.com,"));
+    assert_eq!(lines[3], "5,monkey@gmail.com,duplicate row");
+    assert_eq!(lines[4], "6,existing@gmail.com,already subscribed");
+    assert_eq!(lines[5], "7,erased@gmail.com,suppressed");
+}
+
+#[tokio::test]
+async fn aliases_of_suppressed_addresses_are_skipped() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.alias_folding_domains = vec!["gmail.com".into()];
+    })
+    .await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "INSERT INTO suppressions (email_hash, reason) VALUES ($1, 'erasure_request')",
+        suppression_hash(&app.hmac_secret, "monkeyface@gmail.com")
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let csv = "email,name\n\
+        Monkey.Face+news@gmail.com,monkey\n\
+        old.monkey@gmail.com,old monkey\n";
+    let location = import_location(&app, csv, &[("list", "default")]).await;
+
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("<li>Imported: 1</li>"));
+    assert!(html.contains("<li>Skipped suppressed addresses: 1</li>"));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_imported_as_confirmed_with_a_consent_note() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_welcome_step(&serde_json::json!({
+        "subject": "Welcome",
+        "html_content": "<p>Welcome</p>",
+        "text_content": "Welcome",
+        "delay_hours": "24",
+    }))
+    .await;
+
+    import_location(
+        &app,
+        "email,name\nmonkey@gmail.com,monkey\n",
+        &[
+            ("list", "default"),
+            ("confirmed", "on"),
+            ("consent_note", "Opted in on our old provider"),
+        ],
+    )
+    .await;
+    app.deliver_outbox().await;
+
+    assert_eq!(
+        subscriber_statuses(&app).await,
+        vec![("monkey@gmail.com".into(), "confirmed".into())]
+    );
+    let consent = sqlx::query!("SELECT event_type, note FROM consent_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(consent.event_type, "import");
+    assert_eq!(
+        consent.note.as_deref(),
+        Some("Opted in on our old provider")
+    );
+    let membership = sqlx::query!("SELECT status, opt_in, consented_at FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.status, "confirmed");
+    assert_eq!(membership.opt_in, "manual");
+    assert!(membership.consented_at.is_some());
+    let queued = sqlx::query!("SELECT count(*) as \"count!\" FROM welcome_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 1);
+}
+
+#[tokio::test]
+async fn rows_that_fail_to_import_are_reported_and_the_rest_imported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "ALTER TABLE subscriptions ADD CONSTRAINT no_broken CHECK (email <> 'broken@gmail.com')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let csv = "email,name\n\
+        monkey@gmail.com,monkey\n\
+        broken@gmail.com,broken\n\
+        gorilla@gmail.com,gorilla\n";
+    let location = import_location(&app, csv, &[("list", "default")]).await;
+
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("<li>Imported: 2</li>"));
+    assert!(html.contains("<li>Rows that failed to import: 1</li>"));
+    let report = app
+        .get_admin_path(&format!("{}/report", location))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(
+        report.lines().nth(1),
+        Some("3,broken@gmail.com,could not be imported")
+    );
+}
+
+#[tokio::test]
+async fn large_files_are_imported_from_disk() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..500 {
+        csv.push_str(&format!("monkey{}@gmail.com,monkey {}\n", i, i));
+    }
+
+    let location = import_location(&app, &csv, &[("list", "default")]).await;
+
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("<li>Imported: 500</li>"));
+}
+
+#[tokio::test]
+async fn uploads_without_a_list_go_to_the_default_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let location = import_location(&app, "email,name\nmonkey@gmail.com,monkey\n", &[]).await;
+
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("<li>Imported: 1</li>"));
+    let list = sqlx::query_scalar!(
+        "SELECT l.slug FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(list, "default");
+}
+
+#[tokio::test]
+async fn confirmed_imports_need_a_consent_note() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_import(
+            "email,name\nmonkey@gmail.com,monkey\n",
+            &[("list", "default"), ("confirmed", "on")],
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let html = app.get_import_html().await;
+    assert!(html.contains("A consent note is required to import subscribers as confirmed."));
+    assert!(subscriber_statuses(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn files_without_an_email_column_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let location = import_location(&app, "name\nmonkey\n", &[("list", "default")]).await;
+
+    let html = app.get_admin_path(&location).await.text().await.unwrap();
+    assert!(html.contains("<li>Status: Failed: The CSV has no `email` column.</li>"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_import("email,name\nmonkey@gmail.com,monkey\n", &[])
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
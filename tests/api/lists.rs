@@ -0,0 +1,287 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+async fn sent_subjects(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn an_admin_can_create_a_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_list(&serde_json::json!({"name": "Weekly digest", "slug": "weekly"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("<p><i>The list Weekly digest has been created.</i></p>"));
+    assert!(
+        html_page.contains("<tr><td>Weekly digest</td><td>weekly</td><td>default (double)</td><td>0</td><td>0</td></tr>")
+    );
+}
+
+#[tokio::test]
+async fn list_slugs_must_be_unique() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_list(&serde_json::json!({"name": "Weekly digest", "slug": "weekly"}))
+        .await;
+    let response = app
+        .post_list(&serde_json::json!({"name": "Another", "slug": "weekly"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("<p><i>A list with the slug weekly already exists.</i></p>"));
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com&list=nope".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_same_email_can_subscribe_to_several_lists() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_list(&serde_json::json!({"name": "Weekly digest", "slug": "weekly"}))
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=monkey&email=monkey%40gmail.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_subscriptions(format!("{}&list=weekly", body))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.deliver_outbox().await;
+
+    // Confirming the second signup only confirms the weekly list
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_confirmation_link(&email_req);
+    reqwest::get(link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let memberships = sqlx::query!(
+        r#"
+        SELECT l.slug, ls.status
+        FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(memberships.len(), 2);
+    assert_eq!(memberships[0].slug, "default");
+    assert_eq!(memberships[0].status, "pending_confirmation");
+    assert_eq!(memberships[1].slug, "weekly");
+    assert_eq!(memberships[1].status, "confirmed");
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_selected_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_list(&serde_json::json!({"name": "Weekly digest", "slug": "weekly"}))
+        .await;
+    app.subscribe_and_confirm("name=monkey&email=default-reader%40example.com".into())
+        .await;
+    app.subscribe_and_confirm("name=monkey&email=weekly-reader%40example.com&list=weekly".into())
+        .await;
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body</p>",
+        "text_content": "Newsletter body",
+        "list": "weekly",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletter(&newsletter_req_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["To"], "weekly-reader@example.com");
+}
+
+#[tokio::test]
+async fn single_opt_in_lists_confirm_subscribers_straight_away() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_list(&serde_json::json!({"name": "Beta testers", "slug": "beta", "opt_in": "single"}))
+        .await;
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains(
+        "<tr><td>Beta testers</td><td>beta</td><td>single</td><td>0</td><td>0</td></tr>"
+    ));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=monkey&email=monkey%40gmail.com&list=beta".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.deliver_outbox().await;
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.status, ls.status as list_status, ls.opt_in, ls.consented_at
+        FROM subscriptions s JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.list_status, "confirmed");
+    assert_eq!(saved.opt_in, "single");
+    assert!(saved.consented_at.is_some());
+
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with("Welcome to Beta testers!"));
+    assert!(!text_body.contains("/subscriptions/confirm"));
+}
+
+#[tokio::test]
+async fn single_opt_in_subscribers_get_the_welcome_series_instead_of_the_welcome_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_list(&serde_json::json!({"name": "Beta testers", "slug": "beta", "opt_in": "single"}))
+        .await;
+    app.post_welcome_step(&serde_json::json!({
+        "subject": "Welcome aboard",
+        "html_content": "<p>Welcome aboard</p>",
+        "text_content": "Welcome aboard",
+        "delay_hours": "0",
+    }))
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=monkey&email=monkey%40gmail.com&list=beta".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(sent_subjects(&app).await, vec!["Welcome aboard"]);
+}
+
+#[tokio::test]
+async fn single_opt_in_welcome_emails_are_held_to_the_send_limits() {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.confirmations_per_email = 1;
+        c.bot_protection.confirmations_per_ip = 2;
+    })
+    .await;
+    app.test_user.login(&app).await;
+    for (name, slug) in [("Beta", "beta"), ("Gamma", "gamma")] {
+        app.post_list(&serde_json::json!({"name": name, "slug": slug, "opt_in": "single"}))
+            .await;
+    }
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let mut codes = Vec::new();
+    for body in [
+        "name=monkey&email=monkey%40gmail.com&list=beta",
+        // Joins the list, without another welcome email.
+        "name=monkey&email=monkey%40gmail.com&list=gamma",
+        "name=gorilla&email=gorilla%40gmail.com&list=beta",
+        // Over the limit of this client.
+        "name=lemur&email=lemur%40gmail.com&list=beta",
+    ] {
+        codes.push(app.post_subscriptions(body.into()).await.status().as_u16());
+    }
+    app.deliver_outbox().await;
+
+    assert_eq!(codes, vec![200, 200, 200, 429]);
+    assert_eq!(sent_subjects(&app).await, vec!["Welcome!", "Welcome!"]);
+    let confirmed = sqlx::query!(
+        "SELECT count(*) as \"count!\" FROM list_subscriptions WHERE status = 'confirmed'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(confirmed, 3);
+}
+
+#[tokio::test]
+async fn double_opt_in_consent_is_recorded_on_confirmation() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+
+    let saved = sqlx::query!("SELECT opt_in, consented_at FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.opt_in, "double");
+    assert!(saved.consented_at.is_some());
+}
@@ -1,4 +1,42 @@
-use crate::helper::{assert_is_redirect_to, spawn_app};
+use monkey_letter::configuration::Settings;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// Only lockouts, no delays, unless a test asks for them.
+fn lockout_after(per_username: i64, per_ip: i64) -> impl FnOnce(&mut Settings) {
+    move |c| {
+        c.login_protection.delay_after_failures = 100;
+        c.login_protection.lockout_after_failures_per_username = per_username;
+        c.login_protection.lockout_after_failures_per_ip = per_ip;
+        c.login_protection.admin_email = Some("admin@example.com".into());
+    }
+}
+
+async fn login_with(app: &TestApp, username: &str, password: &str) -> String {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": username,
+            "password": password
+        }))
+        .await;
+    response.headers()["Location"].to_str().unwrap().to_string()
+}
+
+async fn fail_login(app: &TestApp) -> String {
+    let username = app.test_user.username.clone();
+    assert_eq!(login_with(app, &username, "wrong-password").await, "/login");
+    app.get_login_html().await
+}
+
+async fn log_in(app: &TestApp) -> String {
+    let (username, password) = (
+        app.test_user.username.clone(),
+        app.test_user.password.clone(),
+    );
+    login_with(app, &username, &password).await
+}
 
 #[tokio::test]
 async fn an_error_flash_msg_is_set_on_failure() {
@@ -35,3 +73,161 @@ async fn redirect_to_admin_dashbaord_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn repeated_failures_have_to_wait_before_trying_again() {
+    let app = spawn_app_with(|c| {
+        c.login_protection.delay_after_failures = 1;
+        c.login_protection.delay_seconds = 30;
+    })
+    .await;
+
+    let html_page = fail_login(&app).await;
+    assert!(html_page.contains("Authentication failed"));
+
+    assert_eq!(log_in(&app).await, "/login");
+    // The wait runs from the start of the failed attempt, so a slow password
+    // check can already have taken a second off it.
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page.contains("Please wait 30 seconds before trying again.")
+            || html_page.contains("Please wait 29 seconds before trying again.")
+    );
+}
+
+#[tokio::test]
+async fn accounts_are_locked_after_too_many_failures() {
+    let app = spawn_app_with(lockout_after(3, 100)).await;
+
+    for _ in 0..2 {
+        assert!(fail_login(&app).await.contains("Authentication failed"));
+    }
+    let html_page = fail_login(&app).await;
+    assert!(html_page.contains(
+        "This account is locked after too many failed logins. Please try again in 15 minutes."
+    ));
+
+    // The right password does not help until the lockout is over.
+    assert_eq!(log_in(&app).await, "/login");
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("This account is locked"));
+}
+
+#[tokio::test]
+async fn the_admin_is_emailed_when_their_account_gets_locked() {
+    let app = spawn_app_with(lockout_after(2, 100)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        fail_login(&app).await;
+    }
+    app.deliver_outbox().await;
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["To"], "admin@example.com");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn locking_an_unknown_username_emails_nobody() {
+    let app = spawn_app_with(lockout_after(2, 100)).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..2 {
+        login_with(&app, "nobody", "wrong-password").await;
+    }
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("This account is locked"));
+    app.deliver_outbox().await;
+}
+
+#[tokio::test]
+async fn clients_are_locked_out_after_too_many_failures_across_usernames() {
+    let app = spawn_app_with(lockout_after(100, 3)).await;
+
+    for username in ["alice", "bob", "carol"] {
+        login_with(&app, username, "wrong-password").await;
+    }
+
+    assert_eq!(log_in(&app).await, "/login");
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("Too many failed logins from your network."));
+}
+
+#[tokio::test]
+async fn logging_in_forgets_earlier_failures() {
+    let app = spawn_app_with(lockout_after(3, 100)).await;
+
+    for _ in 0..2 {
+        fail_login(&app).await;
+    }
+    assert_eq!(log_in(&app).await, "/admin/dashboard");
+    for _ in 0..2 {
+        assert!(fail_login(&app).await.contains("Authentication failed"));
+    }
+}
+
+#[tokio::test]
+async fn parallel_guesses_cannot_skip_the_delays() {
+    let app = spawn_app_with(|c| {
+        c.login_protection.delay_after_failures = 1;
+        c.login_protection.delay_seconds = 30;
+    })
+    .await;
+
+    let username = app.test_user.username.clone();
+    let guesses = (0..5).map(|i| {
+        let (app, username) = (&app, &username);
+        async move { login_with(app, username, &format!("guess-{}", i)).await }
+    });
+    futures_util::future::join_all(guesses).await;
+
+    let failures = sqlx::query!("SELECT count(*) as \"count!\" FROM login_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(failures, 1);
+}
+
+#[tokio::test]
+async fn spoofed_forwarding_headers_do_not_escape_the_client_lockout() {
+    let app = spawn_app_with(lockout_after(100, 3)).await;
+
+    for (i, username) in ["alice", "bob", "carol", "dave"].into_iter().enumerate() {
+        app.api_client
+            .post(format!("{}/login", app.address))
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .form(&serde_json::json!({
+                "username": username,
+                "password": "wrong-password"
+            }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("Too many failed logins from your network."));
+}
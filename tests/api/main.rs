@@ -1,8 +1,21 @@
 mod admin_dashboard;
+mod admin_subscribers;
+mod bot_protection;
 mod change_password;
+mod consent;
+mod custom_fields;
+mod deliverability;
 mod health_check;
 mod helper;
+mod imports;
+mod lists;
 mod login;
 mod newsletter;
+mod preferences;
+mod privacy;
+mod rate_limit;
+mod segments;
+mod subscriber_export;
 mod subscriptions;
 mod subscriptions_confirm;
+mod welcome_series;
@@ -171,6 +171,402 @@ async fn concurrent_form_submission_is_handled() {
     app.dispatch_all_pending_emails().await;
 }
 
+#[tokio::test]
+async fn paused_issues_are_not_delivered_until_resumed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<h1>Newsletter  body</h1>",
+        "text_content": "Newsletter body",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    let issue_id = get_issue_id(&app).await;
+
+    let response = app.post_issue_action(issue_id, "pause").await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{issue_id}"));
+    let html_page = app.get_issue_html(issue_id).await;
+    assert!(html_page.contains("The issue delivery has been paused."));
+
+    // Nothing goes out while the issue is paused
+    {
+        let _mock_guard = when_sending_an_email()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    let response = app.post_issue_action(issue_id, "resume").await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{issue_id}"));
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue = sqlx::query!(
+        "SELECT delivery_status, delivered_count FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.delivery_status, "completed");
+    assert_eq!(issue.delivered_count, 1);
+}
+
+#[tokio::test]
+async fn cancelling_an_issue_drops_pending_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<h1>Newsletter  body</h1>",
+        "text_content": "Newsletter body",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    let issue_id = get_issue_id(&app).await;
+
+    let response = app.post_issue_action(issue_id, "cancel").await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{issue_id}"));
+    let html_page = app.get_issue_html(issue_id).await;
+    assert!(html_page.contains("2 pending email(s) will not be sent"));
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let issue = sqlx::query!(
+        "SELECT delivery_status, delivered_count, undelivered_count FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.delivery_status, "cancelled");
+    assert_eq!(issue.delivered_count, 0);
+    assert_eq!(issue.undelivered_count, 2);
+
+    // A cancelled issue cannot be resumed
+    app.post_issue_action(issue_id, "resume").await;
+    let html_page = app.get_issue_html(issue_id).await;
+    assert!(html_page.contains("Only a paused issue can be resumed."));
+}
+
+#[tokio::test]
+async fn opens_are_tracked_when_enabled_for_an_issue() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<html><body><h1>Newsletter body</h1></body></html>",
+        "text_content": "Newsletter body",
+        "track_opens": "true",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    app.dispatch_all_pending_emails().await;
+    let issue_id = get_issue_id(&app).await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    let pixel_url = linkify::LinkFinder::new()
+        .links(html_body)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains("/t/o/"))
+        .expect("No tracking pixel in the email");
+
+    // Opening the email twice only counts as one unique open
+    for _ in 0..2 {
+        let response = reqwest::get(&pixel_url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+    }
+    let event = sqlx::query!(
+        "SELECT occurrences FROM email_events WHERE newsletter_issue_id = $1 AND event_type = 'open'",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.occurrences, 2);
+
+    let html_page = app.get_issue_html(issue_id).await;
+    assert!(html_page.contains("Unique opens: 1 (100.0%)"));
+}
+
+#[tokio::test]
+async fn opens_are_not_tracked_by_default() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<h1>Newsletter body</h1>",
+        "text_content": "Newsletter body",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.starts_with("<h1>Newsletter body</h1>"));
+    assert!(!html_body.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn clicks_are_tracked_and_redirected_to_the_original_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": r#"<a href="https://example.com/page">Read</a> <a data-no-track href="https://example.com/private">Private</a>"#,
+        "text_content": "Newsletter body",
+        "track_clicks": "true",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    app.dispatch_all_pending_emails().await;
+    let issue_id = get_issue_id(&app).await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(r#"href="https://example.com/private""#));
+    let tracked_url = linkify::LinkFinder::new()
+        .links(html_body)
+        .map(|l| l.as_str().replace("&amp;", "&"))
+        .find(|l| l.contains("/t/c/"))
+        .expect("No tracked link in the email");
+
+    let response = app.api_client.get(&tracked_url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers().get("Location").unwrap(),
+        "https://example.com/page"
+    );
+
+    let html_page = app.get_issue_html(issue_id).await;
+    assert!(html_page.contains("<td>https://example.com/page</td><td>1 (100.0%)</td><td>1</td>"));
+}
+
+#[tokio::test]
+async fn clicks_with_a_tampered_signature_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/t/c/some-token?url=https%3A%2F%2Fevil.com&sig=abcd",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn utm_parameters_are_added_to_outbound_links() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "July Update",
+        "html_content": r#"<a href="https://example.com/page?ref=1">Read</a> <a href="https://example.com/?utm_source=other">Other</a>"#,
+        "text_content": "Read it at https://example.com/page.",
+        "utm_tagging": "true",
+        "utm_source": "monkey",
+        "utm_medium": "",
+        "utm_campaign": "",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert!(body["HtmlBody"].as_str().unwrap().starts_with(
+        r#"<a href="https://example.com/page?ref=1&amp;utm_source=monkey&amp;utm_medium=email&amp;utm_campaign=july-update">Read</a> <a href="https://example.com/?utm_source=other">Other</a>"#
+    ));
+    assert!(body["TextBody"].as_str().unwrap().starts_with(
+        "Read it at https://example.com/page?utm_source=monkey&utm_medium=email&utm_campaign=july-update.\n"
+    ));
+}
+
+#[tokio::test]
+async fn the_winning_subject_line_is_sent_to_the_rest_of_the_audience() {
+    let app = spawn_app().await;
+    for _ in 0..4 {
+        create_confirmed_subscriber(&app).await;
+    }
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<html><body><h1>Newsletter body</h1></body></html>",
+        "text_content": "Newsletter body",
+        "subject_variants": "Subject A\nSubject B",
+        "ab_sample_percent": "50",
+        "ab_window_minutes": "60",
+        "ab_metric": "open",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletter(&newsletter_req_body).await;
+    app.dispatch_all_pending_emails().await;
+    let issue_id = get_issue_id(&app).await;
+
+    // Only the sample has been sent, one email per variant
+    let sent = app.email_server.received_requests().await.unwrap();
+    let sample: Vec<serde_json::Value> = sent[sent.len() - 2..]
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    let mut subjects: Vec<&str> = sample
+        .iter()
+        .map(|b| b["Subject"].as_str().unwrap())
+        .collect();
+    subjects.sort();
+    assert_eq!(subjects, ["Subject A", "Subject B"]);
+
+    // Only the recipient of "Subject B" opens the email
+    let b_email = sample.iter().find(|b| b["Subject"] == "Subject B").unwrap();
+    let pixel_url = linkify::LinkFinder::new()
+        .links(b_email["HtmlBody"].as_str().unwrap())
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains("/t/o/"))
+        .expect("No tracking pixel in the email");
+    reqwest::get(&pixel_url).await.unwrap();
+
+    // Close the test window
+    sqlx::query!(
+        "UPDATE newsletter_issues SET ab_decide_at = now() WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let sent = app.email_server.received_requests().await.unwrap();
+    for request in &sent[sent.len() - 2..] {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["Subject"], "Subject B");
+    }
+    let html_page = app.get_issue_html(issue_id).await;
+    assert!(html_page.contains("The winner has been sent to the rest of the audience."));
+    assert!(html_page
+        .contains("<tr><td>Subject B</td><td>1</td><td>1 (100.0%)</td><td>winner</td></tr>"));
+}
+
+#[tokio::test]
+async fn an_ab_test_needs_at_least_two_subject_lines() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body</p>",
+        "text_content": "Newsletter body",
+        "subject_variants": "Only one subject",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletter(&newsletter_req_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.post_newsletter_html().await;
+    assert!(
+        html_page.contains("<p><i>Enter at least two subject lines to run an A/B test.</i></p>")
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn get_issue_id(app: &TestApp) -> uuid::Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the newsletter issue")
+        .newsletter_issue_id
+}
+
 fn when_sending_an_email() -> MockBuilder {
     Mock::given(path("/email")).and(method("POST"))
 }
@@ -194,8 +590,15 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .await
         .error_for_status()
         .unwrap();
-    let email_req = &app.email_server.received_requests().await.unwrap()[0];
-    app.get_confirmation_link(email_req)
+    app.deliver_outbox().await;
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_link(&email_req)
 }
 
 async fn create_confirmed_subscriber(app: &TestApp) {
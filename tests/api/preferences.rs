@@ -0,0 +1,317 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str) {
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": title,
+            "html_content": format!("<html><body><p>{}</p></body></html>", title),
+            "text_content": title,
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+/// Publishes an issue to the single confirmed subscriber and returns the
+/// preferences token found in the email footer.
+async fn preferences_token_from_issue(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    publish_issue(app, "First issue").await;
+    app.dispatch_all_pending_emails().await;
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    let link = linkify::LinkFinder::new()
+        .links(text_body)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains("/preferences?token="))
+        .expect("No preferences link in the email");
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(&link));
+    reqwest::Url::parse(&link)
+        .unwrap()
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn setup(app: &TestApp) -> String {
+    app.test_user.login(app).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    preferences_token_from_issue(app).await
+}
+
+async fn changed_fields(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT field FROM preference_changes ORDER BY changed_at, field")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.field)
+        .collect()
+}
+
+#[tokio::test]
+async fn an_unknown_preferences_token_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.get_preferences("not-a-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_unsubscribe("not-a-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_preferences() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+    app.post_list(&serde_json::json!({"name": "Weekly news", "slug": "weekly"}))
+        .await;
+
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains(r#"value="monkey""#));
+    assert!(html_page.contains(r#"name="list-default" value="on" checked"#));
+
+    let response = app
+        .post_preferences(&[
+            ("token", &token),
+            ("name", "gorilla"),
+            ("frequency", "weekly_digest"),
+            ("pause_weeks", ""),
+            ("list-weekly", "on"),
+        ])
+        .await;
+    assert_is_redirect_to(&response, &format!("/preferences?token={}", token));
+
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("<p><i>Your preferences have been saved.</i></p>"));
+    assert!(html_page.contains(r#"value="gorilla""#));
+    assert!(html_page.contains(r#"name="list-weekly" value="on" checked"#));
+    assert!(!html_page.contains(r#"name="list-default" value="on" checked"#));
+
+    let saved = sqlx::query!("SELECT name, frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "gorilla");
+    assert_eq!(saved.frequency, "weekly_digest");
+    assert_eq!(
+        changed_fields(&app).await,
+        vec!["frequency", "list:default", "list:weekly", "name"]
+    );
+}
+
+#[tokio::test]
+async fn joining_a_list_on_the_preferences_page_records_consent() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+    app.post_list(&serde_json::json!({"name": "Weekly news", "slug": "weekly"}))
+        .await;
+
+    let response = app
+        .post_preferences(&[
+            ("token", &token),
+            ("name", "monkey"),
+            ("frequency", "every_issue"),
+            ("pause_weeks", ""),
+            ("list-default", "on"),
+            ("list-weekly", "on"),
+        ])
+        .await;
+    assert_is_redirect_to(&response, &format!("/preferences?token={}", token));
+
+    let membership = sqlx::query!(
+        r#"
+        SELECT ls.status, ls.opt_in, ls.consented_at
+        FROM list_subscriptions ls JOIN lists l ON l.list_id = ls.list_id
+        WHERE l.slug = 'weekly'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.status, "confirmed");
+    assert_eq!(membership.opt_in, "single");
+    assert!(membership.consented_at.is_some());
+    let consent = sqlx::query!(
+        r#"
+        SELECT c.note FROM consent_events c JOIN lists l ON l.list_id = c.list_id
+        WHERE l.slug = 'weekly' AND c.event_type = 'preferences'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        consent.note.as_deref(),
+        Some("Joined on the preferences page")
+    );
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_receive_issues() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+
+    app.post_preferences(&[
+        ("token", &token),
+        ("name", "monkey"),
+        ("frequency", "every_issue"),
+        ("pause_weeks", "2"),
+        ("list-default", "on"),
+    ])
+    .await;
+    assert_eq!(changed_fields(&app).await, vec!["paused_until"]);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app, "Second issue").await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn weekly_digest_readers_get_one_email_for_several_issues() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+    app.post_preferences(&[
+        ("token", &token),
+        ("name", "monkey"),
+        ("frequency", "weekly_digest"),
+        ("list-default", "on"),
+    ])
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app, "Second issue").await;
+    publish_issue(&app, "Third issue").await;
+    // The digest is not due yet
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("UPDATE subscriptions SET next_digest_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["Subject"], "Your weekly digest: 2 new issue(s)");
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<h1>Second issue</h1>"));
+    assert!(html_body.contains("<h1>Third issue</h1>"));
+    assert!(html_body.contains("/preferences?token="));
+}
+
+#[tokio::test]
+async fn unsubscribed_readers_stop_receiving_issues() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+
+    let response = app.post_unsubscribe(&token).await;
+    assert_is_redirect_to(&response, &format!("/preferences?token={}", token));
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("<p><i>You have been unsubscribed.</i></p>"));
+    assert_eq!(changed_fields(&app).await, vec!["status"]);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app, "Second issue").await;
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn assert_queued_issue_is_not_sent(app: &TestApp, change: impl std::future::Future) {
+    publish_issue(app, "Second issue").await;
+    change.await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let statuses = sqlx::query!(
+        "SELECT delivery_status, delivered_count FROM newsletter_issues WHERE title = 'Second issue'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses.delivery_status, "completed");
+    assert_eq!(statuses.delivered_count, 0);
+}
+
+#[tokio::test]
+async fn issues_queued_before_unsubscribing_are_not_sent() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+
+    assert_queued_issue_is_not_sent(&app, app.post_unsubscribe(&token)).await;
+}
+
+#[tokio::test]
+async fn issues_queued_before_pausing_are_not_sent() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+
+    let pause = [
+        ("token", token.as_str()),
+        ("name", "monkey"),
+        ("frequency", "every_issue"),
+        ("pause_weeks", "2"),
+        ("list-default", "on"),
+    ];
+    assert_queued_issue_is_not_sent(&app, app.post_preferences(&pause)).await;
+}
+
+#[tokio::test]
+async fn issues_queued_before_leaving_the_list_are_not_sent() {
+    let app = spawn_app().await;
+    let token = setup(&app).await;
+
+    let leave = [
+        ("token", token.as_str()),
+        ("name", "monkey"),
+        ("frequency", "every_issue"),
+    ];
+    assert_queued_issue_is_not_sent(&app, app.post_preferences(&leave)).await;
+}
@@ -0,0 +1,190 @@
+use monkey_letter::privacy::suppression_hash;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+/// Asks for a privacy link and returns the token it carries.
+async fn request_privacy_token(app: &TestApp, email: &str) -> String {
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_privacy_request(email).await;
+    assert_is_redirect_to(&response, "/privacy");
+    app.deliver_outbox().await;
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_link(email_req).html;
+    assert_eq!(link.path(), "/privacy/manage");
+    link.query_pairs()
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requesting_a_link_for_an_unknown_address_sends_nothing() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_privacy_request("nobody@gmail.com").await;
+    assert_is_redirect_to(&response, "/privacy");
+    app.deliver_outbox().await;
+
+    let html = app.get_privacy_html().await;
+    assert!(html.contains("If that address is subscribed, we have emailed it a link."));
+}
+
+#[tokio::test]
+async fn subscribers_can_download_everything_stored_about_them() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com&tags=vip".into())
+        .await;
+    let token = request_privacy_token(&app, "monkey@gmail.com").await;
+
+    let response = app.get_privacy_export(&token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .contains("attachment"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "monkey@gmail.com");
+    assert_eq!(data["subscriber"]["status"], "confirmed");
+    assert_eq!(data["lists"][0]["status"], "confirmed");
+    assert_eq!(data["tags"], serde_json::json!(["vip"]));
+    assert_eq!(data["confirmation_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["consent_events"].as_array().unwrap().len(), 2);
+    assert!(data["emails"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|e| e["kind"] == "confirmation"));
+}
+
+#[tokio::test]
+async fn erasing_removes_the_subscriber_and_keeps_a_hashed_suppression() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com&tags=vip".into())
+        .await;
+    let token = request_privacy_token(&app, "monkey@gmail.com").await;
+
+    let response = app.post_privacy_erase(&token).await;
+    assert_is_redirect_to(&response, "/privacy");
+    let html = app.get_privacy_html().await;
+    assert!(html.contains("Your data has been deleted."));
+
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions) as "subscribers!",
+            (SELECT COUNT(*) FROM list_subscriptions) as "memberships!",
+            (SELECT COUNT(*) FROM subscription_tokens) as "tokens!",
+            (SELECT COUNT(*) FROM subscriber_tags) as "tags!",
+            (SELECT COUNT(*) FROM consent_events) as "consent!",
+            (SELECT COUNT(*) FROM outbox WHERE recipient = 'monkey@gmail.com') as "emails!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscribers, 0);
+    assert_eq!(remaining.memberships, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.tags, 0);
+    assert_eq!(remaining.consent, 0);
+    assert_eq!(remaining.emails, 0);
+    let suppression = sqlx::query!("SELECT email_hash, reason FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        suppression.email_hash,
+        suppression_hash(&app.hmac_secret, "monkey@gmail.com")
+    );
+    assert_eq!(suppression.reason, "erasure_request");
+
+    // The link cannot be used again.
+    assert_eq!(app.get_privacy_export(&token).await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn privacy_links_expire() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    let token = request_privacy_token(&app, "monkey@gmail.com").await;
+    sqlx::query!("UPDATE privacy_tokens SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(app.get_privacy_export(&token).await.status().as_u16(), 401);
+    assert_eq!(app.post_privacy_erase(&token).await.status().as_u16(), 401);
+    let subscribers = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 1);
+}
+
+#[tokio::test]
+async fn privacy_links_to_an_address_are_rate_limited() {
+    let app = spawn_app_with(|c| c.bot_protection.confirmations_per_email = 2).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // The confirmation email already counts towards the limit.
+    for email in ["monkey@gmail.com", "MONKEY@gmail.com", "monkey@gmail.com"] {
+        let response = app.post_privacy_request(email).await;
+        assert_is_redirect_to(&response, "/privacy");
+    }
+    app.deliver_outbox().await;
+}
+
+#[tokio::test]
+async fn privacy_links_requested_by_a_client_are_rate_limited() {
+    let app = spawn_app_with(|c| c.bot_protection.confirmations_per_ip = 1).await;
+    for n in 1..=2 {
+        sqlx::query!(
+            "INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+            VALUES (gen_random_uuid(), $1, $1, 'monkey', now(), 'confirmed')",
+            format!("monkey{}@gmail.com", n)
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    app.post_privacy_request("monkey1@gmail.com").await;
+    let response = app.post_privacy_request("monkey2@gmail.com").await;
+    assert_is_redirect_to(&response, "/privacy");
+
+    let html = app.get_privacy_html().await;
+    assert!(html.contains("Too many requests from this address. Please try again later."));
+    assert_eq!(
+        sqlx::query_scalar!("SELECT count(*) FROM outbox WHERE kind = 'privacy'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap(),
+        Some(1)
+    );
+}
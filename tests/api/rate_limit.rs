@@ -0,0 +1,115 @@
+use monkey_letter::configuration::BucketSettings;
+use uuid::Uuid;
+
+use crate::helper::spawn_app_with;
+
+fn tight_bucket() -> BucketSettings {
+    BucketSettings {
+        capacity: 2,
+        refill_per_minute: 1,
+    }
+}
+
+#[tokio::test]
+async fn clients_over_the_limit_are_told_when_to_retry() {
+    let app = spawn_app_with(|c| c.rate_limits.subscribe = tight_bucket()).await;
+
+    for _ in 0..2 {
+        let response = app.post_subscriptions(String::new()).await;
+        assert_eq!(400, response.status().as_u16());
+    }
+    let response = app.post_subscriptions(String::new()).await;
+
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+}
+
+#[tokio::test]
+async fn each_route_group_has_its_own_bucket() {
+    let app = spawn_app_with(|c| c.rate_limits.subscribe = tight_bucket()).await;
+    for _ in 0..3 {
+        app.post_subscriptions(String::new()).await;
+    }
+
+    let response = app
+        .api_client
+        .get(format!("{}/subscriptions/confirm", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn limits_are_shared_between_app_instances() {
+    let key_prefix = format!("rate_limit:{}", Uuid::new_v4());
+    let configure = |c: &mut monkey_letter::configuration::Settings| {
+        c.rate_limits.subscribe = tight_bucket();
+        c.rate_limits.key_prefix = key_prefix.clone();
+    };
+    let first = spawn_app_with(configure).await;
+    let second = spawn_app_with(configure).await;
+
+    first.post_subscriptions(String::new()).await;
+    second.post_subscriptions(String::new()).await;
+    let response = first.post_subscriptions(String::new()).await;
+
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn nothing_is_limited_when_rate_limiting_is_disabled() {
+    let app = spawn_app_with(|c| {
+        c.rate_limits.enabled = false;
+        c.rate_limits.subscribe = tight_bucket();
+    })
+    .await;
+
+    for _ in 0..3 {
+        let response = app.post_subscriptions(String::new()).await;
+        assert_eq!(400, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn spoofed_forwarding_headers_do_not_reset_the_bucket() {
+    let app = spawn_app_with(|c| c.rate_limits.subscribe = tight_bucket()).await;
+
+    for i in 0..3 {
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", app.address))
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .send()
+            .await
+            .unwrap();
+        let expected = if i < 2 { 400 } else { 429 };
+        assert_eq!(expected, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_get_their_own_buckets() {
+    let app = spawn_app_with(|c| {
+        c.rate_limits.subscribe = tight_bucket();
+        c.application.trusted_proxies = vec!["127.0.0.0/8".to_string().try_into().unwrap()];
+    })
+    .await;
+
+    for i in 0..3 {
+        let response = app
+            .api_client
+            .post(format!("{}/subscriptions", app.address))
+            .header("X-Forwarded-For", format!("198.51.100.{}", i))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(400, response.status().as_u16());
+    }
+}
@@ -0,0 +1,160 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn tags_from_the_signup_form_are_stored() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=monkey&email=monkey%40gmail.com&tags=Rust%2C+beta".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let tags = sqlx::query!("SELECT tag FROM subscriber_tags ORDER BY tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let tags: Vec<_> = tags.into_iter().map(|r| r.tag).collect();
+    assert_eq!(tags, ["beta", "rust"]);
+}
+
+#[tokio::test]
+async fn invalid_signup_tags_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40gmail.com&tags=not+a+tag".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn tags_can_be_updated_through_the_api() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com&tags=rust".into())
+        .await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_tags(&serde_json::json!({
+            "email": "monkey@gmail.com",
+            "add": ["VIP", "beta"],
+            "remove": ["rust"]
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["tags"], serde_json::json!(["beta", "vip"]));
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_through_the_api_returns_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscriber_tags(&serde_json::json!({
+            "email": "nobody@gmail.com",
+            "add": ["vip"]
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn segments_with_a_malformed_tag_expression_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_segment(&serde_json::json!({
+            "name": "Broken",
+            "tag_expression": "rust and (beta"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+
+    let html_page = app.get_segments_html().await;
+    assert!(
+        html_page.contains("<p><i>A parenthesis in the tag expression is never closed.</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn issues_targeting_a_segment_only_reach_its_members() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=rustacean%40example.com&tags=rust".into())
+        .await;
+    app.subscribe_and_confirm("name=monkey&email=gopher%40example.com&tags=go".into())
+        .await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_segment(&serde_json::json!({
+            "name": "Rust fans",
+            "tag_expression": "rust and not go",
+            "subscribed_before": "2999-01-01"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/segments");
+    let segment_id = sqlx::query!("SELECT segment_id FROM segments")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .segment_id;
+
+    // The recipient count is shown before publishing
+    let html_page = app
+        .api_client
+        .get(format!(
+            "{}/admin/newsletters?list=default&segment={}",
+            app.address, segment_id
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p>This issue would currently go to 1 recipient(s).</p>"));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_req_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body</p>",
+        "text_content": "Newsletter body",
+        "list": "default",
+        "segment": segment_id.to_string(),
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletter(&newsletter_req_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert_eq!(body["To"], "rustacean@example.com");
+}
@@ -0,0 +1,155 @@
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn export(app: &TestApp, query: &str) -> String {
+    let response = app
+        .get_admin_path(&format!("/admin/subscribers/export?{}", query))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.text().await.unwrap()
+}
+
+/// monkey is confirmed on the default list and tagged vip, gorilla is
+/// pending on the weekly list and signed up in 2023.
+async fn seed_subscribers(app: &TestApp) {
+    app.test_user.login(app).await;
+    app.post_list(&serde_json::json!({"name": "Weekly digest", "slug": "weekly"}))
+        .await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com&tags=vip".into())
+        .await;
+    app.post_subscriptions("name=gorilla&email=gorilla%40gmail.com&list=weekly".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2023-05-01T12:00:00Z' WHERE email = 'gorilla@gmail.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+
+    let response = app.get_admin_path("/admin/subscribers/export").await;
+
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .contains("subscribers.csv"));
+    let body = response.text().await.unwrap();
+    let mut lines: Vec<_> = body.lines().collect();
+    assert_eq!(
+        lines.remove(0),
+        "email,name,status,subscribed_at,lists,tags"
+    );
+    lines.sort();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("gorilla@gmail.com,gorilla,pending_confirmation,2023-05-01"));
+    assert!(lines[1].starts_with("monkey@gmail.com,monkey,confirmed,"));
+    assert!(lines[1].ends_with(",default,vip"));
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_ndjson() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+
+    let body = export(&app, "format=ndjson&tag=vip").await;
+
+    let rows: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["email"], "monkey@gmail.com");
+    assert_eq!(rows[0]["lists"], serde_json::json!(["default"]));
+    assert_eq!(rows[0]["tags"], serde_json::json!(["vip"]));
+}
+
+#[tokio::test]
+async fn exports_can_be_filtered() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    let emails = |body: String| -> Vec<String> {
+        body.lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_owned())
+            .collect()
+    };
+
+    assert_eq!(
+        emails(export(&app, "status=confirmed").await),
+        vec!["monkey@gmail.com"]
+    );
+    assert_eq!(
+        emails(export(&app, "list=weekly").await),
+        vec!["gorilla@gmail.com"]
+    );
+    assert!(emails(export(&app, "list=weekly&status=confirmed").await).is_empty());
+    assert_eq!(
+        emails(export(&app, "tag=vip").await),
+        vec!["monkey@gmail.com"]
+    );
+    assert_eq!(
+        emails(export(&app, "from=2023-01-01&to=2023-05-01").await),
+        vec!["gorilla@gmail.com"]
+    );
+    assert_eq!(
+        emails(export(&app, "from=2024-01-01").await),
+        vec!["monkey@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn large_exports_include_every_subscriber() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'subscriber' || n || '@gmail.com',
+            'subscriber' || n || '@gmail.com', 'subscriber', now(), 'confirmed'
+        FROM generate_series(1, 1234) n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let body = export(&app, "").await;
+
+    let mut emails: Vec<_> = body.lines().skip(1).collect();
+    assert_eq!(emails.len(), 1234);
+    emails.sort();
+    emails.dedup();
+    assert_eq!(emails.len(), 1234);
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for query in ["status=gone", "from=yesterday", "format=xml"] {
+        let response = app
+            .get_admin_path(&format!("/admin/subscribers/export?{}", query))
+            .await;
+        assert_eq!(response.status().as_u16(), 400, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_path("/admin/subscribers/export").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -1,7 +1,11 @@
+use monkey_letter::{
+    configuration::get_configuration, startup::Application, subscriber_keys::rekey_subscribers,
+};
+use uuid::Uuid;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helper::spawn_app;
+use crate::helper::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_200_for_valid_form_data() {
@@ -96,6 +100,7 @@ async fn subscribe_sends_a_confirmation_email_form_valid_submit() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.deliver_outbox().await;
 }
 
 #[tokio::test]
@@ -111,6 +116,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.deliver_outbox().await;
     let email_req = &app.email_server.received_requests().await.unwrap()[0];
 
     let link = app.get_confirmation_link(email_req);
@@ -131,3 +137,332 @@ async fn subscribe_fails_if_there_is_fatal_db_err() {
     let res = app.post_subscriptions(body.into()).await;
     assert_eq!(res.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..2 {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    app.deliver_outbox().await;
+
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_link(&email_reqs[0]);
+    let second_link = app.get_confirmation_link(&email_reqs[1]);
+    assert_eq!(first_link.html, second_link.html);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_returns_200_without_an_email() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40gmail.com";
+    app.subscribe_and_confirm(body.into()).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.text().await.unwrap(), "");
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_readers_who_sign_up_again_must_confirm_again() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40gmail.com";
+    app.subscribe_and_confirm(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE list_subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.status, ls.status as list_status
+        FROM subscriptions s JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.list_status, "pending_confirmation");
+
+    app.deliver_outbox().await;
+    let email_req = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_confirmation_link(&email_req);
+    reqwest::get(link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_succeeds_even_if_the_email_provider_is_down() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.deliver_outbox().await;
+    let pending = sqlx::query!("SELECT attempts, last_error, sent_at FROM outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.attempts, 1);
+    assert!(pending.last_error.is_some());
+    assert!(pending.sent_at.is_none());
+
+    // The retry goes through once the provider is back
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE outbox SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.deliver_outbox().await;
+    let sent = sqlx::query!("SELECT sent_at FROM outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(sent.sent_at.is_some());
+}
+
+#[tokio::test]
+async fn addresses_differing_only_by_case_are_the_same_subscriber() {
+    let app = spawn_app().await;
+
+    for body in [
+        "name=monkey&email=Monkey%40Gmail.com",
+        "name=monkey&email=%20monkey%40GMAIL.COM%20",
+    ] {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let saved = sqlx::query!("SELECT email, email_key FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Monkey@gmail.com");
+    assert_eq!(saved[0].email_key, "monkey@gmail.com");
+}
+
+#[tokio::test]
+async fn internationalised_domains_are_stored_punycode_encoded() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=monkey&email=monkey%40b%C3%BCcher.example".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "monkey@xn--bcher-kva.example");
+}
+
+#[tokio::test]
+async fn concurrent_signups_for_the_same_address_both_succeed() {
+    let app = spawn_app().await;
+    let body = "name=monkey&email=monkey%40gmail.com";
+
+    let (first, second) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn rekeying_merges_subscribers_that_newly_share_a_key() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey.face%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=monkey&email=monkeyface%2Bnews%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // Keyed by the database, before the application computed the keys.
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_key, name, subscribed_at, status)
+        VALUES ($1, 'gorilla@Bücher.example', 'gorilla@bücher.example', 'gorilla', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let summary = rekey_subscribers(&app.db_pool, &["gmail.com".to_string()])
+        .await
+        .unwrap();
+
+    assert_eq!(summary.merged, 1);
+    assert_eq!(summary.rekeyed, 2);
+    let saved = sqlx::query!("SELECT email, email_key, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let saved: Vec<_> = saved
+        .into_iter()
+        .map(|r| (r.email, r.email_key, r.status))
+        .collect();
+    assert_eq!(
+        saved,
+        vec![
+            (
+                "gorilla@xn--bcher-kva.example".to_string(),
+                "gorilla@xn--bcher-kva.example".to_string(),
+                "confirmed".to_string()
+            ),
+            (
+                "monkey.face@gmail.com".to_string(),
+                "monkeyface@gmail.com".to_string(),
+                "confirmed".to_string()
+            ),
+        ]
+    );
+    let memberships = sqlx::query!("SELECT count(*) as \"count!\" FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(memberships, 1);
+}
+
+#[tokio::test]
+async fn the_application_rekeys_subscribers_when_the_alias_domains_change() {
+    let app = spawn_app().await;
+    for body in [
+        "name=monkey&email=monkey.face%40gmail.com",
+        "name=monkey&email=monkeyface%2Bnews%40gmail.com",
+    ] {
+        app.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    let mut config = get_configuration().unwrap();
+    config.database.database_name = sqlx::query_scalar!("SELECT current_database()")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    config.application.port = 0;
+    config.subscriptions.alias_folding_domains = vec!["gmail.com".into()];
+    Application::build(config).await.unwrap();
+
+    let keys = sqlx::query_scalar!("SELECT email_key FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys, vec!["monkeyface@gmail.com".to_string()]);
+}
+
+#[tokio::test]
+async fn aliases_are_folded_for_configured_domains() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.alias_folding_domains = vec!["gmail.com".into()];
+    })
+    .await;
+
+    for body in [
+        "name=monkey&email=monkey.face%40gmail.com",
+        "name=monkey&email=monkeyface%2Bnews%40gmail.com",
+        "name=monkey&email=monkey.face%2Bnews%40example.com",
+        "name=monkey&email=monkeyface%40example.com",
+    ] {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let emails: Vec<_> = saved.into_iter().map(|r| r.email).collect();
+    assert_eq!(
+        emails,
+        vec![
+            "monkey.face+news@example.com",
+            "monkey.face@gmail.com",
+            "monkeyface@example.com"
+        ]
+    );
+}
@@ -1,9 +1,10 @@
+use monkey_letter::{preferences::get_preferences_token, signup_cleanup::cleanup_stale_signups};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helper::spawn_app;
+use crate::helper::{spawn_app, TestApp};
 
 #[tokio::test]
 async fn confirmation_without_token_rejected_with_400() {
@@ -28,6 +29,7 @@ async fn link_returned_by_sub_returns_200_when_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.deliver_outbox().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let link = app.get_confirmation_link(email_request);
 
@@ -48,6 +50,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.deliver_outbox().await;
     let email_req = &app.email_server.received_requests().await.unwrap()[0];
     let link = app.get_confirmation_link(email_req);
 
@@ -65,3 +68,363 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "monk struct");
     assert_eq!(saved.status, "confirmed");
 }
+
+fn token_from_link(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn a_confirmation_link_only_works_once() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=monkey&email=monkey%40test.com".into())
+        .await;
+    app.deliver_outbox().await;
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_link(email_req);
+
+    let res = reqwest::get(link.html.clone()).await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let res = reqwest::get(link.html).await.unwrap();
+    assert_eq!(res.status().as_u16(), 410);
+    let html_page = res.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has already been used."));
+}
+
+#[tokio::test]
+async fn an_expired_link_offers_to_resend_the_confirmation_email() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=monkey&email=monkey%40test.com".into())
+        .await;
+    app.deliver_outbox().await;
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_link(email_req);
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(hours => $1)",
+        app.subscription_settings.confirmation_token_ttl_hours as i32 + 1
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let res = reqwest::get(link.html.clone()).await.unwrap();
+    assert_eq!(res.status().as_u16(), 410);
+    let html_page = res.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired."));
+    assert!(html_page.contains(r#"action="/subscriptions/confirm/resend""#));
+
+    let res = app
+        .post_resend_confirmation(&token_from_link(&link.html))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+    app.deliver_outbox().await;
+    let email_req = &app.email_server.received_requests().await.unwrap()[1];
+    let new_link = app.get_confirmation_link(email_req);
+    assert_ne!(new_link.html, link.html);
+    reqwest::get(new_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn resending_for_a_confirmed_subscriber_sends_nothing() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40test.com".into())
+        .await;
+    let token = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let res = app.post_resend_confirmation(&token).await;
+    assert_eq!(res.status().as_u16(), 200);
+    app.deliver_outbox().await;
+
+    let res = app.post_resend_confirmation("not-a-token").await;
+    assert_eq!(res.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn cleanup_removes_stale_tokens_and_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=confirmed&email=confirmed%40test.com".into())
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=pending&email=pending%40test.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_subscriptions("name=recent&email=recent%40test.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let days = app
+        .subscription_settings
+        .token_retention_days
+        .max(app.subscription_settings.pending_retention_days) as i32
+        + 1;
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens t SET created_at = now() - make_interval(days => $1)
+        FROM subscriptions s
+        WHERE s.id = t.subscriber_id AND s.email <> 'recent@test.com'
+        "#,
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    backdate_signups(&app, days).await;
+
+    let report = cleanup_stale_signups(&app.db_pool, &app.subscription_settings)
+        .await
+        .unwrap();
+    assert_eq!(report.deleted_tokens, 2);
+    assert_eq!(report.deleted_subscribers, 1);
+    let remaining: Vec<String> = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect();
+    assert_eq!(remaining, vec!["confirmed@test.com", "recent@test.com"]);
+}
+
+async fn backdate_signups(app: &TestApp, days: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - make_interval(days => $1)",
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET pending_since = now() - make_interval(days => $1)
+        WHERE pending_since IS NOT NULL
+        "#,
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn an_unconfirmed_resubscription_expires_without_deleting_the_subscriber() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_welcome_step(&serde_json::json!({
+        "subject": "Welcome",
+        "html_content": "<p>Welcome</p>",
+        "text_content": "Welcome",
+        "delay_hours": "0",
+    }))
+    .await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let token = {
+        let mut connection = app.db_pool.acquire().await.unwrap();
+        get_preferences_token(&mut connection, "monkey@gmail.com")
+            .await
+            .unwrap()
+            .unwrap()
+    };
+    app.post_unsubscribe(&token).await;
+    let days = app.subscription_settings.pending_retention_days as i32 + 1;
+    // A long-time subscriber, who signs up again today.
+    backdate_signups(&app, days).await;
+    app.post_subscriptions("name=monkey&email=monkey%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let report = cleanup_stale_signups(&app.db_pool, &app.subscription_settings)
+        .await
+        .unwrap();
+    assert_eq!(report.deleted_subscribers, 0);
+    assert_eq!(report.expired_resubscriptions, 0);
+
+    // The new signup is never confirmed.
+    backdate_signups(&app, days).await;
+    backdate_tokens(&app, i64::from(days) * 24).await;
+    let report = cleanup_stale_signups(&app.db_pool, &app.subscription_settings)
+        .await
+        .unwrap();
+    assert_eq!(report.deleted_subscribers, 0);
+    assert_eq!(report.expired_resubscriptions, 1);
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.status, ls.status as list_status
+        FROM subscriptions s JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    assert_eq!(saved.list_status, "unsubscribed");
+    let delivered = sqlx::query!("SELECT count(*) as \"count!\" FROM welcome_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(delivered, 1);
+}
+
+async fn backdate_tokens(app: &TestApp, hours: i64) {
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(hours => $1)",
+        hours as i32
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn pending_subscribers_get_a_limited_number_of_reminders() {
+    let app = spawn_app().await;
+    let reminder_after = app.subscription_settings.reminder_after_hours + 1;
+    let max_reminders = app.subscription_settings.max_reminders as usize;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1 + max_reminders as u64)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=monkey&email=monkey%40test.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // Not due yet
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+
+    for _ in 0..max_reminders + 1 {
+        backdate_tokens(&app, reminder_after).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_reqs.len(), 1 + max_reminders);
+    let reminder = email_reqs.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&reminder.body).unwrap();
+    assert_eq!(body["Subject"], "Please confirm your subscription");
+    let link = app.get_confirmation_link(reminder);
+    reqwest::get(link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!(
+        "SELECT s.status, ls.reminders_sent FROM subscriptions s JOIN list_subscriptions ls ON ls.subscriber_id = s.id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.reminders_sent as usize, max_reminders);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_reminded() {
+    let app = spawn_app().await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40test.com".into())
+        .await;
+    backdate_tokens(&app, app.subscription_settings.reminder_after_hours + 1).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_address_that_cannot_be_reminded_does_not_hold_up_the_others() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for email in ["broken%40test.com", "monkey%40test.com"] {
+        app.post_subscriptions(format!("name=monkey&email={}", email))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    sqlx::query!("UPDATE subscriptions SET email = 'not-an-email' WHERE email = 'broken@test.com'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    backdate_tokens(&app, app.subscription_settings.reminder_after_hours + 1).await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let reminders = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .filter(|body| body["Subject"] == "Please confirm your subscription")
+        .map(|body| body["To"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(reminders, vec!["monkey@test.com"]);
+    let reminders_sent: Vec<i16> = sqlx::query!("SELECT reminders_sent FROM list_subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.reminders_sent)
+        .collect();
+    assert_eq!(reminders_sent, vec![1, 1]);
+}
@@ -0,0 +1,166 @@
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn add_step(app: &TestApp, subject: &str, delay_hours: i32) -> reqwest::Response {
+    app.post_welcome_step(&serde_json::json!({
+        "subject": subject,
+        "html_content": format!("<p>{} for {{{{ name }}}}</p>", subject),
+        "text_content": format!("{} for {{{{ name }}}}", subject),
+        "delay_hours": delay_hours.to_string(),
+    }))
+    .await
+}
+
+async fn make_steps_due(app: &TestApp) {
+    sqlx::query!("UPDATE welcome_queue SET send_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn sent_subjects(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_receive_the_welcome_series_in_order() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = add_step(&app, "Welcome", 0).await;
+    assert_is_redirect_to(&response, "/admin/welcome");
+    add_step(&app, "Getting started", 48).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    app.email_server.reset().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(sent_subjects(&app).await, vec!["Welcome"]);
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Welcome for monkey</p>"));
+
+    let html_page = app.get_welcome_html().await;
+    assert!(
+        html_page.contains("<tr><td>1</td><td>Welcome</td><td>0h</td><td>1</td><td>0</td></tr>")
+    );
+    assert!(html_page
+        .contains("<tr><td>2</td><td>Getting started</td><td>48h</td><td>0</td><td>1</td></tr>"));
+
+    make_steps_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        sent_subjects(&app).await,
+        vec!["Welcome", "Getting started"]
+    );
+    let html_page = app.get_welcome_html().await;
+    assert!(html_page
+        .contains("<tr><td>2</td><td>Getting started</td><td>48h</td><td>1</td><td>0</td></tr>"));
+}
+
+#[tokio::test]
+async fn steps_cannot_go_out_before_the_previous_one() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    add_step(&app, "Welcome", 24).await;
+
+    let response = add_step(&app, "Too early", 12).await;
+    assert_is_redirect_to(&response, "/admin/welcome");
+
+    let html_page = app.get_welcome_html().await;
+    assert!(html_page
+        .contains("<p><i>Step 2 has to go out at least 24 hours after confirmation.</i></p>"));
+    assert!(!html_page.contains("Too early"));
+}
+
+#[tokio::test]
+async fn unsubscribing_stops_the_welcome_series() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    add_step(&app, "Welcome", 0).await;
+    add_step(&app, "Getting started", 48).await;
+    app.subscribe_and_confirm("name=monkey&email=monkey%40gmail.com".into())
+        .await;
+    app.email_server.reset().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_req = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_req.body).unwrap();
+    let link = linkify::LinkFinder::new()
+        .links(body["TextBody"].as_str().unwrap())
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains("/preferences?token="))
+        .unwrap();
+    let token = link.split("token=").nth(1).unwrap();
+    app.post_unsubscribe(token).await;
+
+    make_steps_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(sent_subjects(&app).await, vec!["Welcome"]);
+}
+
+#[tokio::test]
+async fn a_failing_welcome_email_does_not_hold_up_the_others() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    add_step(&app, "Welcome", 0).await;
+    for email in ["bouncing%40gmail.com", "monkey%40gmail.com"] {
+        app.subscribe_and_confirm(format!("name=monkey&email={}", email))
+            .await;
+    }
+    app.email_server.reset().await;
+
+    Mock::given(path("/email"))
+        .and(body_string_contains("bouncing@gmail.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivered = sqlx::query!(
+        "SELECT recipient FROM outbox WHERE kind = 'welcome_series' AND sent_at IS NOT NULL"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].recipient, "monkey@gmail.com");
+    let queued = sqlx::query!("SELECT count(*) as \"count!\" FROM welcome_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 0);
+}